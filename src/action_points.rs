@@ -0,0 +1,159 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::common::Special;
+
+/// Everything a character can spend action points on
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Action {
+    Move,
+    Attack,
+    OpenInventory,
+    UseItem,
+    CastSpell,
+    Fly,
+}
+
+/// Reason why the server refused an action
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ActionError {
+    NotEnoughActionPoints {
+        action: Action,
+        cost: u32,
+        available: u32,
+    },
+    NotYourTurn,
+    NotYourCharacter,
+    UnknownCharacter,
+    /// The target tile can't be reached with the available AP and Endurance
+    Unreachable,
+    /// Characters can only be placed inside the own spawn zone
+    NotInSpawnZone,
+    TileOccupied,
+    /// All characters of the squad are already placed
+    NothingToPlace,
+    /// The target is farther away than the attack reaches
+    OutOfRange,
+    /// Something blocks the sight to the target
+    NoLineOfSight,
+    /// Only Pegasi can fly, and only up to the maximum altitude
+    CannotFly,
+    /// There is no ground to land on below the flying character
+    CannotLand,
+    /// Characters can't attack themselves or their own squad
+    InvalidTarget,
+    /// No spell with this name is loaded
+    UnknownSpell,
+    /// Only Unicorns can cast spells, and only those meant for them
+    CannotCast,
+    /// The character doesn't carry that item, or has nothing in that slot
+    ItemNotCarried,
+    /// Only weapons and armour can be equipped
+    CannotEquip,
+    /// Only consumables can be used
+    CannotUse,
+    /// The item would exceed what the character can carry
+    TooHeavy,
+    UnknownSkill,
+    /// The race can't learn from the skill tree, or prerequisites are missing
+    SkillLocked,
+    AlreadyLearned,
+    NotEnoughXp {
+        cost: u32,
+        available: u32,
+    },
+}
+
+#[derive(Clone, Component, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ActionPoints {
+    pub current: u32,
+    /// Action points can be stored up to twice the endurance
+    pub max: u32,
+}
+
+impl ActionPoints {
+    /// A character starts with floor(5 + Agility / 2) action points
+    pub fn from_special(special: &Special) -> Self {
+        let max = 2 * special.endurance as u32;
+        Self {
+            current: (5 + special.agility as u32 / 2).min(max),
+            max,
+        }
+    }
+
+    /// Adds the action points a character receives at the start of their players turn
+    pub fn refill(&mut self, special: &Special) {
+        self.current = (self.current + special.agility as u32).min(self.max);
+    }
+
+    pub fn can_spend(&self, cost: u32) -> bool {
+        cost <= self.current
+    }
+
+    /// Burns `cost` action points for `action`, leaving them untouched if there aren't enough
+    pub fn spend(&mut self, action: Action, cost: u32) -> Result<(), ActionError> {
+        if !self.can_spend(cost) {
+            return Err(ActionError::NotEnoughActionPoints {
+                action,
+                cost,
+                available: self.current,
+            });
+        }
+        self.current -= cost;
+        Ok(())
+    }
+}
+
+/// Checks that `client_id` may act with a character owned by `owner` while `current_player` is acting
+pub fn authorize(
+    client_id: u64,
+    current_player: Option<u64>,
+    owner: u64,
+) -> Result<(), ActionError> {
+    if current_player != Some(client_id) {
+        return Err(ActionError::NotYourTurn);
+    }
+    if owner != client_id {
+        return Err(ActionError::NotYourCharacter);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_gdd_formulas() {
+        let mut special = Special::new();
+        special.agility = 7;
+        special.endurance = 4;
+
+        let mut action_points = ActionPoints::from_special(&special);
+        assert_eq!(action_points.current, 8);
+        assert_eq!(action_points.max, 8);
+
+        action_points.current = 0;
+        action_points.refill(&special);
+        assert_eq!(action_points.current, 7);
+        action_points.refill(&special);
+        assert_eq!(action_points.current, 8);
+    }
+
+    #[test]
+    fn rejects_spending_more_than_available() {
+        let mut action_points = ActionPoints::from_special(&Special::new());
+        assert_eq!(action_points.current, 7);
+        assert_eq!(
+            action_points.spend(Action::Attack, 8),
+            Err(ActionError::NotEnoughActionPoints {
+                action: Action::Attack,
+                cost: 8,
+                available: 7
+            })
+        );
+        assert_eq!(action_points.current, 7);
+        assert_eq!(action_points.spend(Action::Move, 3), Ok(()));
+        assert_eq!(action_points.current, 4);
+    }
+}
@@ -0,0 +1,421 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::{Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    path::Path,
+    time::Duration,
+};
+
+use bevy_renet::renet::{generate_random_bytes, ConnectToken, NETCODE_KEY_BYTES};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    common::Username,
+    messages::{decode, encode},
+    PROTOCOL_ID,
+};
+
+/// Config shared by the server and the token issuer, relative to the working directory
+pub const DEFAULT_AUTH_CONFIG: &str = "auth.json";
+
+/// Where clients ask for connect tokens, unless the config says otherwise
+pub const DEFAULT_ISSUER_ADDRESS: &str = "127.0.0.1:5001";
+
+/// Connect tokens have to be used within this many seconds
+pub const TOKEN_EXPIRY_SECONDS: u64 = 300;
+
+/// Seconds without packets until a connection times out
+const CONNECTION_TIMEOUT_SECONDS: i32 = 15;
+
+/// Longest username that fits into the user data of a connect token
+pub const MAX_USERNAME_LENGTH: usize = 32;
+
+/// Requests and responses are far smaller, anything larger is garbage
+const MAX_TOKEN_MESSAGE_SIZE: u64 = 4 * 1024;
+
+/// Seconds the issuer and the client wait for the other side
+const TOKEN_IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Holds the private key, so it's never committed, the token issuer creates it on its first run
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuthConfig {
+    /// Hex encoded key the connect tokens are encrypted with, only the server and the issuer may know it
+    pub private_key: String,
+    #[serde(default = "default_issuer_address")]
+    pub issuer_address: SocketAddr,
+}
+
+fn default_issuer_address() -> SocketAddr {
+    DEFAULT_ISSUER_ADDRESS.parse().unwrap()
+}
+
+impl AuthConfig {
+    /// A config with a new random key and the default issuer address
+    pub fn generate() -> Self {
+        Self {
+            private_key: generate_private_key(),
+            issuer_address: default_issuer_address(),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, AuthError> {
+        let bytes = fs::read(path).map_err(|error| AuthError::Io(error.to_string()))?;
+        serde_json::from_slice(&bytes).map_err(|error| AuthError::Config(error.to_string()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), AuthError> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|error| AuthError::Config(error.to_string()))?;
+        fs::write(path, json).map_err(|error| AuthError::Io(error.to_string()))
+    }
+
+    pub fn private_key(&self) -> Result<[u8; NETCODE_KEY_BYTES], AuthError> {
+        let key = self.private_key.trim();
+        let mut bytes = [0; NETCODE_KEY_BYTES];
+        if key.len() != NETCODE_KEY_BYTES * 2 || !key.is_ascii() {
+            return Err(AuthError::InvalidKey);
+        }
+        for (byte, digits) in bytes.iter_mut().zip(key.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).map_err(|_| AuthError::InvalidKey)?;
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| AuthError::InvalidKey)?;
+        }
+        Ok(bytes)
+    }
+}
+
+/// A new random key, hex encoded for the [`AuthConfig`]
+pub fn generate_private_key() -> String {
+    generate_random_bytes::<NETCODE_KEY_BYTES>()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Client id handed out by the issuer, the secret proves it's the same client when asking again
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Identity {
+    pub client_id: u64,
+    pub secret: [u8; 32],
+}
+
+/// What a client sends to the issuer to get a connect token
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TokenRequest {
+    pub protocol_id: u64,
+    /// The server the token is for, it only accepts tokens with its own address
+    pub server_address: SocketAddr,
+    pub username: String,
+    /// Set when reconnecting, to get a token for the same client id again
+    pub identity: Option<Identity>,
+}
+
+impl TokenRequest {
+    pub fn new(
+        server_address: SocketAddr,
+        username: &Username,
+        identity: Option<Identity>,
+    ) -> Self {
+        Self {
+            protocol_id: PROTOCOL_ID,
+            server_address,
+            username: username.0.clone(),
+            identity,
+        }
+    }
+}
+
+/// Reason why no connect token could be issued or used
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum AuthError {
+    Io(String),
+    Config(String),
+    /// The private key isn't 32 hex encoded bytes
+    InvalidKey,
+    VersionMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    InvalidUsername,
+    /// The identity wasn't handed out by this issuer or its secret doesn't match
+    UnknownIdentity,
+    /// The issuer sent something that isn't a connect token
+    InvalidToken,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Io(error) => write!(f, "{}", error),
+            AuthError::Config(error) => write!(f, "invalid config: {}", error),
+            AuthError::InvalidKey => write!(
+                f,
+                "the private key has to be {} hex encoded bytes",
+                NETCODE_KEY_BYTES
+            ),
+            AuthError::VersionMismatch { expected, actual } => {
+                write!(f, "protocol version {} doesn't match {}", actual, expected)
+            }
+            AuthError::InvalidUsername => write!(
+                f,
+                "usernames have to be 1 to {} characters long",
+                MAX_USERNAME_LENGTH
+            ),
+            AuthError::UnknownIdentity => write!(f, "unknown identity"),
+            AuthError::InvalidToken => write!(f, "invalid connect token"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl From<std::io::Error> for AuthError {
+    fn from(error: std::io::Error) -> Self {
+        AuthError::Io(error.to_string())
+    }
+}
+
+/// Hands out connect tokens, every new client gets a client id no other client got
+pub struct TokenIssuer {
+    private_key: [u8; NETCODE_KEY_BYTES],
+    next_client_id: u64,
+    /// Secrets of all identities handed out, so reconnecting clients can prove who they are
+    secrets: HashMap<u64, [u8; 32]>,
+}
+
+impl TokenIssuer {
+    /// Ids start at the current time in milliseconds, so they don't repeat after a restart either
+    pub fn new(private_key: [u8; NETCODE_KEY_BYTES], current_time: Duration) -> Self {
+        Self {
+            private_key,
+            next_client_id: current_time.as_millis() as u64,
+            secrets: HashMap::new(),
+        }
+    }
+
+    /// A token for the requested server, together with the identity it was issued for
+    ///
+    /// Requests with an identity get a token for its client id again
+    pub fn issue(
+        &mut self,
+        request: &TokenRequest,
+        current_time: Duration,
+    ) -> Result<(Identity, ConnectToken), AuthError> {
+        if request.protocol_id != PROTOCOL_ID {
+            return Err(AuthError::VersionMismatch {
+                expected: PROTOCOL_ID,
+                actual: request.protocol_id,
+            });
+        }
+        let username = request.username.trim();
+        if username.is_empty() || username.len() > MAX_USERNAME_LENGTH {
+            return Err(AuthError::InvalidUsername);
+        }
+        let identity = match request.identity {
+            Some(identity) if self.secrets.get(&identity.client_id) == Some(&identity.secret) => {
+                identity
+            }
+            Some(_) => return Err(AuthError::UnknownIdentity),
+            None => Identity {
+                client_id: self.next_client_id,
+                secret: generate_random_bytes(),
+            },
+        };
+        let token = ConnectToken::generate(
+            current_time,
+            PROTOCOL_ID,
+            TOKEN_EXPIRY_SECONDS,
+            identity.client_id,
+            CONNECTION_TIMEOUT_SECONDS,
+            vec![request.server_address],
+            Some(&Username(username.to_string()).to_netcode_user_data()),
+            &self.private_key,
+        )
+        .map_err(|error| AuthError::Io(error.to_string()))?;
+        if request.identity.is_none() {
+            self.secrets.insert(identity.client_id, identity.secret);
+            self.next_client_id += 1;
+        }
+        Ok((identity, token))
+    }
+
+    /// Answers the token request of one client, returning the client id the token was issued for
+    pub fn serve(
+        &mut self,
+        stream: &mut TcpStream,
+        current_time: Duration,
+    ) -> Result<u64, AuthError> {
+        stream.set_read_timeout(Some(TOKEN_IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(TOKEN_IO_TIMEOUT))?;
+        let mut bytes = Vec::new();
+        stream
+            .take(MAX_TOKEN_MESSAGE_SIZE + 1)
+            .read_to_end(&mut bytes)?;
+        let result = decode::<TokenRequest>(&bytes, MAX_TOKEN_MESSAGE_SIZE)
+            .map_err(|error| AuthError::Io(error.to_string()))
+            .and_then(|request| self.issue(&request, current_time));
+        let response: Result<(Identity, Vec<u8>), AuthError> = match &result {
+            Ok((identity, token)) => {
+                let mut token_bytes = Vec::new();
+                token.write(&mut token_bytes)?;
+                Ok((*identity, token_bytes))
+            }
+            Err(error) => Err(error.clone()),
+        };
+        stream.write_all(&encode(&response))?;
+        // the client reads until the connection is closed
+        stream.shutdown(Shutdown::Write)?;
+        result.map(|(identity, _)| identity.client_id)
+    }
+}
+
+/// Asks the issuer at `issuer_address` for a connect token and the identity it was issued for
+pub fn request_token(
+    issuer_address: SocketAddr,
+    request: &TokenRequest,
+) -> Result<(Identity, ConnectToken), AuthError> {
+    let mut stream = TcpStream::connect_timeout(&issuer_address, TOKEN_IO_TIMEOUT)?;
+    stream.set_read_timeout(Some(TOKEN_IO_TIMEOUT))?;
+    stream.write_all(&encode(request))?;
+    stream.shutdown(Shutdown::Write)?;
+    let mut bytes = Vec::new();
+    stream
+        .take(MAX_TOKEN_MESSAGE_SIZE + 1)
+        .read_to_end(&mut bytes)?;
+    let response: Result<(Identity, Vec<u8>), AuthError> =
+        decode(&bytes, MAX_TOKEN_MESSAGE_SIZE).map_err(|_| AuthError::InvalidToken)?;
+    let (identity, token) = response?;
+    let token = ConnectToken::read(&mut token.as_slice()).map_err(|_| AuthError::InvalidToken)?;
+    Ok((identity, token))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    fn config() -> AuthConfig {
+        AuthConfig::generate()
+    }
+
+    fn request(username: &str) -> TokenRequest {
+        TokenRequest::new(
+            "127.0.0.1:5000".parse().unwrap(),
+            &Username(username.to_string()),
+            None,
+        )
+    }
+
+    #[test]
+    fn keys_are_hex_encoded() {
+        let mut config = config();
+        assert!(config.private_key().is_ok());
+        config.private_key.pop();
+        assert_eq!(config.private_key(), Err(AuthError::InvalidKey));
+        config.private_key.push('g');
+        assert_eq!(config.private_key(), Err(AuthError::InvalidKey));
+        let config: AuthConfig = serde_json::from_str(r#"{ "private_key": "00" }"#).unwrap();
+        assert_eq!(config.issuer_address, default_issuer_address());
+    }
+
+    #[test]
+    fn generated_configs_are_saved_and_loaded() {
+        let path = std::env::temp_dir().join(format!("foe-auth-{}.json", std::process::id()));
+        let config = config();
+        config.save(&path).unwrap();
+        let loaded = AuthConfig::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.private_key(), config.private_key());
+        assert_eq!(loaded.issuer_address, config.issuer_address);
+        assert_ne!(AuthConfig::generate().private_key, config.private_key);
+    }
+
+    #[test]
+    fn the_example_config_has_no_key() {
+        let config: AuthConfig =
+            serde_json::from_str(include_str!("../auth.example.json")).unwrap();
+        assert_eq!(config.private_key(), Err(AuthError::InvalidKey));
+    }
+
+    #[test]
+    fn client_ids_are_never_repeated() {
+        let mut issuer = TokenIssuer::new(config().private_key().unwrap(), Duration::ZERO);
+        let first = issuer.issue(&request("Applejack"), Duration::ZERO).unwrap();
+        let second = issuer.issue(&request("Applejack"), Duration::ZERO).unwrap();
+        assert_ne!(first.0.client_id, second.0.client_id);
+    }
+
+    #[test]
+    fn identities_get_their_client_id_again() {
+        let mut issuer = TokenIssuer::new(config().private_key().unwrap(), Duration::ZERO);
+        let (identity, _) = issuer
+            .issue(&request("Pinkie Pie"), Duration::ZERO)
+            .unwrap();
+        let mut reconnect = request("Pinkie Pie");
+        reconnect.identity = Some(identity);
+        let (again, _) = issuer.issue(&reconnect, Duration::ZERO).unwrap();
+        assert_eq!(again, identity);
+        // nopony else can claim the id
+        reconnect.identity = Some(Identity {
+            secret: [0; 32],
+            ..identity
+        });
+        assert_eq!(
+            issuer.issue(&reconnect, Duration::ZERO).unwrap_err(),
+            AuthError::UnknownIdentity
+        );
+        let (new, _) = issuer
+            .issue(&request("Pinkie Pie"), Duration::ZERO)
+            .unwrap();
+        assert_ne!(new.client_id, identity.client_id);
+    }
+
+    #[test]
+    fn bad_requests_get_no_token() {
+        let mut issuer = TokenIssuer::new(config().private_key().unwrap(), Duration::ZERO);
+        assert_eq!(
+            issuer.issue(&request(" "), Duration::ZERO).unwrap_err(),
+            AuthError::InvalidUsername
+        );
+        assert_eq!(
+            issuer
+                .issue(
+                    &request(&"x".repeat(MAX_USERNAME_LENGTH + 1)),
+                    Duration::ZERO
+                )
+                .unwrap_err(),
+            AuthError::InvalidUsername
+        );
+        let mut old_client = request("Rarity");
+        old_client.protocol_id = PROTOCOL_ID - 1;
+        assert_eq!(
+            issuer.issue(&old_client, Duration::ZERO).unwrap_err(),
+            AuthError::VersionMismatch {
+                expected: PROTOCOL_ID,
+                actual: PROTOCOL_ID - 1
+            }
+        );
+    }
+
+    #[test]
+    fn tokens_are_served_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer_address = listener.local_addr().unwrap();
+        let mut issuer = TokenIssuer::new(config().private_key().unwrap(), Duration::ZERO);
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let client_id = issuer.serve(&mut stream, Duration::ZERO);
+            let (mut stream, _) = listener.accept().unwrap();
+            (client_id, issuer.serve(&mut stream, Duration::ZERO))
+        });
+        let (identity, _) = request_token(issuer_address, &request("Fluttershy")).unwrap();
+        assert_eq!(
+            request_token(issuer_address, &request("")).unwrap_err(),
+            AuthError::InvalidUsername
+        );
+        let (client_id, refused) = server.join().unwrap();
+        assert_eq!(client_id, Ok(identity.client_id));
+        assert_eq!(refused, Err(AuthError::InvalidUsername));
+    }
+}
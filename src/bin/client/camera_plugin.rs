@@ -1,5 +1,7 @@
 use bevy::{prelude::*, input::mouse::MouseWheel};
 
+use crate::gui_plugin::{ChatInput, ChatLog};
+
 pub struct CameraPlugin;
 
 impl Plugin for CameraPlugin {
@@ -65,6 +67,8 @@ fn move_camera(
     time: Res<Time>,
     mut mouse_wheel_events: EventReader<MouseWheel>,
     mut current_zoom: ResMut<ZoomSettings>,
+    chat_input: Res<ChatInput>,
+    chat_log: Res<ChatLog>,
 ) {
     let mut dir = Vec3::splat(0.0);
     if key_input.pressed(KeyCode::A) {
@@ -84,10 +88,16 @@ fn move_camera(
         dir.z -= 1.0;
     }
 
-    for event in mouse_wheel_events.iter() {
+    // the chat panel scrolls instead
+    for event in mouse_wheel_events.iter().filter(|_| !chat_log.hovered) {
         current_zoom.add(-event.y * time.delta_seconds());
     }
 
+    // the letters go into the chat meanwhile
+    if chat_input.typing {
+        dir = Vec3::ZERO;
+    }
+
     for mut transform in &mut query {
         transform.translation += dir.normalize_or_zero() * time.delta_seconds() * MOVE_SPEED;
     }
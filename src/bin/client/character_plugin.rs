@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use fallout_equestria_tactics::{
+    character::{Character, Owner, Position},
+    common::Race,
+    flight::Altitude,
+    map::{AxialCoordinates, HexLayout},
+};
+
+/// Gives characters sent by the server a visible model
+pub struct CharacterPlugin;
+
+impl Plugin for CharacterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(load_character_assets)
+            .add_system(spawn_character_models)
+            // runs after the commands inserting Moving have been applied
+            .add_system_to_stage(CoreStage::PostUpdate, update_character_transforms)
+            .add_system(animate_movement);
+        info!("CharacterPlugin has been loaded");
+    }
+}
+
+/// Speed of walking characters in tiles per second
+const WALK_SPEED: f32 = 3.0;
+
+/// Tiles a character still has to walk over
+///
+/// Its [`Position`] already is the end of the path, the model catches up tile by tile
+#[derive(Component)]
+pub struct Moving(pub VecDeque<AxialCoordinates>);
+
+/// Height of a character model, it is lifted by half of it to stand on its tile
+const CHARACTER_HEIGHT: f32 = 1.2;
+
+#[derive(Resource)]
+struct CharacterAssets {
+    mesh: Handle<Mesh>,
+    earth_pony: Handle<StandardMaterial>,
+    pegasus: Handle<StandardMaterial>,
+    unicorn: Handle<StandardMaterial>,
+}
+
+fn load_character_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(CharacterAssets {
+        mesh: meshes.add(Mesh::from(shape::Capsule {
+            radius: 0.3,
+            depth: CHARACTER_HEIGHT - 0.6,
+            ..default()
+        })),
+        earth_pony: materials.add(Color::rgb(0.8, 0.55, 0.3).into()),
+        pegasus: materials.add(Color::rgb(0.4, 0.7, 0.95).into()),
+        unicorn: materials.add(Color::rgb(0.75, 0.45, 0.9).into()),
+    });
+}
+
+/// Returns where the model of a character standing on `position` or flying above it is placed
+pub fn character_translation(position: &Position, altitude: &Altitude, layout: &HexLayout) -> Vec3 {
+    position.to_world(layout)
+        + Vec3::Y * (CHARACTER_HEIGHT / 2.0 + altitude.0 as f32 * layout.elevation_step)
+}
+
+fn spawn_character_models(
+    mut commands: Commands,
+    query: Query<(Entity, &Race, &Position, &Altitude, &Owner), Added<Character>>,
+    character_assets: Res<CharacterAssets>,
+    layout: Res<HexLayout>,
+) {
+    for (entity, race, position, altitude, owner) in &query {
+        info!("Spawning model for character of {}", owner.0);
+        let material = match race {
+            Race::EarthPony => character_assets.earth_pony.clone(),
+            Race::Pegasus => character_assets.pegasus.clone(),
+            Race::Unicorn => character_assets.unicorn.clone(),
+        };
+        commands.entity(entity).insert(PbrBundle {
+            mesh: character_assets.mesh.clone(),
+            material,
+            transform: Transform::from_translation(character_translation(
+                position, altitude, &layout,
+            )),
+            ..default()
+        });
+    }
+}
+
+fn update_character_transforms(
+    mut query: Query<
+        (&Position, &Altitude, &mut Transform),
+        (
+            Or<(Changed<Position>, Changed<Altitude>)>,
+            With<Character>,
+            Without<Moving>,
+        ),
+    >,
+    layout: Res<HexLayout>,
+) {
+    for (position, altitude, mut transform) in &mut query {
+        transform.translation = character_translation(position, altitude, &layout);
+    }
+}
+
+fn animate_movement(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Moving, &Altitude, &mut Transform)>,
+    layout: Res<HexLayout>,
+    time: Res<Time>,
+) {
+    for (entity, mut moving, altitude, mut transform) in &mut query {
+        let mut distance = WALK_SPEED * layout.size * time.delta_seconds();
+        while let Some(next) = moving.0.front() {
+            let target = character_translation(&Position(*next), altitude, &layout);
+            let to_target = target - transform.translation;
+            if to_target.length() > distance {
+                transform.translation += to_target.normalize() * distance;
+                break;
+            }
+            distance -= to_target.length();
+            transform.translation = target;
+            moving.0.pop_front();
+        }
+        if moving.0.is_empty() {
+            commands.entity(entity).remove::<Moving>();
+        }
+    }
+}
@@ -1,74 +1,272 @@
-use std::{net::{UdpSocket, SocketAddr}, time::SystemTime};
+use std::{net::{UdpSocket, SocketAddr}, time::{Instant, SystemTime}};
 
-use bevy::prelude::*;
+use bevy::{
+    ecs::system::{SystemParam, SystemState},
+    prelude::*,
+};
 
 use bevy_renet::{
-    renet::{ClientAuthentication, DefaultChannel, RenetClient, RenetConnectionConfig},
+    renet::{ClientAuthentication, DefaultChannel, RenetClient},
     RenetClientPlugin,
 };
 use fallout_equestria_tactics::{
-    common::{Player, ServerEntity, Username},
-    messages::{ClientMessage, ServerMessage},
-    resources::{LevelName, Players},
-    PROTOCOL_ID,
+    action_points::ActionPoints,
+    auth::{request_token, AuthError, Identity, TokenRequest, DEFAULT_ISSUER_ADDRESS},
+    character::{Health, Position},
+    flight::Altitude,
+    map::Map,
+    common::{Player, ServerEntity, Special, Username},
+    messages::{
+        connection_config, decode, encode, ChatLine, ClientMessage, Handshake, Sequenced,
+        ServerMessage, CHAT_CHANNEL, MAX_SERVER_MESSAGE_SIZE,
+    },
+    replication::{GameSnapshot, MatchPhase, SequenceTracker},
+    resources::{Characters, LevelName, Players, TurnOrder},
 };
 
-use crate::common::ClientState;
+use crate::{
+    character_plugin::Moving, common::ClientState, gui_plugin::{ChatLog, MatchResult},
+    placement_plugin::SpawnZone, squad_builder_plugin::SquadStatus,
+};
 pub struct ClientPlugin;
 
 impl Plugin for ClientPlugin {
     fn build(&self, app: &mut App) {
+        let server_addr = "127.0.0.1:5000".parse().unwrap();
+        let user_name = Username("fartbag".to_string());
+        let (client, identity) = FoEClient::connect(server_addr, &user_name, None)
+            .unwrap_or_else(|error| {
+                panic!(
+                    "Couldn't get a connect token from {}, is the token_issuer running? {}",
+                    DEFAULT_ISSUER_ADDRESS, error
+                )
+            });
         app.add_plugin(RenetClientPlugin::default())
-            .insert_resource(FoEClient::new("127.0.0.1:5000".parse().unwrap(), &Username("fartbag".to_string())))
+            .insert_resource(client)
+            .insert_resource(ClientIdentity {
+                server_addr,
+                user_name,
+                identity,
+            })
             .insert_resource(Players::new())
+            .insert_resource(Characters::new())
+            .insert_resource(SequenceTracker::default())
             .add_system(handle_reliable_messages)
-            .add_system(handle_unreliable_messages);
+            .add_system(reconnect)
+            .add_system_set(SystemSet::on_exit(ClientState::Results).with_system(reset_match))
+            .add_system(handle_unreliable_messages)
+            .add_system(handle_chat_messages);
         info!("ClientPlugin loaded");
     }
 }
 
+/// Seconds between attempts to reconnect after the connection dropped
+const RECONNECT_INTERVAL: f32 = 2.0;
+
+/// Who this client is to the token issuer, removed once the server doesn't want it back
+#[derive(Resource)]
+struct ClientIdentity {
+    server_addr: SocketAddr,
+    user_name: Username,
+    identity: Identity,
+}
+
 struct FoEClient;
 
 impl FoEClient {
-    fn new(server_addr: SocketAddr, user_name: &Username) -> RenetClient {
-        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
-        let connection_config = RenetConnectionConfig::default();
+    /// Connects with a connect token of the token issuer running on localhost, which also picks the client id
+    ///
+    /// Reconnecting clients pass their `identity` to keep their client id
+    fn connect(
+        server_addr: SocketAddr,
+        user_name: &Username,
+        identity: Option<Identity>,
+    ) -> Result<(RenetClient, Identity), AuthError> {
+        let issuer_addr = DEFAULT_ISSUER_ADDRESS.parse().unwrap();
+        let (identity, connect_token) = request_token(
+            issuer_addr,
+            &TokenRequest::new(server_addr, user_name, identity),
+        )?;
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        let connection_config = connection_config();
         let current_time = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap();
-        let client_id = current_time.as_millis() as u64;
+        let authentication = ClientAuthentication::Secure { connect_token };
+        let mut client =
+            RenetClient::new(current_time, socket, connection_config, authentication)
+                .map_err(|error| AuthError::Io(error.to_string()))?;
+        // queued until the connection stands, the server expects it before anything else
+        client.send_message(DefaultChannel::Reliable, encode(&Handshake::new()));
+        Ok((client, identity))
+    }
+}
 
-        let authentication = ClientAuthentication::Unsecure {
-            client_id,
-            protocol_id: PROTOCOL_ID,
-            server_addr,
-            user_data: Some(user_name.to_netcode_user_data()),
-        };
-        RenetClient::new(current_time, socket, connection_config, authentication).unwrap()
+/// Replaces the client with a new connection under the same identity once the connection dropped
+///
+/// The server keeps the player in the match for a while and sends everything again on return
+fn reconnect(
+    mut commands: Commands,
+    client: Res<RenetClient>,
+    identity: Option<Res<ClientIdentity>>,
+    time: Res<Time>,
+    mut cooldown: Local<f32>,
+) {
+    let identity = match identity {
+        Some(identity) if client.disconnected().is_some() => identity,
+        _ => return,
+    };
+    *cooldown -= time.delta_seconds();
+    if *cooldown > 0.0 {
+        return;
+    }
+    *cooldown = RECONNECT_INTERVAL;
+    info!("Connection lost, reconnecting");
+    match FoEClient::connect(
+        identity.server_addr,
+        &identity.user_name,
+        Some(identity.identity),
+    ) {
+        Ok((client, _)) => {
+            commands.insert_resource(client);
+            // the new connection counts from zero and starts with a snapshot
+            commands.insert_resource(SequenceTracker::default());
+        }
+        Err(error) => warn!("Couldn't reconnect: {}", error),
     }
 }
 
+/// Applies the messages of the server, with direct access to the world
+///
+/// A snapshot or a character coming into sight spawns characters the deltas right behind it already change,
+/// so the commands of every message are applied before the next one
 fn handle_reliable_messages(
-    mut client: ResMut<RenetClient>,
-    mut players: ResMut<Players>,
-    mut app_state: ResMut<State<ClientState>>,
-    mut commands: Commands,
-    mut level_name: ResMut<LevelName>,
+    world: &mut World,
+    receiver: &mut SystemState<(
+        ResMut<'static, RenetClient>,
+        ResMut<'static, SequenceTracker>,
+    )>,
+    game: &mut SystemState<ReplicatedGame<'static, 'static>>,
 ) {
-    while let Some(message) = client.receive_message(DefaultChannel::Reliable) {
-        let server_message = bincode::deserialize(&message).unwrap();
-        match server_message {
+    let (client_id, server_messages) = {
+        let (mut client, mut sequence_tracker) = receiver.get_mut(world);
+        let server_messages = receive_server_messages(&mut client, &mut sequence_tracker);
+        (client.client_id(), server_messages)
+    };
+    apply_server_messages(world, game, client_id, server_messages);
+}
+
+/// Returns the messages of the server that are next in the sequence, asks for a snapshot once it lost track
+fn receive_server_messages(
+    client: &mut RenetClient,
+    sequence_tracker: &mut SequenceTracker,
+) -> Vec<ServerMessage> {
+    let now = Instant::now();
+    let mut server_messages = Vec::new();
+    // snapshots come over the chunk channel, the sequence puts everything back in order
+    for channel in [DefaultChannel::Chunk, DefaultChannel::Reliable] {
+        let channel: u8 = channel.into();
+        while let Some(message) = client.receive_message(channel) {
+            match decode(&message, MAX_SERVER_MESSAGE_SIZE) {
+                // the connection ends right after, there is nothing to keep in order
+                Ok(Sequenced {
+                    message: ServerMessage::Kicked(error),
+                    ..
+                }) => server_messages.push(ServerMessage::Kicked(error)),
+                Ok(sequenced) => server_messages.extend(sequence_tracker.receive(sequenced, now)),
+                Err(error) => error!("Couldn't read message of the server: {}", error),
+            }
+        }
+    }
+    if sequence_tracker.take_resync_request(now) {
+        warn!("Lost track of the game, asking the server for a snapshot");
+        client.send_message(DefaultChannel::Reliable, encode(&ClientMessage::RequestResync));
+    }
+    server_messages
+}
+
+/// Applies `server_messages` in order, the commands of each one are applied before the next
+fn apply_server_messages(
+    world: &mut World,
+    game: &mut SystemState<ReplicatedGame>,
+    client_id: u64,
+    server_messages: Vec<ServerMessage>,
+) {
+    // a snapshot and the deltas after it arrive together, only the last state change counts
+    let mut next_state = None;
+    for server_message in server_messages {
+        game.get_mut(world)
+            .apply(server_message, client_id, &mut next_state);
+        game.apply(world);
+    }
+    let mut app_state = world.resource_mut::<State<ClientState>>();
+    if let Some(state) = next_state.filter(|state| state != app_state.current()) {
+        // the server decides, whatever the client queued itself this frame
+        app_state.overwrite_set(state).unwrap();
+    }
+}
+
+/// Everything the messages of the server change on the client
+#[derive(SystemParam)]
+struct ReplicatedGame<'w, 's> {
+    commands: Commands<'w, 's>,
+    app_state: Res<'w, State<ClientState>>,
+    players: ResMut<'w, Players>,
+    level_name: ResMut<'w, LevelName>,
+    characters: ResMut<'w, Characters>,
+    stats_query: Query<
+        'w,
+        's,
+        (
+            &'static mut Special,
+            &'static mut Health,
+            &'static mut ActionPoints,
+        ),
+    >,
+    position_query: Query<'w, 's, &'static mut Position>,
+    altitude_query: Query<'w, 's, &'static mut Altitude>,
+    map: ResMut<'w, Map>,
+    spawn_zone: ResMut<'w, SpawnZone>,
+    match_result: ResMut<'w, MatchResult>,
+    squad_status: ResMut<'w, SquadStatus>,
+}
+
+impl<'w, 's> ReplicatedGame<'w, 's> {
+    /// Applies a single message, state changes only end up in `next_state`
+    fn apply(
+        &mut self,
+        message: ServerMessage,
+        client_id: u64,
+        next_state: &mut Option<ClientState>,
+    ) {
+        let Self {
+            commands,
+            app_state,
+            players,
+            level_name,
+            characters,
+            stats_query,
+            position_query,
+            altitude_query,
+            map,
+            spawn_zone,
+            match_result,
+            squad_status,
+        } = self;
+        match message {
+            ServerMessage::Kicked(error) => {
+                error!("Kicked by the server: {}", error);
+                // the server won't keep the slot of a kicked client
+                commands.remove_resource::<ClientIdentity>();
+            }
+            // the own player comes with the snapshot
             ServerMessage::PlayerConnected(id, player_name, server_entity) => {
                 info!("{} connected", id);
-                let mut entity = commands
-                    .spawn(ServerEntity(server_entity));
-
-                entity.insert(Name::from(player_name));
-                if id == client.client_id() {
-                    entity.insert(Player(id));
-                    app_state.set(ClientState::Connected).unwrap();
-                }
-                players.players.insert(id, entity.id());
+                let entity = commands
+                    .spawn(ServerEntity(server_entity))
+                    .insert(Name::from(player_name))
+                    .id();
+                players.players.insert(id, entity);
             }
             ServerMessage::PlayerDisconnected(id) => {
                 info!("{} disconnected", id);
@@ -76,35 +274,390 @@ fn handle_reliable_messages(
                     commands.entity(player).despawn();
                 }
             }
-            ServerMessage::PlayerTurn(id) => {
-                if id == client.client_id() {
-                    app_state.set(ClientState::Acting).unwrap();
-                } else if app_state.current() != &ClientState::Idling {
-                    app_state.set(ClientState::Idling).unwrap();
+            ServerMessage::PlayerAbsent(id) => {
+                info!("{} lost the connection", id);
+            }
+            ServerMessage::PlayerReturned(id) => {
+                info!("{} returned", id);
+            }
+            ServerMessage::Snapshot(snapshot) => {
+                info!("Received a snapshot of the game");
+                let current = next_state.as_ref().unwrap_or(app_state.current());
+                if let Some(state) = snapshot_state(current, &snapshot, client_id) {
+                    *next_state = Some(state);
+                }
+                for (_, player) in players.players.drain() {
+                    commands.entity(player).despawn();
+                }
+                for player in snapshot.players {
+                    let mut entity = commands.spawn(ServerEntity(player.entity));
+                    entity.insert(Name::from(player.name));
+                    if player.id == client_id {
+                        entity.insert(Player(player.id));
+                    }
+                    players.players.insert(player.id, entity.id());
+                }
+                for (_, entity) in characters.characters.drain() {
+                    if let Ok(position) = position_query.get(entity) {
+                        map.vacate(position.0);
+                    }
+                    commands.entity(entity).despawn_recursive();
                 }
+                for character in snapshot.characters {
+                    let data = character.data;
+                    map.occupy(data.position, data.entity);
+                    let mut entity = commands.spawn(data.to_bundle());
+                    entity.insert(ServerEntity(data.entity));
+                    if let Some(inventory) = character.inventory {
+                        entity.insert(inventory);
+                    }
+                    if let Some(ledger) = character.ledger {
+                        entity.insert(ledger);
+                    }
+                    characters.characters.insert(data.entity, entity.id());
+                }
+                spawn_zone.0 = snapshot.spawn_zone;
+                level_name.0 = snapshot.level;
+                commands.insert_resource(TurnOrder {
+                    order: snapshot.turn_order.into(),
+                });
+            }
+            ServerMessage::PlayerTurn(id) => {
+                *next_state = Some(if id == client_id {
+                    ClientState::Acting
+                } else {
+                    ClientState::Idling
+                });
             }
             ServerMessage::LoadLevel(level) => {
                 info!("Shoud load level {}", level);
                 level_name.0 = level;
-                app_state.set(ClientState::LoadingLevel).unwrap();
+                *next_state = Some(ClientState::LoadingLevel);
+            }
+            ServerMessage::AssignSpawnZone(tiles) => {
+                info!("This players spawn zone has {} tiles", tiles.len());
+                spawn_zone.0 = tiles;
+            }
+            ServerMessage::PlacementTurn(id) => {
+                *next_state = Some(if id == client_id {
+                    ClientState::Placing
+                } else {
+                    ClientState::Idling
+                });
+            }
+            ServerMessage::PlacementRejected(error) => {
+                warn!("Server rejected placement: {:?}", error);
+            }
+            ServerMessage::CharacterSpawned(character)
+            | ServerMessage::EnteredVision(character) => {
+                info!("{} appeared at {:?}", character.name, character.position);
+                // the server derives the stats from the race definitions, the client takes them as is
+                // the local map tracks characters by their server entity
+                map.occupy(character.position, character.entity);
+                let entity = commands
+                    .spawn(character.to_bundle())
+                    .insert(ServerEntity(character.entity))
+                    .id();
+                characters.characters.insert(character.entity, entity);
+            }
+            // characters out of sight are forgotten until they are seen again
+            ServerMessage::CharacterDespawned(server_entity)
+            | ServerMessage::LeftVision(server_entity) => {
+                if let Some(entity) = characters.characters.remove(&server_entity) {
+                    if let Ok(position) = position_query.get(entity) {
+                        map.vacate(position.0);
+                    }
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
+            ServerMessage::ActionPointsChanged(server_entity, new_action_points) => {
+                if let Some(&entity) = characters.get(&server_entity) {
+                    if let Ok((_, _, mut action_points)) = stats_query.get_mut(entity) {
+                        *action_points = new_action_points;
+                    }
+                }
+            }
+            ServerMessage::CharacterMoved(server_entity, path) => {
+                if let Some(&entity) = characters.get(&server_entity) {
+                    if let Ok(mut position) = position_query.get_mut(entity) {
+                        map.vacate(position.0);
+                        position.0 = *path.tiles.last().unwrap_or(&position.0);
+                        map.occupy(position.0, server_entity);
+                        commands
+                            .entity(entity)
+                            .insert(Moving(path.tiles.into_iter().skip(1).collect()));
+                    }
+                }
             }
-            ServerMessage::AssignSpawnpoint(spawn_point) => {
-                info!("This players spawnpoint is {:?}", spawn_point);
+            ServerMessage::AltitudeChanged(server_entity, new_altitude) => {
+                if let Some(&entity) = characters.get(&server_entity) {
+                    if let Ok(mut altitude) = altitude_query.get_mut(entity) {
+                        *altitude = new_altitude;
+                    }
+                }
+            }
+            ServerMessage::AttackResolved(result) => {
+                info!("Attack resolved: {:?}", result);
+                if let Some(&entity) = characters.get(&result.defender) {
+                    if let Ok((_, mut health, _)) = stats_query.get_mut(entity) {
+                        *health = result.health;
+                    }
+                    if result.is_lethal() {
+                        characters.characters.remove(&result.defender);
+                        if let Ok(position) = position_query.get(entity) {
+                            map.vacate(position.0);
+                        }
+                        commands.entity(entity).despawn_recursive();
+                    }
+                }
+            }
+            ServerMessage::SpellCast(result) => {
+                info!("Spell cast: {:?}", result);
+                for (server_entity, health) in result.affected {
+                    if let Some(&entity) = characters.get(&server_entity) {
+                        if let Ok((_, mut current_health, _)) = stats_query.get_mut(entity) {
+                            *current_health = health;
+                        }
+                        if !health.is_alive() {
+                            characters.characters.remove(&server_entity);
+                            if let Ok(position) = position_query.get(entity) {
+                                map.vacate(position.0);
+                            }
+                            commands.entity(entity).despawn_recursive();
+                        }
+                    }
+                }
+            }
+            ServerMessage::HealthChanged(server_entity, new_health) => {
+                if let Some(&entity) = characters.get(&server_entity) {
+                    if let Ok((_, mut health, _)) = stats_query.get_mut(entity) {
+                        *health = new_health;
+                    }
+                }
+            }
+            ServerMessage::InventoryChanged(server_entity, inventory) => {
+                if let Some(&entity) = characters.get(&server_entity) {
+                    commands.entity(entity).insert(inventory);
+                }
+            }
+            ServerMessage::ExperienceChanged(server_entity, ledger) => {
+                if let Some(&entity) = characters.get(&server_entity) {
+                    commands.entity(entity).insert(ledger);
+                }
+            }
+            ServerMessage::StatsChanged(server_entity, new_special, new_health, new_action_points) => {
+                if let Some(&entity) = characters.get(&server_entity) {
+                    if let Ok((mut special, mut health, mut action_points)) =
+                        stats_query.get_mut(entity)
+                    {
+                        *special = new_special;
+                        *health = new_health;
+                        *action_points = new_action_points;
+                    }
+                }
+            }
+            ServerMessage::MatchEnded { winner, stats } => {
+                info!("Match ended, winner is {:?}", winner);
+                **match_result = MatchResult { winner, stats };
+                *next_state = Some(ClientState::Results);
+            }
+            ServerMessage::SquadAccepted => {
+                **squad_status = SquadStatus::Accepted;
+            }
+            ServerMessage::SquadRejected(error) => {
+                warn!("Server rejected squad: {:?}", error);
+                **squad_status = SquadStatus::Rejected(error);
+            }
+            ServerMessage::ActionRejected(server_entity, error) => {
+                warn!("Server rejected action of {:?}: {:?}", server_entity, error);
             }
             _ => (),
         }
     }
 }
 
+/// The state the client should be in after the snapshot, None to stay in the current one
+///
+/// Only clients with the level loaded take part in placement and turns
+fn snapshot_state(
+    current: &ClientState,
+    snapshot: &GameSnapshot,
+    client_id: u64,
+) -> Option<ClientState> {
+    let own_turn = snapshot.current_player == Some(client_id);
+    let next = match (current, snapshot.phase) {
+        (ClientState::WaitingToConnect, _) => ClientState::Connected,
+        (ClientState::Connected, MatchPhase::LoadingLevel) => ClientState::LoadingLevel,
+        (
+            ClientState::Placing | ClientState::Idling | ClientState::Acting,
+            MatchPhase::Placement,
+        ) if own_turn => ClientState::Placing,
+        (
+            ClientState::Placing | ClientState::Idling | ClientState::Acting,
+            MatchPhase::Turns,
+        ) if own_turn => ClientState::Acting,
+        (
+            ClientState::Placing | ClientState::Idling | ClientState::Acting,
+            MatchPhase::Placement | MatchPhase::Turns,
+        ) => ClientState::Idling,
+        _ => return None,
+    };
+    (&next != current).then_some(next)
+}
+
+/// Forgets everything about the last match, the level is loaded anew for the next one
+fn reset_match(
+    mut commands: Commands,
+    mut characters: ResMut<Characters>,
+    mut map: ResMut<Map>,
+    mut spawn_zone: ResMut<SpawnZone>,
+    level_query: Query<(Entity, &Name), Without<Parent>>,
+) {
+    for (_, entity) in characters.characters.drain() {
+        commands.entity(entity).despawn_recursive();
+    }
+    for (entity, name) in &level_query {
+        if name.as_str() == "Level" {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    map.clear();
+    spawn_zone.0.clear();
+}
+
 fn handle_unreliable_messages(
     mut client: ResMut<RenetClient>,
     players: Res<Players>,
     mut commands: Commands,
 ) {
     while let Some(message) = client.receive_message(DefaultChannel::Unreliable) {
-        let server_message: ServerMessage = bincode::deserialize(&message).unwrap();
+        let server_message: ServerMessage = match decode(&message, MAX_SERVER_MESSAGE_SIZE) {
+            Ok(server_message) => server_message,
+            Err(error) => {
+                error!("Couldn't read message of the server: {}", error);
+                continue;
+            }
+        };
         match server_message {
             _ => (),
         }
     }
 }
+
+/// Writes what the server relayed into the [`ChatLog`], with the names of the players
+fn handle_chat_messages(
+    mut client: ResMut<RenetClient>,
+    players: Res<Players>,
+    name_query: Query<&Name>,
+    mut chat_log: ResMut<ChatLog>,
+) {
+    let name = |id: u64| {
+        players
+            .get(&id)
+            .and_then(|&entity| name_query.get(entity).ok())
+            .map_or_else(|| id.to_string(), |name| name.to_string())
+    };
+    while let Some(message) = client.receive_message(CHAT_CHANNEL) {
+        let line = match decode(&message, MAX_SERVER_MESSAGE_SIZE) {
+            Ok(line) => line,
+            Err(error) => {
+                error!("Couldn't read chat of the server: {}", error);
+                continue;
+            }
+        };
+        let line = match line {
+            ChatLine::Public(from, text) => format!("{}: {}", name(from), text),
+            ChatLine::Private { from, to, text } if from == client.client_id() => {
+                format!("To {}: {}", name(to), text)
+            }
+            ChatLine::Private { from, text, .. } => format!("{} whispers: {}", name(from), text),
+            ChatLine::Team(from, text) => format!("[Team] {}: {}", name(from), text),
+            ChatLine::System(text) => text,
+            ChatLine::Refused(error) => format!("Not sent, {}", error),
+        };
+        chat_log.push(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fallout_equestria_tactics::{
+        character::{CharacterBundle, CharacterData},
+        common::Race,
+        map::AxialCoordinates,
+        pathfinding::Path,
+        replication::CharacterSnapshot,
+    };
+
+    use super::*;
+
+    const START: AxialCoordinates = AxialCoordinates::new(0, 0, 0);
+
+    fn setup() -> (World, SystemState<ReplicatedGame<'static, 'static>>) {
+        let mut world = World::new();
+        world.insert_resource(State::new(ClientState::Idling));
+        world.insert_resource(Players::new());
+        world.insert_resource(Characters::new());
+        world.insert_resource(LevelName::new(""));
+        world.insert_resource(Map::generate(3, 3));
+        world.insert_resource(SpawnZone(Vec::new()));
+        world.insert_resource(MatchResult::default());
+        world.insert_resource(SquadStatus::Editing);
+        let game = SystemState::new(&mut world);
+        (world, game)
+    }
+
+    /// A snapshot of a running match with a single character standing on [`START`]
+    fn snapshot(server_entity: Entity) -> ServerMessage {
+        let bundle = CharacterBundle::new("Test", 2, Race::EarthPony, Special::new(), START);
+        ServerMessage::Snapshot(GameSnapshot {
+            phase: MatchPhase::Turns,
+            level: String::new(),
+            players: Vec::new(),
+            characters: vec![CharacterSnapshot {
+                data: CharacterData::from_bundle(server_entity, &bundle),
+                inventory: None,
+                ledger: None,
+            }],
+            current_player: Some(2),
+            turn_order: Vec::new(),
+            spawn_zone: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn deltas_right_after_the_snapshot_are_applied() {
+        let (mut world, mut game) = setup();
+        let server_entity = Entity::from_raw(7);
+        let goal = AxialCoordinates::new(2, 0, 0);
+        let path = Path {
+            tiles: vec![START, AxialCoordinates::new(1, 0, 0), goal],
+            cost: 2.0,
+        };
+        let messages = vec![
+            snapshot(server_entity),
+            ServerMessage::CharacterMoved(server_entity, path),
+        ];
+        apply_server_messages(&mut world, &mut game, 1, messages);
+
+        let entity = world.resource::<Characters>().characters[&server_entity];
+        assert_eq!(world.get::<Position>(entity).unwrap().0, goal);
+        let map = world.resource::<Map>();
+        assert_eq!(map.get(START).unwrap().occupant, None);
+        assert_eq!(map.get(goal).unwrap().occupant, Some(server_entity));
+    }
+
+    #[test]
+    fn characters_despawned_right_after_the_snapshot_free_their_tile() {
+        let (mut world, mut game) = setup();
+        let server_entity = Entity::from_raw(7);
+        let messages = vec![
+            snapshot(server_entity),
+            ServerMessage::CharacterDespawned(server_entity),
+        ];
+        apply_server_messages(&mut world, &mut game, 1, messages);
+
+        assert!(world.resource::<Characters>().characters.is_empty());
+        assert_eq!(world.resource::<Map>().get(START).unwrap().occupant, None);
+    }
+}
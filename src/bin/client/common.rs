@@ -6,6 +6,9 @@ pub enum ClientState {
     Connected,
     LoadingLevel,
     LevelLoaded,
+    Placing,
     Idling,
     Acting,
+    /// The match is over and its results are shown
+    Results,
 }
@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use bevy_renet::renet::RenetClient;
+use fallout_equestria_tactics::{
+    character::{Character, Owner, Position},
+    common::Special,
+    map::{HexLayout, Map},
+    visibility::sight_range,
+};
+
+use crate::{
+    common::ClientState,
+    picking_plugin::{hex_mesh, hex_transform},
+    placement_plugin::SpawnZone,
+};
+
+/// Darkens hexes none of our characters can see and hides those we have never seen
+pub struct FogPlugin;
+
+impl Plugin for FogPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Fog::default())
+            .add_startup_system(load_fog_assets)
+            .add_system(spawn_fog_overlays)
+            .add_system(update_fog.after(spawn_fog_overlays))
+            .add_system_set(SystemSet::on_exit(ClientState::Results).with_system(clear_fog));
+        info!("FogPlugin has been loaded");
+    }
+}
+
+/// Fog is drawn slightly above other hex overlays, so they don't flicker with each other
+const FOG_OFFSET: f32 = 0.01;
+
+/// What our characters know about the map, hexes are keyed by q and r
+#[derive(Default, Resource)]
+pub struct Fog {
+    /// Hexes one of our characters has seen at some point
+    pub explored: HashSet<(i32, i32)>,
+    /// Hexes one of our characters sees right now
+    pub visible: HashSet<(i32, i32)>,
+    overlays: HashMap<(i32, i32), Entity>,
+}
+
+#[derive(Resource)]
+struct FogAssets {
+    mesh: Handle<Mesh>,
+    unexplored: Handle<StandardMaterial>,
+    out_of_sight: Handle<StandardMaterial>,
+}
+
+#[derive(Component)]
+struct FogOverlay;
+
+fn load_fog_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    layout: Res<HexLayout>,
+) {
+    let mut fog_material = |alpha| {
+        materials.add(StandardMaterial {
+            base_color: Color::rgba(0.0, 0.0, 0.0, alpha),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        })
+    };
+    commands.insert_resource(FogAssets {
+        mesh: meshes.add(hex_mesh(&layout)),
+        unexplored: fog_material(0.9),
+        out_of_sight: fog_material(0.5),
+    });
+}
+
+/// Covers every hex of the map with fog, as soon as the map knows about it
+fn spawn_fog_overlays(
+    mut commands: Commands,
+    mut fog: ResMut<Fog>,
+    map: Res<Map>,
+    layout: Res<HexLayout>,
+    fog_assets: Res<FogAssets>,
+) {
+    if !map.is_changed() {
+        return;
+    }
+    for tile in map.tiles() {
+        let key = (tile.coordinates.q, tile.coordinates.r);
+        if fog.overlays.contains_key(&key) {
+            continue;
+        }
+        let mut transform = hex_transform(tile.coordinates, &layout);
+        transform.translation.y += FOG_OFFSET;
+        let entity = commands
+            .spawn(PbrBundle {
+                mesh: fog_assets.mesh.clone(),
+                material: fog_assets.unexplored.clone(),
+                transform,
+                ..default()
+            })
+            .insert(FogOverlay)
+            .insert(Name::from("Fog"))
+            .id();
+        fog.overlays.insert(key, entity);
+    }
+}
+
+/// Recomputes what our characters see whenever one of them moved, appeared or died
+fn update_fog(
+    mut fog: ResMut<Fog>,
+    client: Res<RenetClient>,
+    map: Res<Map>,
+    spawn_zone: Res<SpawnZone>,
+    fog_assets: Res<FogAssets>,
+    character_query: Query<(&Owner, &Position, &Special), With<Character>>,
+    changed_query: Query<(), (With<Character>, Or<(Added<Character>, Changed<Position>)>)>,
+    removed: RemovedComponents<Character>,
+    mut overlay_query: Query<(&mut Handle<StandardMaterial>, &mut Visibility), With<FogOverlay>>,
+) {
+    if changed_query.is_empty()
+        && removed.iter().next().is_none()
+        && !map.is_changed()
+        && !spawn_zone.is_changed()
+    {
+        return;
+    }
+
+    let fog = &mut *fog;
+    fog.visible.clear();
+    for (owner, position, special) in &character_query {
+        if owner.0 != client.client_id() {
+            continue;
+        }
+        for coordinates in map.visible_tiles(position.0, sight_range(special)) {
+            fog.visible.insert((coordinates.q, coordinates.r));
+        }
+    }
+    // the spawn zone has to be seen to place characters on it
+    fog.explored.extend(
+        spawn_zone
+            .0
+            .iter()
+            .map(|coordinates| (coordinates.q, coordinates.r)),
+    );
+    fog.explored.extend(fog.visible.iter().copied());
+
+    for (key, &entity) in &fog.overlays {
+        if let Ok((mut material, mut visibility)) = overlay_query.get_mut(entity) {
+            visibility.is_visible = !fog.visible.contains(key);
+            *material = if fog.explored.contains(key) {
+                fog_assets.out_of_sight.clone()
+            } else {
+                fog_assets.unexplored.clone()
+            };
+        }
+    }
+}
+
+fn clear_fog(mut commands: Commands, mut fog: ResMut<Fog>) {
+    for (_, entity) in fog.overlays.drain() {
+        commands.entity(entity).despawn_recursive();
+    }
+    *fog = Fog::default();
+}
@@ -1,6 +1,12 @@
-use bevy::prelude::*;
+use std::collections::VecDeque;
+
+use bevy::{app::AppExit, input::mouse::MouseWheel, prelude::*};
 use bevy_renet::renet::{DefaultChannel, RenetClient};
-use fallout_equestria_tactics::messages::ClientMessage;
+use fallout_equestria_tactics::{
+    messages::{check_chat_text, encode, ChatMessage, ClientMessage, CHAT_CHANNEL},
+    resources::Players,
+    stats::PlayerStats,
+};
 
 use crate::common::ClientState;
 
@@ -22,6 +28,18 @@ impl Plugin for GuiPlugin {
         app.add_system_set(SystemSet::on_enter(ClientState::Idling).with_system(setup_idling))
             .add_system_set(SystemSet::on_update(ClientState::Idling).with_system(update_idling))
             .add_system_set(SystemSet::on_exit(ClientState::Idling).with_system(exit_idling));
+        app.insert_resource(MatchResult::default())
+            .add_system_set(SystemSet::on_enter(ClientState::Results).with_system(setup_results))
+            .add_system_set(SystemSet::on_update(ClientState::Results).with_system(update_results))
+            .add_system_set(SystemSet::on_exit(ClientState::Results).with_system(exit_results));
+        app.add_system_set(SystemSet::on_enter(ClientState::Placing).with_system(setup_placing))
+            .add_system_set(SystemSet::on_exit(ClientState::Placing).with_system(exit_placing));
+        app.insert_resource(ChatLog::default())
+            .insert_resource(ChatInput::default())
+            .add_startup_system(setup_chat)
+            .add_system(type_chat)
+            .add_system(scroll_chat)
+            .add_system(update_chat.after(type_chat).after(scroll_chat));
         info!("GuiPlugin loaded");
     }
 }
@@ -65,7 +83,7 @@ fn setup_ready_button(mut commands: Commands, asset_server: Res<AssetServer>) {
 fn handle_ready_button(
     mut interaction_query: Query<
         (&Interaction, &mut BackgroundColor),
-        (Changed<Interaction>, With<Button>),
+        (Changed<Interaction>, With<ReadyButton>),
     >,
     mut client: ResMut<RenetClient>,
 ) {
@@ -73,7 +91,7 @@ fn handle_ready_button(
         match interaction {
             Interaction::Clicked => {
                 *background_color = PRESSED_BUTTON.into();
-                let message = bincode::serialize(&ClientMessage::ClientReady).unwrap();
+                let message = encode(&ClientMessage::ClientReady);
                 client.send_message(DefaultChannel::Reliable, message);
             }
             Interaction::Hovered => {
@@ -133,7 +151,7 @@ fn update_acting(
         match interaction {
             Interaction::Clicked => {
                 *background_color = PRESSED_BUTTON.into();
-                let message = bincode::serialize(&ClientMessage::EndTurn).unwrap();
+                let message = encode(&ClientMessage::EndTurn);
                 client.send_message(DefaultChannel::Reliable, message);
             }
             Interaction::Hovered => {
@@ -179,3 +197,393 @@ fn exit_idling(mut commands: Commands, query: Query<Entity, With<IdleText>>) {
         commands.entity(entity).despawn_recursive();
     }
 }
+
+#[derive(Component)]
+struct PlacingText;
+
+fn setup_placing(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn(
+            TextBundle::from_section(
+                "Place a character in your spawn zone",
+                TextStyle {
+                    font: asset_server.load("fonts/Overseer.otf"),
+                    font_size: 46.0,
+                    ..default()
+                },
+            )
+            .with_text_alignment(TextAlignment::CENTER),
+        )
+        .insert(PlacingText)
+        .insert(Name::from("Placing Text"));
+}
+
+fn exit_placing(mut commands: Commands, query: Query<Entity, With<PlacingText>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Outcome of the last match, as announced by the server
+#[derive(Default, Resource)]
+pub struct MatchResult {
+    pub winner: Option<u64>,
+    pub stats: Vec<PlayerStats>,
+}
+
+#[derive(Component)]
+struct ResultsScreen;
+
+#[derive(Component)]
+struct ReturnToLobbyButton;
+
+#[derive(Component)]
+struct QuitButton;
+
+fn setup_results(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    match_result: Res<MatchResult>,
+    client: Res<RenetClient>,
+) {
+    let font = asset_server.load("fonts/Overseer.otf");
+    let text_style = |font_size| TextStyle {
+        font: font.clone(),
+        font_size,
+        ..default()
+    };
+    let headline = match match_result.winner {
+        Some(winner) if winner == client.client_id() => String::from("You win!"),
+        Some(winner) => match match_result
+            .stats
+            .iter()
+            .find(|stats| stats.player == winner)
+        {
+            Some(stats) => format!("{} wins", stats.name),
+            None => String::from("You lost"),
+        },
+        None => String::from("Nopony survived"),
+    };
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .insert(ResultsScreen)
+        .insert(Name::from("Results Screen"))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(headline, text_style(46.0)));
+            for stats in &match_result.stats {
+                parent.spawn(TextBundle::from_section(
+                    format!(
+                        "{}: {} damage dealt, {} damage taken, {} kills, {} lost",
+                        stats.name,
+                        stats.damage_dealt,
+                        stats.damage_taken,
+                        stats.kills,
+                        stats.characters_lost
+                    ),
+                    text_style(24.0),
+                ));
+            }
+            for (label, is_quit) in [("Lobby", false), ("Quit", true)] {
+                let mut button = parent.spawn(ButtonBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(150.0), Val::Px(65.0)),
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        ..default()
+                    },
+                    background_color: NORMAL_BUTTON.into(),
+                    ..default()
+                });
+                if is_quit {
+                    button.insert(QuitButton);
+                } else {
+                    button.insert(ReturnToLobbyButton);
+                }
+                button.with_children(|parent| {
+                    parent.spawn(TextBundle::from_section(label, text_style(46.0)));
+                });
+            }
+        });
+}
+
+fn update_results(
+    mut interaction_query: Query<
+        (
+            &Interaction,
+            &mut BackgroundColor,
+            Option<&ReturnToLobbyButton>,
+            Option<&QuitButton>,
+        ),
+        Changed<Interaction>,
+    >,
+    mut client: ResMut<RenetClient>,
+    mut app_state: ResMut<State<ClientState>>,
+    mut app_exit_events: EventWriter<AppExit>,
+) {
+    for (interaction, mut background_color, return_to_lobby, quit) in &mut interaction_query {
+        match interaction {
+            Interaction::Clicked => {
+                *background_color = PRESSED_BUTTON.into();
+                if return_to_lobby.is_some() {
+                    let message = encode(&ClientMessage::ReturnToLobby);
+                    client.send_message(DefaultChannel::Reliable, message);
+                    app_state.set(ClientState::Connected).unwrap();
+                    return;
+                } else if quit.is_some() {
+                    client.disconnect();
+                    app_exit_events.send(AppExit);
+                }
+            }
+            Interaction::Hovered => {
+                *background_color = HOVERED_BUTTON.into();
+            }
+            Interaction::None => {
+                *background_color = NORMAL_BUTTON.into();
+            }
+        }
+    }
+}
+
+fn exit_results(mut commands: Commands, query: Query<Entity, With<ResultsScreen>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Chat lines kept for scrolling back
+const MAX_CHAT_LINES: usize = 100;
+
+/// Chat lines the panel shows at once
+const VISIBLE_CHAT_LINES: usize = 10;
+
+/// Everything said in the chat, newest last
+#[derive(Default, Resource)]
+pub struct ChatLog {
+    lines: VecDeque<String>,
+    /// Lines scrolled back from the newest one
+    scroll: usize,
+    /// The mouse is over the chat panel, so the mouse wheel scrolls it instead of zooming
+    pub hovered: bool,
+}
+
+impl ChatLog {
+    pub fn push(&mut self, line: String) {
+        if self.lines.len() == MAX_CHAT_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+        // keeps showing the same lines while scrolled back
+        if self.scroll > 0 {
+            self.scroll = (self.scroll + 1).min(self.max_scroll());
+        }
+    }
+
+    fn max_scroll(&self) -> usize {
+        self.lines.len().saturating_sub(VISIBLE_CHAT_LINES)
+    }
+
+    fn visible_lines(&self) -> impl Iterator<Item = &String> {
+        let end = self.lines.len() - self.scroll;
+        self.lines
+            .range(end.saturating_sub(VISIBLE_CHAT_LINES)..end)
+    }
+}
+
+/// What the player is typing, Enter starts and sends a message, Escape drops it
+#[derive(Default, Resource)]
+pub struct ChatInput {
+    text: String,
+    /// Keys go into the chat, the other controls ignore them meanwhile
+    pub typing: bool,
+}
+
+#[derive(Component)]
+struct ChatPanel;
+
+#[derive(Component)]
+struct ChatText;
+
+#[derive(Component)]
+struct ChatInputText;
+
+fn setup_chat(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/Overseer.otf"),
+        font_size: 20.0,
+        color: Color::WHITE,
+    };
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(10.0),
+                    bottom: Val::Px(10.0),
+                    ..default()
+                },
+                size: Size::new(Val::Px(420.0), Val::Auto),
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(6.0)),
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+            ..default()
+        })
+        .insert(Interaction::default())
+        .insert(ChatPanel)
+        .insert(Name::from("Chat Panel"))
+        .with_children(|parent| {
+            parent
+                .spawn(
+                    TextBundle::from_section("", text_style.clone()).with_style(Style {
+                        max_size: Size::new(Val::Px(408.0), Val::Undefined),
+                        ..default()
+                    }),
+                )
+                .insert(ChatText);
+            parent
+                .spawn(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        color: Color::GRAY,
+                        ..text_style
+                    },
+                ))
+                .insert(ChatInputText);
+        });
+}
+
+/// Turns what the player typed into a chat message, `/w <player> <text>` whispers to a player
+/// given by name or id and `/t <text>` goes to the team
+fn parse_chat_input(
+    input: &str,
+    player_id: impl Fn(&str) -> Option<u64>,
+) -> Result<ChatMessage, String> {
+    let message = if let Some(rest) = input.strip_prefix("/w ") {
+        let (player, text) = rest
+            .trim_start()
+            .split_once(' ')
+            .ok_or_else(|| "Whisper with /w <player> <text>".to_string())?;
+        let id = player
+            .parse()
+            .ok()
+            .or_else(|| player_id(player))
+            .ok_or_else(|| format!("There is no player {}", player))?;
+        ChatMessage::Private(id, text.to_string())
+    } else if let Some(text) = input.strip_prefix("/t ") {
+        ChatMessage::Team(text.to_string())
+    } else {
+        ChatMessage::Public(input.to_string())
+    };
+    // the server checks it again, this only saves the round trip
+    check_chat_text(message.text()).map_err(|error| error.to_string())?;
+    Ok(message)
+}
+
+fn type_chat(
+    key_input: Res<Input<KeyCode>>,
+    mut received_characters: EventReader<ReceivedCharacter>,
+    mut chat_input: ResMut<ChatInput>,
+    mut chat_log: ResMut<ChatLog>,
+    mut client: ResMut<RenetClient>,
+    players: Res<Players>,
+    name_query: Query<&Name>,
+) {
+    if !chat_input.typing {
+        received_characters.clear();
+        if key_input.just_pressed(KeyCode::Return) {
+            chat_input.typing = true;
+        }
+        return;
+    }
+    if key_input.just_pressed(KeyCode::Escape) {
+        received_characters.clear();
+        *chat_input = ChatInput::default();
+        return;
+    }
+    if key_input.just_pressed(KeyCode::Back) {
+        chat_input.text.pop();
+    }
+    for received in received_characters.iter() {
+        // Enter and Backspace come along as control characters
+        if !received.char.is_control() {
+            chat_input.text.push(received.char);
+        }
+    }
+    if !key_input.just_pressed(KeyCode::Return) {
+        return;
+    }
+    let input = std::mem::take(&mut *chat_input).text;
+    if input.trim().is_empty() {
+        return;
+    }
+    let player_id = |name: &str| {
+        players.players.iter().find_map(|(&id, &entity)| {
+            name_query
+                .get(entity)
+                .ok()
+                .filter(|player_name| player_name.as_str() == name)
+                .map(|_| id)
+        })
+    };
+    match parse_chat_input(&input, player_id) {
+        Ok(message) => client.send_message(CHAT_CHANNEL, encode(&message)),
+        Err(error) => chat_log.push(error),
+    }
+}
+
+fn scroll_chat(
+    mut mouse_wheel_events: EventReader<MouseWheel>,
+    mut chat_log: ResMut<ChatLog>,
+    panel_query: Query<&Interaction, (Changed<Interaction>, With<ChatPanel>)>,
+) {
+    for interaction in &panel_query {
+        chat_log.hovered = interaction != &Interaction::None;
+    }
+    if !chat_log.hovered {
+        mouse_wheel_events.clear();
+        return;
+    }
+    for event in mouse_wheel_events.iter() {
+        let scroll = if event.y > 0.0 {
+            chat_log.scroll + 1
+        } else {
+            chat_log.scroll.saturating_sub(1)
+        };
+        chat_log.scroll = scroll.min(chat_log.max_scroll());
+    }
+}
+
+fn update_chat(
+    chat_log: Res<ChatLog>,
+    chat_input: Res<ChatInput>,
+    mut text_query: Query<&mut Text, (With<ChatText>, Without<ChatInputText>)>,
+    mut input_query: Query<&mut Text, (With<ChatInputText>, Without<ChatText>)>,
+) {
+    if chat_log.is_changed() {
+        for mut text in &mut text_query {
+            text.sections[0].value = chat_log
+                .visible_lines()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join("\n");
+        }
+    }
+    if chat_input.is_changed() {
+        for mut text in &mut input_query {
+            text.sections[0].value = if chat_input.typing {
+                format!("> {}_", chat_input.text)
+            } else {
+                "Press Enter to chat".to_string()
+            };
+        }
+    }
+}
@@ -1,12 +1,17 @@
 use bevy::prelude::*;
-use fallout_equestria_tactics::resources::LevelName;
+use fallout_equestria_tactics::{
+    map::{HexLayout, Map},
+    resources::LevelName,
+};
 
 pub struct InitPlugin;
 
 impl Plugin for InitPlugin {
     fn build(&self, app: &mut App) {
         app
-        .insert_resource(LevelName::default());
+        .insert_resource(LevelName::default())
+        .insert_resource(HexLayout::default())
+        .insert_resource(Map::new());
         info!("InitPlugin has been loaded");
     }
 }
\ No newline at end of file
@@ -0,0 +1,193 @@
+use bevy::prelude::*;
+use bevy_renet::renet::{DefaultChannel, RenetClient};
+use fallout_equestria_tactics::{
+    character::Character,
+    common::{ServerEntity, Special},
+    inventory::{carry_capacity, find_item, EquipmentSlot, Inventory, Item},
+    messages::{encode, ClientMessage},
+};
+
+use crate::{common::ClientState, gui_plugin::ChatInput, picking_plugin::SelectedCharacter};
+
+/// Shows the inventory of the selected character, toggled with I
+///
+/// Clicking an item uses consumables and equips or unequips weapons and armour
+pub struct InventoryPlugin;
+
+impl Plugin for InventoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(InventoryOpen(false))
+            .add_system(toggle_inventory)
+            .add_system(update_inventory_panel.after(toggle_inventory))
+            .add_system(handle_item_buttons)
+            .add_system_set(SystemSet::on_exit(ClientState::Acting).with_system(close_inventory));
+        info!("InventoryPlugin has been loaded");
+    }
+}
+
+const ITEM_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
+const HOVERED_ITEM_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
+
+#[derive(Resource)]
+struct InventoryOpen(bool);
+
+#[derive(Component)]
+struct InventoryPanel;
+
+/// What clicking the item button asks the server to do
+#[derive(Component)]
+enum ItemButton {
+    Use(String),
+    Equip(String),
+    Unequip(EquipmentSlot),
+}
+
+fn toggle_inventory(
+    key_input: Res<Input<KeyCode>>,
+    chat_input: Res<ChatInput>,
+    app_state: Res<State<ClientState>>,
+    mut open: ResMut<InventoryOpen>,
+) {
+    if app_state.current() == &ClientState::Acting
+        && !chat_input.typing
+        && key_input.just_pressed(KeyCode::I)
+    {
+        open.0 = !open.0;
+    }
+}
+
+fn close_inventory(mut open: ResMut<InventoryOpen>) {
+    open.0 = false;
+}
+
+/// Rebuilds the panel whenever it opens or closes, the selection changes or the server sent a new inventory
+fn update_inventory_panel(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    open: Res<InventoryOpen>,
+    selected: Res<SelectedCharacter>,
+    items: Res<Assets<Item>>,
+    character_query: Query<(&Inventory, &Special), With<Character>>,
+    changed_query: Query<(), Changed<Inventory>>,
+    panel_query: Query<Entity, With<InventoryPanel>>,
+) {
+    if !open.is_changed() && !selected.is_changed() && changed_query.is_empty() {
+        return;
+    }
+    for entity in &panel_query {
+        commands.entity(entity).despawn_recursive();
+    }
+    let (inventory, special) = match selected
+        .0
+        .filter(|_| open.0)
+        .and_then(|entity| character_query.get(entity).ok())
+    {
+        Some(character) => character,
+        None => return,
+    };
+
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/Overseer.otf"),
+        font_size: 24.0,
+        color: Color::WHITE,
+    };
+    let mut entries = Vec::new();
+    for (slot, equipped) in [
+        (EquipmentSlot::Weapon, &inventory.weapon),
+        (EquipmentSlot::Armour, &inventory.armour),
+    ] {
+        if let Some(name) = equipped {
+            entries.push((format!("{} ({:?})", name, slot), ItemButton::Unequip(slot)));
+        }
+    }
+    for name in &inventory.items {
+        let button = match find_item(&items, name).and_then(|item| item.kind.slot()) {
+            Some(_) => ItemButton::Equip(name.clone()),
+            None => ItemButton::Use(name.clone()),
+        };
+        entries.push((name.clone(), button));
+    }
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    right: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    ..default()
+                },
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.7).into(),
+            ..default()
+        })
+        .insert(InventoryPanel)
+        .insert(Name::from("Inventory Panel"))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                format!(
+                    "Saddlebags {}/{}",
+                    inventory.weight(&items),
+                    carry_capacity(special)
+                ),
+                text_style.clone(),
+            ));
+            for (label, button) in entries {
+                parent
+                    .spawn(ButtonBundle {
+                        style: Style {
+                            margin: UiRect::top(Val::Px(4.0)),
+                            padding: UiRect::all(Val::Px(4.0)),
+                            ..default()
+                        },
+                        background_color: ITEM_BUTTON.into(),
+                        ..default()
+                    })
+                    .insert(button)
+                    .with_children(|parent| {
+                        parent.spawn(TextBundle::from_section(label, text_style.clone()));
+                    });
+            }
+        });
+}
+
+fn handle_item_buttons(
+    mut interaction_query: Query<
+        (&Interaction, &ItemButton, &mut BackgroundColor),
+        Changed<Interaction>,
+    >,
+    selected: Res<SelectedCharacter>,
+    mut client: ResMut<RenetClient>,
+    character_query: Query<&ServerEntity, With<Character>>,
+) {
+    for (interaction, button, mut background_color) in &mut interaction_query {
+        match interaction {
+            Interaction::Clicked => {
+                let server_entity = match selected
+                    .0
+                    .and_then(|entity| character_query.get(entity).ok())
+                {
+                    Some(server_entity) => server_entity.0,
+                    None => continue,
+                };
+                let message = match button {
+                    ItemButton::Use(name) => ClientMessage::UseItem(server_entity, name.clone()),
+                    ItemButton::Equip(name) => {
+                        ClientMessage::EquipItem(server_entity, name.clone())
+                    }
+                    ItemButton::Unequip(slot) => ClientMessage::UnequipItem(server_entity, *slot),
+                };
+                client.send_message(DefaultChannel::Reliable, encode(&message));
+            }
+            Interaction::Hovered => {
+                *background_color = HOVERED_ITEM_BUTTON.into();
+            }
+            Interaction::None => {
+                *background_color = ITEM_BUTTON.into();
+            }
+        }
+    }
+}
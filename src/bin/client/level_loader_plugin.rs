@@ -3,7 +3,7 @@ use bevy_rapier3d::prelude::RapierColliderHandle;
 use bevy_renet::renet::{DefaultChannel, RenetClient};
 
 use fallout_equestria_tactics::level_loader::*;
-use fallout_equestria_tactics::messages::ClientMessage;
+use fallout_equestria_tactics::messages::{encode, ClientMessage};
 
 use crate::common::ClientState;
 
@@ -16,10 +16,13 @@ impl Plugin for LevelLoaderPlugin {
         app.add_system_set(
             SystemSet::on_update(ClientState::LoadingLevel)
                 .with_system(add_collider)
+                .with_system(build_map)
                 .with_system(check_load_completed),
         );
         app.add_system_set(
-            SystemSet::on_exit(ClientState::LoadingLevel).with_system(notify_server),
+            SystemSet::on_exit(ClientState::LoadingLevel)
+                .with_system(bake_map)
+                .with_system(notify_server),
         );
         info!("LevelLoaderPlugin has been loaded");
     }
@@ -44,6 +47,6 @@ fn check_load_completed(
 
 fn notify_server(mut client: ResMut<RenetClient>) {
     info!("Notifying server");
-    let message = bincode::serialize(&ClientMessage::LevelLoaded).unwrap();
+    let message = encode(&ClientMessage::LevelLoaded);
     client.send_message(DefaultChannel::Reliable, message);
 }
@@ -0,0 +1,434 @@
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_6};
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::{QueryFilter, RapierContext};
+use bevy_renet::renet::{DefaultChannel, RenetClient};
+use fallout_equestria_tactics::{
+    action_points::ActionPoints,
+    character::{Character, Owner, Position},
+    combat::AttackType,
+    line_of_sight::LineOfSight,
+    common::{Race, ServerEntity, Special},
+    flight::{can_fly, Altitude, HEIGHT_PER_AP, MAX_ALTITUDE},
+    map::{AxialCoordinates, HexLayout, HexOrientation, Map},
+    messages::{encode, ClientMessage},
+    pathfinding::Mover,
+    progression::XpLedger,
+    race::{find_race_definition, RaceDefinition},
+    spell::Spell,
+};
+
+use crate::{common::ClientState, gui_plugin::ChatInput};
+
+/// Turns the mouse cursor into hexes and lets the player select and move characters
+pub struct PickingPlugin;
+
+impl Plugin for PickingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<HexHovered>()
+            .add_event::<HexClicked>()
+            .insert_resource(HoveredHex(None))
+            .insert_resource(SelectedCharacter(None))
+            .add_startup_system(spawn_hover_highlight)
+            .add_system(pick_hex)
+            .add_system(update_hover_highlight.after(pick_hex))
+            .add_system(update_line_of_sight.after(select_or_move))
+            .add_system(select_or_move.after(pick_hex))
+            .add_system(update_path_preview.after(select_or_move))
+            .add_system(change_altitude)
+            .add_system(cast_spell)
+            .add_system_set(SystemSet::on_exit(ClientState::Results).with_system(clear_selection));
+        info!("PickingPlugin has been loaded");
+    }
+}
+
+/// The cursor moved onto another hex, None if it left the map
+pub struct HexHovered(pub Option<AxialCoordinates>);
+
+/// A hex of the map was clicked
+pub struct HexClicked(pub AxialCoordinates);
+
+/// Hex below the cursor
+#[derive(Resource)]
+pub struct HoveredHex(pub Option<AxialCoordinates>);
+
+/// Own character that receives move orders
+#[derive(Resource)]
+pub struct SelectedCharacter(pub Option<Entity>);
+
+#[derive(Component)]
+struct HoverHighlight;
+
+#[derive(Component)]
+struct PathPreview;
+
+#[derive(Resource)]
+struct PathPreviewAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+/// Height above the floor at which hex overlays are drawn, so they don't flicker with the floor
+const OVERLAY_OFFSET: f32 = 0.02;
+
+/// Returns a flat hexagon matching the hexes of `layout`, to be placed with [`hex_transform`]
+pub fn hex_mesh(layout: &HexLayout) -> Mesh {
+    Mesh::from(shape::RegularPolygon::new(layout.size, 6))
+}
+
+/// Places a [`hex_mesh`] on the hex at `coordinates`
+pub fn hex_transform(coordinates: AxialCoordinates, layout: &HexLayout) -> Transform {
+    let rotation = match layout.orientation {
+        HexOrientation::Pointy => Quat::from_rotation_x(-FRAC_PI_2),
+        HexOrientation::Flat => {
+            Quat::from_rotation_y(FRAC_PI_6) * Quat::from_rotation_x(-FRAC_PI_2)
+        }
+    };
+    Transform::from_translation(coordinates.to_world(layout) + Vec3::Y * OVERLAY_OFFSET)
+        .with_rotation(rotation)
+}
+
+fn spawn_hover_highlight(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    layout: Res<HexLayout>,
+) {
+    commands
+        .spawn(PbrBundle {
+            mesh: meshes.add(hex_mesh(&layout)),
+            material: materials.add(StandardMaterial {
+                base_color: Color::rgba(1.0, 1.0, 1.0, 0.35),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            }),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(HoverHighlight)
+        .insert(Name::from("Hover Highlight"));
+
+    commands.insert_resource(PathPreviewAssets {
+        mesh: meshes.add(Mesh::from(shape::UVSphere {
+            radius: 0.12,
+            ..default()
+        })),
+        material: materials.add(StandardMaterial {
+            base_color: Color::rgb(0.35, 0.75, 0.35),
+            unlit: true,
+            ..default()
+        }),
+    });
+}
+
+/// Casts a ray from the cursor into the level and reports the hex it hits
+fn pick_hex(
+    mouse_input: Res<Input<MouseButton>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    windows: Res<Windows>,
+    rapier_context: Res<RapierContext>,
+    layout: Res<HexLayout>,
+    map: Res<Map>,
+    mut hovered: ResMut<HoveredHex>,
+    mut hovered_events: EventWriter<HexHovered>,
+    mut clicked_events: EventWriter<HexClicked>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let mut hovered_hex = None;
+    for (camera, camera_transform) in &cameras {
+        let (ray_pos, ray_dir) = ray_from_mouse_position(window, camera, camera_transform);
+        let hit = rapier_context.cast_ray(ray_pos, ray_dir, f32::MAX, true, QueryFilter::new());
+
+        if let Some((_entity, toi)) = hit {
+            let point = ray_pos + ray_dir * toi;
+            hovered_hex = map
+                .get(AxialCoordinates::from_world(point, &layout))
+                .map(|tile| tile.coordinates);
+        }
+    }
+
+    if hovered_hex != hovered.0 {
+        hovered.0 = hovered_hex;
+        hovered_events.send(HexHovered(hovered_hex));
+    }
+    if mouse_input.just_pressed(MouseButton::Left) {
+        if let Some(coordinates) = hovered_hex {
+            info!("Clicked hex {:?}", coordinates);
+            clicked_events.send(HexClicked(coordinates));
+        }
+    }
+}
+
+fn update_hover_highlight(
+    mut hovered_events: EventReader<HexHovered>,
+    mut query: Query<(&mut Transform, &mut Visibility), With<HoverHighlight>>,
+    layout: Res<HexLayout>,
+) {
+    for HexHovered(hovered) in hovered_events.iter() {
+        for (mut transform, mut visibility) in &mut query {
+            visibility.is_visible = hovered.is_some();
+            if let Some(coordinates) = hovered {
+                *transform = hex_transform(*coordinates, &layout);
+            }
+        }
+    }
+}
+
+/// Tints the hover highlight by whether the selected character can see the hovered hex
+fn update_line_of_sight(
+    hovered: Res<HoveredHex>,
+    selected: Res<SelectedCharacter>,
+    map: Res<Map>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    highlight_query: Query<&Handle<StandardMaterial>, With<HoverHighlight>>,
+    character_query: Query<&Position, With<Character>>,
+) {
+    if !hovered.is_changed() && !selected.is_changed() {
+        return;
+    }
+    let position = selected.0.and_then(|entity| character_query.get(entity).ok());
+    let line_of_sight = match (position, hovered.0) {
+        (Some(position), Some(target)) => map.line_of_sight(position.0, target),
+        _ => LineOfSight::Clear,
+    };
+    let color = match line_of_sight {
+        LineOfSight::Clear => Color::rgba(1.0, 1.0, 1.0, 0.35),
+        LineOfSight::PartialCover => Color::rgba(1.0, 0.8, 0.2, 0.35),
+        LineOfSight::Blocked => Color::rgba(0.9, 0.2, 0.2, 0.35),
+    };
+    for handle in &highlight_query {
+        if let Some(material) = materials.get_mut(handle) {
+            material.base_color = color;
+        }
+    }
+}
+
+/// Selects own characters by clicking their hex, clicking an enemy attacks it
+/// and clicking any other hex moves the selected one there
+fn select_or_move(
+    mut clicked_events: EventReader<HexClicked>,
+    mut selected: ResMut<SelectedCharacter>,
+    mut client: ResMut<RenetClient>,
+    app_state: Res<State<ClientState>>,
+    character_query: Query<(Entity, &Owner, &Position, &ServerEntity), With<Character>>,
+) {
+    for HexClicked(coordinates) in clicked_events.iter() {
+        let clicked_character = character_query
+            .iter()
+            .find(|(_, _, position, _)| position.q == coordinates.q && position.r == coordinates.r);
+        let own_character = clicked_character
+            .filter(|(_, owner, ..)| owner.0 == client.client_id())
+            .map(|(entity, ..)| entity);
+
+        if own_character.is_some() {
+            selected.0 = own_character;
+            info!("Selected {:?}", own_character);
+        } else if let Some(selected_character) = selected.0 {
+            if app_state.current() != &ClientState::Acting {
+                continue;
+            }
+            let (_, _, position, server_entity) = match character_query.get(selected_character) {
+                Ok(character) => character,
+                Err(_) => continue,
+            };
+            let message = match clicked_character {
+                Some((_, _, enemy_position, enemy)) => {
+                    let attack_type = if position.distance(enemy_position.0) <= 1 {
+                        AttackType::Melee
+                    } else {
+                        AttackType::Ranged
+                    };
+                    ClientMessage::Attack(server_entity.0, enemy.0, attack_type)
+                }
+                None => ClientMessage::MoveCharacter(server_entity.0, *coordinates),
+            };
+            client.send_message(DefaultChannel::Reliable, encode(&message));
+        }
+    }
+}
+
+/// Shows the path the selected character would take to the hovered hex
+fn update_path_preview(
+    mut commands: Commands,
+    hovered: Res<HoveredHex>,
+    selected: Res<SelectedCharacter>,
+    map: Res<Map>,
+    layout: Res<HexLayout>,
+    preview_assets: Res<PathPreviewAssets>,
+    preview_query: Query<Entity, With<PathPreview>>,
+    character_query: Query<
+        (&Position, &Altitude, &ActionPoints, &Special, &ServerEntity),
+        With<Character>,
+    >,
+    changed_query: Query<(), (With<Character>, Or<(Changed<Position>, Changed<Altitude>)>)>,
+) {
+    if !hovered.is_changed() && !selected.is_changed() && changed_query.is_empty() {
+        return;
+    }
+    for entity in &preview_query {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let (target, character) = match (hovered.0, selected.0) {
+        (Some(target), Some(character)) => (target, character),
+        _ => return,
+    };
+    let (position, altitude, action_points, special, server_entity) =
+        match character_query.get(character) {
+            Ok(character) => character,
+            Err(_) => return,
+        };
+    // the map knows characters by their server entity
+    let path = map
+        .reachable(
+            position.0,
+            &Mover::new(server_entity.0).with_altitude(*altitude),
+            action_points.current as f32,
+            special.endurance as u32,
+        )
+        .path_to(target);
+
+    if let Some(path) = path {
+        for coordinates in path.tiles.into_iter().skip(1) {
+            commands
+                .spawn(PbrBundle {
+                    mesh: preview_assets.mesh.clone(),
+                    material: preview_assets.material.clone(),
+                    transform: Transform::from_translation(
+                        coordinates.to_world(&layout)
+                            + Vec3::Y * (0.2 + altitude.0 as f32 * layout.elevation_step),
+                    ),
+                    ..default()
+                })
+                .insert(PathPreview);
+        }
+    }
+}
+
+/// Lets the selected character rise with E and sink with Q if its race can fly, as far as a single AP allows
+fn change_altitude(
+    key_input: Res<Input<KeyCode>>,
+    chat_input: Res<ChatInput>,
+    selected: Res<SelectedCharacter>,
+    mut client: ResMut<RenetClient>,
+    app_state: Res<State<ClientState>>,
+    race_definitions: Res<Assets<RaceDefinition>>,
+    character_query: Query<(&Race, &Altitude, &ServerEntity), With<Character>>,
+) {
+    if app_state.current() != &ClientState::Acting || chat_input.typing {
+        return;
+    }
+    let change = if key_input.just_pressed(KeyCode::E) {
+        HEIGHT_PER_AP
+    } else if key_input.just_pressed(KeyCode::Q) {
+        -HEIGHT_PER_AP
+    } else {
+        return;
+    };
+    let (race, altitude, server_entity) = match selected
+        .0
+        .and_then(|entity| character_query.get(entity).ok())
+    {
+        Some(character) => character,
+        None => return,
+    };
+    if !find_race_definition(&race_definitions, *race).map_or(false, can_fly) {
+        return;
+    }
+    let target = Altitude((altitude.0 + change).clamp(0, MAX_ALTITUDE));
+    if target != *altitude {
+        let message = encode(&ClientMessage::ChangeAltitude(server_entity.0, target));
+        client.send_message(DefaultChannel::Reliable, message);
+    }
+}
+
+/// Keys 1 to 9 cast the spells the selected character can cast, in alphabetical order, at the hovered hex
+fn cast_spell(
+    key_input: Res<Input<KeyCode>>,
+    chat_input: Res<ChatInput>,
+    selected: Res<SelectedCharacter>,
+    hovered: Res<HoveredHex>,
+    spells: Res<Assets<Spell>>,
+    race_definitions: Res<Assets<RaceDefinition>>,
+    mut client: ResMut<RenetClient>,
+    app_state: Res<State<ClientState>>,
+    character_query: Query<(&Race, &ServerEntity, &XpLedger), With<Character>>,
+) {
+    if app_state.current() != &ClientState::Acting || chat_input.typing {
+        return;
+    }
+    const SPELL_KEYS: [KeyCode; 9] = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+    let index = match SPELL_KEYS
+        .iter()
+        .position(|&key| key_input.just_pressed(key))
+    {
+        Some(index) => index,
+        None => return,
+    };
+    let (target, (race, server_entity, ledger)) = match (
+        hovered.0,
+        selected
+            .0
+            .and_then(|entity| character_query.get(entity).ok()),
+    ) {
+        (Some(target), Some(character)) => (target, character),
+        _ => return,
+    };
+    let definition = match find_race_definition(&race_definitions, *race) {
+        Some(definition) => definition,
+        None => return,
+    };
+    let mut known: Vec<&Spell> = spells
+        .iter()
+        .map(|(_, spell)| spell)
+        .filter(|spell| spell.can_be_cast_by(definition, ledger))
+        .collect();
+    known.sort_by(|a, b| a.name.cmp(&b.name));
+    if let Some(spell) = known.get(index) {
+        let message = encode(&ClientMessage::CastSpell(
+            server_entity.0,
+            spell.name.clone(),
+            target,
+        ));
+        client.send_message(DefaultChannel::Reliable, message);
+    }
+}
+
+fn clear_selection(mut selected: ResMut<SelectedCharacter>) {
+    selected.0 = None;
+}
+
+fn ray_from_mouse_position(
+    window: &Window,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+) -> (Vec3, Vec3) {
+    let mouse_position = window.cursor_position().unwrap_or(Vec2::new(0.0, 0.0));
+
+    let x = 2.0 * (mouse_position.x / window.width() as f32) - 1.0;
+    let y = 2.0 * (mouse_position.y / window.height() as f32) - 1.0;
+
+    let camera_inverse_matrix =
+        camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+    let near = camera_inverse_matrix * Vec3::new(x, y, -1.0).extend(1.0);
+    let far = camera_inverse_matrix * Vec3::new(x, y, 1.0).extend(1.0);
+
+    let near = near.truncate() / near.w;
+    let far = far.truncate() / far.w;
+    let dir: Vec3 = far - near;
+    (near, dir)
+}
@@ -0,0 +1,89 @@
+use bevy::prelude::*;
+use bevy_renet::renet::{DefaultChannel, RenetClient};
+use fallout_equestria_tactics::{
+    map::{AxialCoordinates, HexLayout},
+    messages::{encode, ClientMessage},
+};
+
+use crate::{
+    common::ClientState,
+    picking_plugin::{hex_mesh, hex_transform, HexClicked},
+};
+
+/// Shows the own spawn zone and places characters on it while it's our placement turn
+pub struct PlacementPlugin;
+
+impl Plugin for PlacementPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SpawnZone(Vec::new()))
+            .add_system_set(SystemSet::on_enter(ClientState::Placing).with_system(show_spawn_zone))
+            .add_system_set(SystemSet::on_update(ClientState::Placing).with_system(place_character))
+            .add_system_set(SystemSet::on_exit(ClientState::Placing).with_system(hide_spawn_zone));
+        info!("PlacementPlugin loaded");
+    }
+}
+
+/// Tiles the server allows us to place our characters on
+#[derive(Resource)]
+pub struct SpawnZone(pub Vec<AxialCoordinates>);
+
+impl SpawnZone {
+    pub fn contains(&self, coordinates: AxialCoordinates) -> bool {
+        self.0
+            .iter()
+            .any(|c| c.q == coordinates.q && c.r == coordinates.r)
+    }
+}
+
+#[derive(Component)]
+struct SpawnZoneOverlay;
+
+fn show_spawn_zone(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    layout: Res<HexLayout>,
+    spawn_zone: Res<SpawnZone>,
+) {
+    if spawn_zone.0.is_empty() {
+        warn!("It's our placement turn, but we have no spawn zone");
+    }
+    let mesh = meshes.add(hex_mesh(&layout));
+    let material = materials.add(StandardMaterial {
+        base_color: Color::rgba(0.35, 0.75, 0.35, 0.35),
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        ..default()
+    });
+    for &coordinates in &spawn_zone.0 {
+        commands
+            .spawn(PbrBundle {
+                mesh: mesh.clone(),
+                material: material.clone(),
+                transform: hex_transform(coordinates, &layout),
+                ..default()
+            })
+            .insert(SpawnZoneOverlay);
+    }
+}
+
+/// Asks the server to place the next character on the clicked hex of the spawn zone
+fn place_character(
+    mut clicked_events: EventReader<HexClicked>,
+    mut client: ResMut<RenetClient>,
+    spawn_zone: Res<SpawnZone>,
+) {
+    for HexClicked(coordinates) in clicked_events.iter() {
+        if !spawn_zone.contains(*coordinates) {
+            continue;
+        }
+        let message = encode(&ClientMessage::PlaceCharacter(*coordinates));
+        client.send_message(DefaultChannel::Reliable, message);
+    }
+}
+
+fn hide_spawn_zone(mut commands: Commands, query: Query<Entity, With<SpawnZoneOverlay>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,199 @@
+use bevy::prelude::*;
+use bevy_renet::renet::{DefaultChannel, RenetClient};
+use fallout_equestria_tactics::{
+    character::Character,
+    common::{Race, ServerEntity},
+    messages::{encode, ClientMessage},
+    progression::{SkillTree, XpLedger},
+    race::{find_race_definition, RaceDefinition},
+};
+
+use crate::{common::ClientState, gui_plugin::ChatInput, picking_plugin::SelectedCharacter};
+
+/// Shows the skill trees of the selected character, toggled with K
+///
+/// Clicking a skill that can be learned spends the XP on it
+pub struct SkillsPlugin;
+
+impl Plugin for SkillsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SkillsOpen(false))
+            .add_system(toggle_skills)
+            .add_system(update_skills_panel.after(toggle_skills))
+            .add_system(handle_skill_buttons)
+            .add_system_set(SystemSet::on_exit(ClientState::Results).with_system(close_skills));
+        info!("SkillsPlugin has been loaded");
+    }
+}
+
+const SKILL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
+const HOVERED_SKILL_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
+const LEARNED_SKILL: Color = Color::rgb(0.35, 0.75, 0.35);
+const LOCKED_SKILL: Color = Color::rgb(0.5, 0.5, 0.5);
+
+#[derive(Resource)]
+struct SkillsOpen(bool);
+
+#[derive(Component)]
+struct SkillsPanel;
+
+/// Skill the button learns when clicked
+#[derive(Component)]
+struct SkillButton(String);
+
+fn toggle_skills(
+    key_input: Res<Input<KeyCode>>,
+    chat_input: Res<ChatInput>,
+    mut open: ResMut<SkillsOpen>,
+) {
+    if !chat_input.typing && key_input.just_pressed(KeyCode::K) {
+        open.0 = !open.0;
+    }
+}
+
+fn close_skills(mut open: ResMut<SkillsOpen>) {
+    open.0 = false;
+}
+
+/// Rebuilds the panel whenever it opens or closes, the selection changes or the server sent new XP
+fn update_skills_panel(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    open: Res<SkillsOpen>,
+    selected: Res<SelectedCharacter>,
+    skill_trees: Res<Assets<SkillTree>>,
+    race_definitions: Res<Assets<RaceDefinition>>,
+    character_query: Query<(&Race, &XpLedger), With<Character>>,
+    changed_query: Query<(), Changed<XpLedger>>,
+    panel_query: Query<Entity, With<SkillsPanel>>,
+) {
+    if !open.is_changed() && !selected.is_changed() && changed_query.is_empty() {
+        return;
+    }
+    for entity in &panel_query {
+        commands.entity(entity).despawn_recursive();
+    }
+    let (race, ledger) = match selected
+        .0
+        .filter(|_| open.0)
+        .and_then(|entity| character_query.get(entity).ok())
+    {
+        Some(character) => character,
+        None => return,
+    };
+    let tree_names = find_race_definition(&race_definitions, *race)
+        .map(|definition| definition.skill_trees.clone())
+        .unwrap_or_default();
+    let mut trees: Vec<&SkillTree> = skill_trees
+        .iter()
+        .map(|(_, tree)| tree)
+        .filter(|tree| tree_names.contains(&tree.name))
+        .collect();
+    trees.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/Overseer.otf"),
+        font_size: 24.0,
+        color: Color::WHITE,
+    };
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    ..default()
+                },
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.7).into(),
+            ..default()
+        })
+        .insert(SkillsPanel)
+        .insert(Name::from("Skills Panel"))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                format!("{} XP available", ledger.available()),
+                text_style.clone(),
+            ));
+            for tree in trees {
+                parent.spawn(TextBundle::from_section(
+                    tree.name.clone(),
+                    TextStyle {
+                        font_size: 30.0,
+                        ..text_style.clone()
+                    },
+                ));
+                for skill in &tree.skills {
+                    let learned = ledger.has_learned(&skill.name);
+                    let unlocked = skill
+                        .requires
+                        .iter()
+                        .all(|required| ledger.has_learned(required));
+                    let color = if learned {
+                        LEARNED_SKILL
+                    } else if unlocked && skill.cost <= ledger.available() {
+                        SKILL_BUTTON
+                    } else {
+                        LOCKED_SKILL
+                    };
+                    parent
+                        .spawn(ButtonBundle {
+                            style: Style {
+                                margin: UiRect::top(Val::Px(4.0)),
+                                padding: UiRect::all(Val::Px(4.0)),
+                                ..default()
+                            },
+                            background_color: color.into(),
+                            ..default()
+                        })
+                        .insert(SkillButton(skill.name.clone()))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(
+                                format!("{} ({} XP)", skill.name, skill.cost),
+                                text_style.clone(),
+                            ));
+                        });
+                }
+            }
+        });
+}
+
+fn handle_skill_buttons(
+    mut interaction_query: Query<
+        (&Interaction, &SkillButton, &mut BackgroundColor),
+        Changed<Interaction>,
+    >,
+    selected: Res<SelectedCharacter>,
+    mut client: ResMut<RenetClient>,
+    character_query: Query<&ServerEntity, With<Character>>,
+) {
+    for (interaction, button, mut background_color) in &mut interaction_query {
+        // learned and locked skills keep their colour, the server refuses them anyway
+        let color = background_color.0;
+        if color == LEARNED_SKILL || color == LOCKED_SKILL {
+            continue;
+        }
+        match interaction {
+            Interaction::Clicked => {
+                if let Some(server_entity) = selected
+                    .0
+                    .and_then(|entity| character_query.get(entity).ok())
+                {
+                    let message =
+                        encode(&ClientMessage::SpendXp(server_entity.0, button.0.clone()));
+                    client.send_message(DefaultChannel::Reliable, message);
+                }
+            }
+            Interaction::Hovered => {
+                *background_color = HOVERED_SKILL_BUTTON.into();
+            }
+            Interaction::None => {
+                *background_color = SKILL_BUTTON.into();
+            }
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use bevy::prelude::*;
+use bevy_renet::renet::{DefaultChannel, RenetClient};
+use fallout_equestria_tactics::{
+    common::{Race, Special},
+    inventory::Item,
+    messages::{encode, ClientMessage},
+    race::RaceDefinition,
+    squad::{Squad, SquadError, SquadRules, SquadRulesHandle},
+};
+
+use crate::common::ClientState;
+
+/// Lets the player build a squad in the lobby, before readying up
+///
+/// Every character gets a race, a point-buy SPECIAL and a loadout, within the loaded [`SquadRules`]
+pub struct SquadBuilderPlugin;
+
+impl Plugin for SquadBuilderPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SquadDraft(Squad::default()))
+            .insert_resource(SquadStatus::Editing)
+            .add_system_set(
+                SystemSet::on_update(ClientState::Connected)
+                    .with_system(update_squad_panel)
+                    .with_system(handle_squad_buttons.before(update_squad_panel)),
+            )
+            .add_system_set(
+                SystemSet::on_exit(ClientState::Connected).with_system(remove_squad_panel),
+            );
+        info!("SquadBuilderPlugin has been loaded");
+    }
+}
+
+const SQUAD_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
+const HOVERED_SQUAD_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
+
+/// The squad as currently built, kept between matches
+#[derive(Resource)]
+struct SquadDraft(Squad);
+
+/// What the server made of the last submitted squad
+#[derive(Debug, Resource)]
+pub enum SquadStatus {
+    Editing,
+    Submitted,
+    Accepted,
+    Rejected(SquadError),
+}
+
+#[derive(Component)]
+struct SquadPanel;
+
+#[derive(Clone, Component)]
+enum SquadButton {
+    CycleRace(usize),
+    /// Character and index of the stat in [`Special::stats`]
+    Raise(usize, usize),
+    Lower(usize, usize),
+    AddItem(usize, String),
+    RemoveItem(usize, usize),
+    Submit,
+}
+
+const RACES: [Race; 3] = [Race::EarthPony, Race::Pegasus, Race::Unicorn];
+
+fn race_name(race: Race) -> &'static str {
+    match race {
+        Race::EarthPony => "Earth Pony",
+        Race::Pegasus => "Pegasus",
+        Race::Unicorn => "Unicorn",
+    }
+}
+
+fn spawn_button(
+    parent: &mut ChildBuilder,
+    label: String,
+    action: SquadButton,
+    text_style: &TextStyle,
+) {
+    parent
+        .spawn(ButtonBundle {
+            style: Style {
+                margin: UiRect::all(Val::Px(2.0)),
+                padding: UiRect::all(Val::Px(2.0)),
+                ..default()
+            },
+            background_color: SQUAD_BUTTON.into(),
+            ..default()
+        })
+        .insert(action)
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(label, text_style.clone()));
+        });
+}
+
+fn status_text(status: &SquadStatus) -> String {
+    match status {
+        SquadStatus::Editing => String::from("Submit your squad before readying up"),
+        SquadStatus::Submitted => String::from("Waiting for the server"),
+        SquadStatus::Accepted => String::from("Squad accepted"),
+        SquadStatus::Rejected(error) => format!("Squad rejected: {:?}", error),
+    }
+}
+
+/// Rebuilds the panel whenever the draft or its status changes
+fn update_squad_panel(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    draft: Res<SquadDraft>,
+    status: Res<SquadStatus>,
+    rules_handle: Res<SquadRulesHandle>,
+    rules: Res<Assets<SquadRules>>,
+    panel_query: Query<Entity, With<SquadPanel>>,
+) {
+    if !draft.is_changed() && !status.is_changed() && !panel_query.is_empty() {
+        return;
+    }
+    for entity in &panel_query {
+        commands.entity(entity).despawn_recursive();
+    }
+    let rules = rules_handle.rules(&rules);
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/Overseer.otf"),
+        font_size: 20.0,
+        color: Color::WHITE,
+    };
+    let row = || NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::Row,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        ..default()
+    };
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(10.0),
+                    top: Val::Px(80.0),
+                    ..default()
+                },
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.7).into(),
+            ..default()
+        })
+        .insert(SquadPanel)
+        .insert(Name::from("Squad Panel"))
+        .with_children(|parent| {
+            parent.spawn(NodeBundle::default()).with_children(|parent| {
+                for (index, sheet) in draft.0.characters.iter().enumerate() {
+                    parent
+                        .spawn(NodeBundle {
+                            style: Style {
+                                flex_direction: FlexDirection::Column,
+                                margin: UiRect::all(Val::Px(6.0)),
+                                ..default()
+                            },
+                            ..default()
+                        })
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(
+                                sheet.name.clone(),
+                                TextStyle {
+                                    font_size: 26.0,
+                                    ..text_style.clone()
+                                },
+                            ));
+                            spawn_button(
+                                parent,
+                                race_name(sheet.race).to_string(),
+                                SquadButton::CycleRace(index),
+                                &text_style,
+                            );
+                            parent.spawn(TextBundle::from_section(
+                                format!(
+                                    "{} points left",
+                                    rules.special_points as i64 - sheet.special.points() as i64
+                                ),
+                                text_style.clone(),
+                            ));
+                            for (stat, value) in sheet.special.stats().iter().enumerate() {
+                                parent.spawn(row()).with_children(|parent| {
+                                    spawn_button(
+                                        parent,
+                                        "-".to_string(),
+                                        SquadButton::Lower(index, stat),
+                                        &text_style,
+                                    );
+                                    parent.spawn(TextBundle::from_section(
+                                        format!("{} {}", Special::NAMES[stat], value),
+                                        text_style.clone(),
+                                    ));
+                                    spawn_button(
+                                        parent,
+                                        "+".to_string(),
+                                        SquadButton::Raise(index, stat),
+                                        &text_style,
+                                    );
+                                });
+                            }
+                            for (slot, item) in sheet.loadout.iter().enumerate() {
+                                spawn_button(
+                                    parent,
+                                    format!("- {}", item),
+                                    SquadButton::RemoveItem(index, slot),
+                                    &text_style,
+                                );
+                            }
+                            if sheet.loadout.len() < rules.max_loadout_items {
+                                for item in &rules.loadout {
+                                    spawn_button(
+                                        parent,
+                                        format!("+ {}", item),
+                                        SquadButton::AddItem(index, item.clone()),
+                                        &text_style,
+                                    );
+                                }
+                            }
+                        });
+                }
+            });
+            parent.spawn(row()).with_children(|parent| {
+                spawn_button(
+                    parent,
+                    "Submit".to_string(),
+                    SquadButton::Submit,
+                    &text_style,
+                );
+                parent.spawn(TextBundle::from_section(
+                    status_text(&status),
+                    text_style.clone(),
+                ));
+            });
+        });
+}
+
+/// Edits the draft within the rules and submits it once it passes the same validation as on the server
+fn handle_squad_buttons(
+    mut interaction_query: Query<
+        (&Interaction, &SquadButton, &mut BackgroundColor),
+        Changed<Interaction>,
+    >,
+    mut draft: ResMut<SquadDraft>,
+    mut status: ResMut<SquadStatus>,
+    mut client: ResMut<RenetClient>,
+    rules_handle: Res<SquadRulesHandle>,
+    rules: Res<Assets<SquadRules>>,
+    items: Res<Assets<Item>>,
+    race_definitions: Res<Assets<RaceDefinition>>,
+) {
+    let rules = rules_handle.rules(&rules);
+    for (interaction, button, mut background_color) in &mut interaction_query {
+        match interaction {
+            Interaction::Clicked => (),
+            Interaction::Hovered => {
+                *background_color = HOVERED_SQUAD_BUTTON.into();
+                continue;
+            }
+            Interaction::None => {
+                *background_color = SQUAD_BUTTON.into();
+                continue;
+            }
+        }
+        if let SquadButton::Submit = button {
+            match rules.validate(&draft.0, &items, &race_definitions) {
+                Ok(()) => {
+                    let message = encode(&ClientMessage::SubmitSquad(draft.0.clone()));
+                    client.send_message(DefaultChannel::Reliable, message);
+                    *status = SquadStatus::Submitted;
+                }
+                Err(error) => *status = SquadStatus::Rejected(error),
+            }
+            continue;
+        }
+        let squad = &mut draft.0;
+        match button.clone() {
+            SquadButton::CycleRace(index) => {
+                let sheet = &mut squad.characters[index];
+                let current = RACES
+                    .iter()
+                    .position(|&race| race == sheet.race)
+                    .unwrap_or(0);
+                sheet.race = RACES[(current + 1) % RACES.len()];
+            }
+            SquadButton::Raise(index, stat) => {
+                let special = &mut squad.characters[index].special;
+                let mut stats = special.stats();
+                if stats[stat] < rules.max_stat && special.points() < rules.special_points {
+                    stats[stat] += 1;
+                    *special = Special::from_stats(stats);
+                }
+            }
+            SquadButton::Lower(index, stat) => {
+                let special = &mut squad.characters[index].special;
+                let mut stats = special.stats();
+                if stats[stat] > rules.min_stat {
+                    stats[stat] -= 1;
+                    *special = Special::from_stats(stats);
+                }
+            }
+            SquadButton::AddItem(index, item) => {
+                let loadout = &mut squad.characters[index].loadout;
+                if loadout.len() < rules.max_loadout_items {
+                    loadout.push(item);
+                }
+            }
+            SquadButton::RemoveItem(index, slot) => {
+                squad.characters[index].loadout.remove(slot);
+            }
+            SquadButton::Submit => unreachable!(),
+        }
+        // the server only knows the squad as it was submitted
+        *status = SquadStatus::Editing;
+    }
+}
+
+fn remove_squad_panel(mut commands: Commands, query: Query<Entity, With<SquadPanel>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
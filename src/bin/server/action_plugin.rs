@@ -0,0 +1,651 @@
+use bevy::prelude::*;
+use bevy_turborand::prelude::*;
+use fallout_equestria_tactics::{
+    action_points::{authorize, Action, ActionError, ActionPoints},
+    character::{Character, Health, Owner, Position},
+    combat::{resolve_attack, AttackResult, AttackType},
+    common::{CurrentPlayer, Race, Special},
+    flight::{altitude_cost, can_fly, Altitude, MAX_ALTITUDE},
+    inventory::{find_item, EquipmentSlot, Inventory, Item, OPEN_INVENTORY_COST, USE_ITEM_COST},
+    line_of_sight::{LineOfSight, OBSTACLE_HEIGHT},
+    map::{AxialCoordinates, Map},
+    messages::ServerMessage,
+    pathfinding::{Mover, Path},
+    progression::{ProgressionHandles, XpLedger, XpReason, XpRules},
+    race::{find_race_definition, RaceDefinition},
+    spell::{find_spell, Spell, SpellResult},
+    stats::MatchStats,
+    visibility::sight_range,
+};
+
+use crate::{replication_plugin::Replication, visibility_plugin::VisibleCharacters};
+
+/// Validates and applies the actions players request for their characters
+pub struct ActionPlugin;
+
+impl Plugin for ActionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<MoveRequest>()
+            .add_event::<AttackRequest>()
+            .add_event::<AltitudeRequest>()
+            .add_event::<CastSpellRequest>()
+            .add_event::<ItemRequest>()
+            .add_event::<CharacterKilled>()
+            .add_system(handle_move_requests)
+            .add_system(handle_altitude_requests)
+            .add_system(handle_attack_requests)
+            .add_system(handle_spell_requests)
+            .add_system(handle_item_requests);
+        info!("ActionPlugin has been loaded");
+    }
+}
+
+/// A client asked to move one of its characters
+pub struct MoveRequest {
+    pub client_id: u64,
+    pub character: Entity,
+    pub target: AxialCoordinates,
+}
+
+/// A client asked one of its Pegasi to rise or sink
+pub struct AltitudeRequest {
+    pub client_id: u64,
+    pub character: Entity,
+    pub altitude: Altitude,
+}
+
+/// A character of `victim` died to an attack or spell of `killer`
+pub struct CharacterKilled {
+    pub killer: u64,
+    pub victim: u64,
+}
+
+/// A client asked one of its characters to attack another character
+pub struct AttackRequest {
+    pub client_id: u64,
+    pub attacker: Entity,
+    pub defender: Entity,
+    pub attack_type: AttackType,
+}
+
+/// A client asked one of its Unicorns to cast a spell at a tile
+pub struct CastSpellRequest {
+    pub client_id: u64,
+    pub caster: Entity,
+    pub spell: String,
+    pub target: AxialCoordinates,
+}
+
+/// A client asked one of its characters to do something with its inventory
+pub struct ItemRequest {
+    pub client_id: u64,
+    pub character: Entity,
+    pub action: ItemAction,
+}
+
+pub enum ItemAction {
+    Use(String),
+    Equip(String),
+    Unequip(EquipmentSlot),
+}
+
+/// Books the XP `awards` for `character` and tells its owner about the new total
+fn award_xp(
+    server: &mut Replication,
+    ledger_query: &mut Query<&mut XpLedger>,
+    character: Entity,
+    owner: u64,
+    awards: &[(XpReason, u32)],
+) {
+    let mut ledger = match ledger_query.get_mut(character) {
+        Ok(ledger) => ledger,
+        Err(_) => return,
+    };
+    for &(reason, amount) in awards {
+        ledger.award(reason, amount);
+    }
+    server.send(
+        owner,
+        &ServerMessage::ExperienceChanged(character, ledger.clone()),
+    );
+}
+
+/// Tells `client_id` why the action for `character` was refused
+pub(crate) fn reject(
+    server: &mut Replication,
+    client_id: u64,
+    character: Entity,
+    error: ActionError,
+) {
+    info!(
+        "Rejecting action of {} for {:?}: {:?}",
+        client_id, character, error
+    );
+    server.send(client_id, &ServerMessage::ActionRejected(character, error));
+}
+
+fn handle_move_requests(
+    mut move_requests: EventReader<MoveRequest>,
+    mut server: Replication,
+    mut map: ResMut<Map>,
+    visible_characters: Res<VisibleCharacters>,
+    current_player_query: Query<&CurrentPlayer>,
+    mut character_query: Query<
+        (
+            &Owner,
+            &Special,
+            &Altitude,
+            &mut Position,
+            &mut ActionPoints,
+        ),
+        With<Character>,
+    >,
+) {
+    let current_player = current_player_query.iter().next().map(|c| c.0);
+    for request in move_requests.iter() {
+        let (owner, special, altitude, mut position, mut action_points) =
+            match character_query.get_mut(request.character) {
+                Ok(character) => character,
+                Err(_) => {
+                    reject(
+                        &mut server,
+                        request.client_id,
+                        request.character,
+                        ActionError::UnknownCharacter,
+                    );
+                    continue;
+                }
+            };
+
+        let path = authorize(request.client_id, current_player, owner.0).and_then(|_| {
+            // standing still isn't a move, it would cost AP for nothing
+            if request.target.distance(position.0) == 0 {
+                return Err(ActionError::InvalidTarget);
+            }
+            // movement is limited by AP and by Endurance in tiles
+            map.reachable(
+                position.0,
+                &Mover::new(request.character).with_altitude(*altitude),
+                action_points.current as f32,
+                special.endurance as u32,
+            )
+            .path_to(request.target)
+            .ok_or(ActionError::Unreachable)
+        });
+        let path = match path.and_then(|path| {
+            action_points
+                .spend(Action::Move, path.cost.ceil() as u32)
+                .map(|_| path)
+        }) {
+            Ok(path) => path,
+            Err(error) => {
+                reject(&mut server, request.client_id, request.character, error);
+                continue;
+            }
+        };
+
+        let target = *path.tiles.last().unwrap();
+        let owner = owner.0;
+        map.vacate(position.0);
+        map.occupy(target, request.character);
+        position.0 = target;
+        let action_points = *action_points;
+        info!("Moving {:?} to {:?}", request.character, target);
+
+        // whoever sees the character before it moves, sees it walk away until it's out of their sight,
+        // the cost would tell how far it went from there
+        let (characters, owners) = ([request.character], [owner]);
+        for client_id in visible_characters.observers(&characters, &owners) {
+            let path = if client_id == owner {
+                path.clone()
+            } else {
+                let eyes: Vec<(AxialCoordinates, i32)> = character_query
+                    .iter()
+                    .filter(|(observer, ..)| observer.0 == client_id)
+                    .map(|(_, special, _, position, _)| (position.0, sight_range(special)))
+                    .collect();
+                let tiles = map.visible_path(&path.tiles, &eyes);
+                Path { tiles, cost: 0.0 }
+            };
+            server.send(
+                client_id,
+                &ServerMessage::CharacterMoved(request.character, path),
+            );
+        }
+        visible_characters.send(
+            &mut server,
+            &characters,
+            &owners,
+            &ServerMessage::ActionPointsChanged(request.character, action_points),
+        );
+    }
+}
+
+fn handle_altitude_requests(
+    mut altitude_requests: EventReader<AltitudeRequest>,
+    mut server: Replication,
+    map: Res<Map>,
+    visible_characters: Res<VisibleCharacters>,
+    race_definitions: Res<Assets<RaceDefinition>>,
+    current_player_query: Query<&CurrentPlayer>,
+    mut character_query: Query<
+        (&Owner, &Race, &Position, &mut Altitude, &mut ActionPoints),
+        With<Character>,
+    >,
+) {
+    let current_player = current_player_query.iter().next().map(|c| c.0);
+    for request in altitude_requests.iter() {
+        let (owner, race, position, mut altitude, mut action_points) =
+            match character_query.get_mut(request.character) {
+                Ok(character) => character,
+                Err(_) => {
+                    reject(
+                        &mut server,
+                        request.client_id,
+                        request.character,
+                        ActionError::UnknownCharacter,
+                    );
+                    continue;
+                }
+            };
+
+        let result = authorize(request.client_id, current_player, owner.0).and_then(|_| {
+            let can_fly = find_race_definition(&race_definitions, *race).map_or(false, can_fly);
+            if !can_fly || !(0..=MAX_ALTITUDE).contains(&request.altitude.0) {
+                Err(ActionError::CannotFly)
+            } else if map.get(position.0).map_or(true, |tile| {
+                // obstacles below have to be cleared, not only the ground
+                tile.movement_cost().is_none() && (request.altitude.0 as f32) < OBSTACLE_HEIGHT
+            }) {
+                Err(ActionError::CannotLand)
+            } else {
+                action_points.spend(Action::Fly, altitude_cost(*altitude, request.altitude))
+            }
+        });
+        if let Err(error) = result {
+            reject(&mut server, request.client_id, request.character, error);
+            continue;
+        }
+
+        *altitude = request.altitude;
+        info!("{:?} flies at altitude {}", request.character, altitude.0);
+        let (characters, owners) = ([request.character], [owner.0]);
+        visible_characters.send(
+            &mut server,
+            &characters,
+            &owners,
+            &ServerMessage::AltitudeChanged(request.character, *altitude),
+        );
+        visible_characters.send(
+            &mut server,
+            &characters,
+            &owners,
+            &ServerMessage::ActionPointsChanged(request.character, *action_points),
+        );
+    }
+}
+
+fn handle_attack_requests(
+    mut commands: Commands,
+    mut attack_requests: EventReader<AttackRequest>,
+    mut server: Replication,
+    mut map: ResMut<Map>,
+    mut global_rng: ResMut<GlobalRng>,
+    visible_characters: Res<VisibleCharacters>,
+    mut stats: ResMut<MatchStats>,
+    items: Res<Assets<Item>>,
+    xp_rules: Res<Assets<XpRules>>,
+    progression: Res<ProgressionHandles>,
+    current_player_query: Query<&CurrentPlayer>,
+    mut ledger_query: Query<&mut XpLedger>,
+    mut kills: EventWriter<CharacterKilled>,
+    mut character_query: Query<
+        (
+            &Owner,
+            &Special,
+            &Position,
+            &Inventory,
+            &mut ActionPoints,
+            &mut Health,
+        ),
+        With<Character>,
+    >,
+) {
+    let current_player = current_player_query.iter().next().map(|c| c.0);
+    for request in attack_requests.iter() {
+        let [attacker, defender] =
+            match character_query.get_many_mut([request.attacker, request.defender]) {
+                Ok(characters) => characters,
+                Err(_) => {
+                    let error = if request.attacker == request.defender {
+                        ActionError::InvalidTarget
+                    } else {
+                        ActionError::UnknownCharacter
+                    };
+                    reject(&mut server, request.client_id, request.attacker, error);
+                    continue;
+                }
+            };
+        let (owner, special, position, inventory, mut action_points, _) = attacker;
+        let (
+            defender_owner,
+            defender_special,
+            defender_position,
+            defender_inventory,
+            _,
+            mut health,
+        ) = defender;
+
+        let distance = position.distance(defender_position.0);
+        let cover = map.line_of_sight(position.0, defender_position.0);
+        let outcome = authorize(request.client_id, current_player, owner.0)
+            .and_then(|_| {
+                if defender_owner.0 == owner.0 || !health.is_alive() {
+                    Err(ActionError::InvalidTarget)
+                } else if distance > request.attack_type.range() {
+                    Err(ActionError::OutOfRange)
+                } else if cover == LineOfSight::Blocked
+                    || !visible_characters.sees(request.client_id, request.defender)
+                {
+                    Err(ActionError::NoLineOfSight)
+                } else {
+                    action_points.spend(Action::Attack, request.attack_type.cost())
+                }
+            })
+            .and_then(|_| {
+                resolve_attack(
+                    global_rng.get_mut(),
+                    special,
+                    defender_special,
+                    request.attack_type,
+                    distance,
+                    cover,
+                )
+            })
+            .map(|outcome| {
+                outcome.with_equipment(
+                    inventory.damage_bonus(&items, request.attack_type),
+                    defender_inventory.protection(&items),
+                )
+            });
+        let outcome = match outcome {
+            Ok(outcome) => outcome,
+            Err(error) => {
+                reject(&mut server, request.client_id, request.attacker, error);
+                continue;
+            }
+        };
+
+        let before = health.current;
+        health.take_damage(outcome.damage());
+        // overkill isn't worth anything, same as with spells
+        let damage = before.saturating_sub(health.current);
+        let result = AttackResult {
+            attacker: request.attacker,
+            defender: request.defender,
+            attack_type: request.attack_type,
+            outcome,
+            health: *health,
+        };
+        info!("Attack resolved: {:?}", result);
+        stats.record_attack(owner.0, defender_owner.0, damage, result.is_lethal());
+        if result.is_lethal() {
+            info!("{:?} died", request.defender);
+            map.vacate(defender_position.0);
+            commands.entity(request.defender).despawn();
+            kills.send(CharacterKilled {
+                killer: owner.0,
+                victim: defender_owner.0,
+            });
+        }
+        let rules = progression.rules(&xp_rules);
+        award_xp(
+            &mut server,
+            &mut ledger_query,
+            request.attacker,
+            owner.0,
+            &[
+                (XpReason::Damage, damage * rules.damage),
+                (XpReason::Kill, result.is_lethal() as u32 * rules.kill),
+            ],
+        );
+
+        let (characters, owners) = (
+            [request.attacker, request.defender],
+            [owner.0, defender_owner.0],
+        );
+        visible_characters.send(
+            &mut server,
+            &characters,
+            &owners,
+            &ServerMessage::AttackResolved(result),
+        );
+        visible_characters.send(
+            &mut server,
+            &characters[..1],
+            &owners[..1],
+            &ServerMessage::ActionPointsChanged(request.attacker, *action_points),
+        );
+    }
+}
+
+fn handle_spell_requests(
+    mut commands: Commands,
+    mut spell_requests: EventReader<CastSpellRequest>,
+    mut server: Replication,
+    mut map: ResMut<Map>,
+    spells: Res<Assets<Spell>>,
+    race_definitions: Res<Assets<RaceDefinition>>,
+    visible_characters: Res<VisibleCharacters>,
+    mut stats: ResMut<MatchStats>,
+    xp_rules: Res<Assets<XpRules>>,
+    progression: Res<ProgressionHandles>,
+    current_player_query: Query<&CurrentPlayer>,
+    mut ledger_query: Query<&mut XpLedger>,
+    mut kills: EventWriter<CharacterKilled>,
+    mut character_query: Query<
+        (&Owner, &Race, &Position, &mut ActionPoints, &mut Health),
+        With<Character>,
+    >,
+) {
+    let current_player = current_player_query.iter().next().map(|c| c.0);
+    for request in spell_requests.iter() {
+        let (owner, race, position, mut action_points, _) =
+            match character_query.get_mut(request.caster) {
+                Ok(character) => character,
+                Err(_) => {
+                    reject(
+                        &mut server,
+                        request.client_id,
+                        request.caster,
+                        ActionError::UnknownCharacter,
+                    );
+                    continue;
+                }
+            };
+        let (owner, position) = (owner.0, position.0);
+
+        let spell = authorize(request.client_id, current_player, owner).and_then(|_| {
+            let spell = find_spell(&spells, &request.spell).ok_or(ActionError::UnknownSpell)?;
+            let can_cast = match (
+                find_race_definition(&race_definitions, *race),
+                ledger_query.get(request.caster),
+            ) {
+                (Some(definition), Ok(ledger)) => spell.can_be_cast_by(definition, ledger),
+                _ => false,
+            };
+            if !can_cast {
+                Err(ActionError::CannotCast)
+            } else if map.get(request.target).is_none() {
+                Err(ActionError::InvalidTarget)
+            } else if position.distance(request.target) > spell.range {
+                Err(ActionError::OutOfRange)
+            } else if map.line_of_sight(position, request.target) == LineOfSight::Blocked {
+                Err(ActionError::NoLineOfSight)
+            } else {
+                action_points
+                    .spend(Action::CastSpell, spell.ap_cost)
+                    .map(|_| spell)
+            }
+        });
+        let spell = match spell {
+            Ok(spell) => spell,
+            Err(error) => {
+                reject(&mut server, request.client_id, request.caster, error);
+                continue;
+            }
+        };
+        let action_points = *action_points;
+
+        let mut result = SpellResult {
+            caster: request.caster,
+            spell: spell.name.clone(),
+            target: request.target,
+            affected: Vec::new(),
+        };
+        let rules = progression.rules(&xp_rules);
+        let mut awards = vec![(XpReason::SpellCast, rules.spell_cast)];
+        let mut characters = vec![request.caster];
+        let mut owners = vec![owner];
+        let targets: Vec<Entity> = spell
+            .area
+            .tiles(position, request.target)
+            .into_iter()
+            .filter_map(|coordinates| map.get(coordinates).and_then(|tile| tile.occupant))
+            .collect();
+        for target in targets {
+            let (target_owner, _, target_position, _, mut health) =
+                match character_query.get_mut(target) {
+                    Ok(character) => character,
+                    Err(_) => continue,
+                };
+            let before = health.current;
+            for effect in &spell.effects {
+                effect.apply(&mut health);
+            }
+            let damage = before.saturating_sub(health.current);
+            // friendly fire hurts, but doesn't count towards the stats
+            if target_owner.0 != owner {
+                stats.record_attack(owner, target_owner.0, damage, !health.is_alive());
+                awards.push((XpReason::Damage, damage * rules.damage));
+                if !health.is_alive() {
+                    awards.push((XpReason::Kill, rules.kill));
+                }
+            }
+            if !health.is_alive() {
+                info!("{:?} died", target);
+                map.vacate(target_position.0);
+                commands.entity(target).despawn();
+                kills.send(CharacterKilled {
+                    killer: owner,
+                    victim: target_owner.0,
+                });
+            }
+            result.affected.push((target, *health));
+            characters.push(target);
+            owners.push(target_owner.0);
+        }
+        info!("Spell cast: {:?}", result);
+        award_xp(
+            &mut server,
+            &mut ledger_query,
+            request.caster,
+            owner,
+            &awards,
+        );
+
+        visible_characters.send(
+            &mut server,
+            &characters,
+            &owners,
+            &ServerMessage::SpellCast(result),
+        );
+        visible_characters.send(
+            &mut server,
+            &characters[..1],
+            &owners[..1],
+            &ServerMessage::ActionPointsChanged(request.caster, action_points),
+        );
+    }
+}
+
+fn handle_item_requests(
+    mut item_requests: EventReader<ItemRequest>,
+    mut server: Replication,
+    items: Res<Assets<Item>>,
+    visible_characters: Res<VisibleCharacters>,
+    current_player_query: Query<&CurrentPlayer>,
+    mut character_query: Query<
+        (&Owner, &mut Inventory, &mut ActionPoints, &mut Health),
+        With<Character>,
+    >,
+) {
+    let current_player = current_player_query.iter().next().map(|c| c.0);
+    for request in item_requests.iter() {
+        let (owner, mut inventory, mut action_points, mut health) =
+            match character_query.get_mut(request.character) {
+                Ok(character) => character,
+                Err(_) => {
+                    reject(
+                        &mut server,
+                        request.client_id,
+                        request.character,
+                        ActionError::UnknownCharacter,
+                    );
+                    continue;
+                }
+            };
+
+        // changes are tried on a copy, so a failed AP payment leaves the inventory untouched
+        let mut changed = inventory.clone();
+        let mut changed_health = *health;
+        let result = authorize(request.client_id, current_player, owner.0).and_then(|_| {
+            let item = |name: &str| find_item(&items, name).ok_or(ActionError::ItemNotCarried);
+            match &request.action {
+                ItemAction::Use(name) => {
+                    changed.use_item(item(name)?, &mut changed_health)?;
+                    action_points.spend(Action::UseItem, USE_ITEM_COST)
+                }
+                ItemAction::Equip(name) => {
+                    changed.equip(item(name)?)?;
+                    action_points.spend(Action::OpenInventory, OPEN_INVENTORY_COST)
+                }
+                ItemAction::Unequip(slot) => {
+                    changed.unequip(*slot)?;
+                    action_points.spend(Action::OpenInventory, OPEN_INVENTORY_COST)
+                }
+            }
+        });
+        if let Err(error) = result {
+            reject(&mut server, request.client_id, request.character, error);
+            continue;
+        }
+
+        *inventory = changed;
+        info!(
+            "Inventory of {:?} is now {:?}",
+            request.character, *inventory
+        );
+        server.send(
+            owner.0,
+            &ServerMessage::InventoryChanged(request.character, inventory.clone()),
+        );
+
+        let (characters, owners) = ([request.character], [owner.0]);
+        if changed_health != *health {
+            *health = changed_health;
+            visible_characters.send(
+                &mut server,
+                &characters,
+                &owners,
+                &ServerMessage::HealthChanged(request.character, *health),
+            );
+        }
+        visible_characters.send(
+            &mut server,
+            &characters,
+            &owners,
+            &ServerMessage::ActionPointsChanged(request.character, *action_points),
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use std::{collections::HashMap, time::Instant};
+
+use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+use fallout_equestria_tactics::{
+    common::{CurrentPlayer, Player},
+    messages::{
+        check_chat_text, decode, encode, ChatError, ChatLine, ChatMessage, RateLimit, CHAT_CHANNEL,
+        CHAT_MESSAGES_PER_SECOND, CHAT_MESSAGE_BURST, MAX_CHAT_MESSAGE_SIZE,
+    },
+    resources::Players,
+};
+
+use crate::{
+    action_plugin::CharacterKilled, reconnect_plugin::Absent, replication_plugin::Replication,
+    server_plugin::Session,
+};
+
+/// Relays the chat of the players and posts system messages about joins, turns and kills
+///
+/// The chat has a channel of its own, it isn't part of the game state and never waits for the numbered deltas
+pub struct ChatPlugin;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(setup_chatters)
+            .add_system(relay_chat)
+            // despawned players only show up as removed after the commands of the update are applied
+            .add_system_to_stage(CoreStage::PostUpdate, announce);
+        info!("ChatPlugin has been loaded");
+    }
+}
+
+/// Team of a player, team chat only reaches players of the same one
+///
+/// Matches are free for all, so every player starts out on a team of their own
+#[derive(Clone, Copy, Component, Debug, Eq, PartialEq)]
+pub struct Team(pub u64);
+
+/// How fast the player may chat, separate from the limit on game messages
+#[derive(Component)]
+struct ChatLimit {
+    rate_limit: RateLimit,
+    /// The player was told to slow down, once is enough until a message gets through again
+    notified: bool,
+}
+
+fn setup_chatters(mut commands: Commands, query: Query<(Entity, &Player), Added<Player>>) {
+    let now = Instant::now();
+    for (entity, player) in &query {
+        commands
+            .entity(entity)
+            .insert(Team(player.0))
+            .insert(ChatLimit {
+                rate_limit: RateLimit::with_rate(now, CHAT_MESSAGES_PER_SECOND, CHAT_MESSAGE_BURST),
+                notified: false,
+            });
+    }
+}
+
+/// Checks every chat message and passes it on with the actual sender, refusals only go back to the sender
+///
+/// Every message counts towards the rate limit, garbage gets the client kicked like on the game channels
+fn relay_chat(
+    mut server: Replication,
+    players: Res<Players>,
+    mut chatter_query: Query<(&mut Session, &Team, &mut ChatLimit)>,
+    team_query: Query<(&Player, &Team), Without<Absent>>,
+) {
+    let now = Instant::now();
+    let connected = server.clients_id();
+    for &client_id in &connected {
+        while let Some(bytes) = server.receive_message(client_id, CHAT_CHANNEL) {
+            // clients without a player or handshake yet, or kicked ones, don't get to chat
+            let (mut session, &team, mut limit) = match players
+                .get(&client_id)
+                .and_then(|&entity| chatter_query.get_mut(entity).ok())
+            {
+                Some(chatter) => chatter,
+                None => continue,
+            };
+            if !session.can_chat() {
+                continue;
+            }
+            if limit.rate_limit.allow(now).is_err() {
+                if !limit.notified {
+                    limit.notified = true;
+                    refuse(&mut server, client_id, ChatError::RateLimited);
+                }
+                continue;
+            }
+            limit.notified = false;
+            let message = match decode::<ChatMessage>(&bytes, MAX_CHAT_MESSAGE_SIZE) {
+                Ok(message) => message,
+                Err(error) => {
+                    session.kick(&mut server, client_id, error);
+                    continue;
+                }
+            };
+            let text = match check_chat_text(message.text()) {
+                Ok(text) => text.to_string(),
+                Err(error) => {
+                    refuse(&mut server, client_id, error);
+                    continue;
+                }
+            };
+            match message {
+                ChatMessage::Public(_) => {
+                    server.broadcast_message(
+                        CHAT_CHANNEL,
+                        encode(&ChatLine::Public(client_id, text)),
+                    );
+                }
+                ChatMessage::Private(to, _) if connected.contains(&to) => {
+                    let bytes = encode(&ChatLine::Private {
+                        from: client_id,
+                        to,
+                        text,
+                    });
+                    // the sender gets it back, once is enough when whispering to oneself
+                    if to != client_id {
+                        server.send_message(to, CHAT_CHANNEL, bytes.clone());
+                    }
+                    server.send_message(client_id, CHAT_CHANNEL, bytes);
+                }
+                ChatMessage::Private(..) => {
+                    refuse(&mut server, client_id, ChatError::UnknownPlayer)
+                }
+                ChatMessage::Team(_) => {
+                    let bytes = encode(&ChatLine::Team(client_id, text));
+                    let members = team_query
+                        .iter()
+                        .filter(|(_, member_team)| **member_team == team);
+                    for (member, _) in members {
+                        server.send_message(member.0, CHAT_CHANNEL, bytes.clone());
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn refuse(server: &mut RenetServer, client_id: u64, error: ChatError) {
+    info!("Refusing chat of {}: {}", client_id, error);
+    server.send_message(client_id, CHAT_CHANNEL, encode(&ChatLine::Refused(error)));
+}
+
+/// Posts system messages to everyone
+///
+/// Names are remembered, players that left are already despawned when it's time to say so
+fn announce(
+    mut server: ResMut<RenetServer>,
+    mut names: Local<HashMap<Entity, (u64, String)>>,
+    mut kills: EventReader<CharacterKilled>,
+    joined_query: Query<(Entity, &Player, &Name), Added<Player>>,
+    absent_query: Query<Entity, Added<Absent>>,
+    returned: RemovedComponents<Absent>,
+    left: RemovedComponents<Player>,
+    turn_query: Query<&CurrentPlayer, Added<CurrentPlayer>>,
+) {
+    let mut lines = Vec::new();
+    for (entity, player, name) in &joined_query {
+        names.insert(entity, (player.0, name.to_string()));
+        lines.push(format!("{} joined", name));
+    }
+    for entity in left.iter() {
+        if let Some((_, name)) = names.remove(&entity) {
+            lines.push(format!("{} left", name));
+        }
+    }
+    for entity in &absent_query {
+        if let Some((_, name)) = names.get(&entity) {
+            lines.push(format!("{} lost the connection", name));
+        }
+    }
+    for entity in returned.iter() {
+        // players whose grace period ran out lose both at once
+        if let Some((_, name)) = names.get(&entity) {
+            lines.push(format!("{} is back", name));
+        }
+    }
+    let name_of = |id: u64| {
+        names
+            .values()
+            .find(|(player, _)| *player == id)
+            .map_or_else(|| id.to_string(), |(_, name)| name.clone())
+    };
+    for kill in kills.iter() {
+        lines.push(format!(
+            "{} killed a character of {}",
+            name_of(kill.killer),
+            name_of(kill.victim)
+        ));
+    }
+    for current_player in &turn_query {
+        lines.push(format!("It's the turn of {}", name_of(current_player.0)));
+    }
+    for line in lines {
+        info!("{}", line);
+        server.broadcast_message(CHAT_CHANNEL, encode(&ChatLine::System(line)));
+    }
+}
@@ -9,4 +9,6 @@ pub enum ServerState {
     SpawnPhase,
     PlayerTurn,
     NextTurn,
+    /// Only one player has characters left, waits for the players to return to the lobby
+    GameOver,
 }
@@ -1,17 +1,22 @@
 use std::net::{SocketAddr, UdpSocket};
 use std::time::SystemTime;
 
-use bevy_renet::renet::{RenetConnectionConfig, RenetServer, ServerAuthentication, ServerConfig};
-use fallout_equestria_tactics::PROTOCOL_ID;
+use bevy_renet::renet::{RenetServer, ServerAuthentication, ServerConfig, NETCODE_KEY_BYTES};
+use fallout_equestria_tactics::{messages::connection_config, PROTOCOL_ID};
 
 pub struct FoEServer;
 
 impl FoEServer {
-    pub fn new(server_addr: SocketAddr) -> RenetServer {
+    /// Only accepts clients with a connect token signed with `private_key`, see the `token_issuer`
+    pub fn new(server_addr: SocketAddr, private_key: [u8; NETCODE_KEY_BYTES]) -> RenetServer {
         let socket = UdpSocket::bind(server_addr).unwrap();
-        let connection_config = RenetConnectionConfig::default();
-        let server_config =
-            ServerConfig::new(64, PROTOCOL_ID, server_addr, ServerAuthentication::Unsecure);
+        let connection_config = connection_config();
+        let server_config = ServerConfig::new(
+            64,
+            PROTOCOL_ID,
+            server_addr,
+            ServerAuthentication::Secure { private_key },
+        );
         let current_time = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap();
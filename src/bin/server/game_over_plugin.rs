@@ -0,0 +1,148 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{app::AppExit, prelude::*};
+use fallout_equestria_tactics::{
+    character::{Character, Health, Owner},
+    common::{CurrentPlayer, LevelLoaded, Player, Readiness},
+    map::Map,
+    messages::ServerMessage,
+    resources::TurnOrder,
+    stats::MatchStats,
+};
+
+use crate::{common::ServerState, replication_plugin::Replication};
+
+/// Ends the match once only one player has characters left
+///
+/// Afterwards the server goes back to the lobby when every player asked for it,
+/// or shuts down if all players left
+pub struct GameOverPlugin;
+
+impl Plugin for GameOverPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ReturnToLobbyRequest>()
+            .insert_resource(ReturningPlayers::default())
+            .add_system_set(
+                SystemSet::on_update(ServerState::PlayerTurn).with_system(check_for_winner),
+            )
+            .add_system_set(SystemSet::on_enter(ServerState::GameOver).with_system(end_match))
+            .add_system_set(
+                SystemSet::on_update(ServerState::GameOver).with_system(wait_for_players),
+            )
+            .add_system_set(SystemSet::on_exit(ServerState::GameOver).with_system(reset_match));
+        info!("GameOverPlugin has been loaded");
+    }
+}
+
+/// A client wants to play another match
+pub struct ReturnToLobbyRequest {
+    pub client_id: u64,
+}
+
+/// Players that want to play another match
+#[derive(Default, Resource)]
+struct ReturningPlayers(HashSet<u64>);
+
+fn check_for_winner(
+    character_query: Query<(&Owner, &Health), With<Character>>,
+    mut app_state: ResMut<State<ServerState>>,
+) {
+    let survivors: HashSet<u64> = character_query
+        .iter()
+        .filter(|(_, health)| health.is_alive())
+        .map(|(owner, _)| owner.0)
+        .collect();
+    if survivors.len() <= 1 {
+        // the killing blow may have queued the next turn in the same frame, the end of the match wins
+        app_state.overwrite_set(ServerState::GameOver).unwrap();
+    }
+}
+
+/// Announces the winner and the stats of the match
+fn end_match(
+    mut server: Replication,
+    mut returning_players: ResMut<ReturningPlayers>,
+    stats: Res<MatchStats>,
+    character_query: Query<(&Owner, &Health), With<Character>>,
+    mut player_query: Query<(&mut Readiness, &mut LevelLoaded), With<Player>>,
+) {
+    let mut survivors: HashMap<u64, usize> = HashMap::new();
+    for (owner, health) in &character_query {
+        if health.is_alive() {
+            *survivors.entry(owner.0).or_default() += 1;
+        }
+    }
+    let winner = survivors.keys().next().copied();
+    info!("Match ended, winner is {:?}", winner);
+    for stats in stats.to_vec() {
+        info!(
+            "{} ({}): {} damage dealt, {} damage taken, {} kills, {} characters lost",
+            stats.name,
+            stats.player,
+            stats.damage_dealt,
+            stats.damage_taken,
+            stats.kills,
+            stats.characters_lost
+        );
+    }
+
+    server.broadcast(&ServerMessage::MatchEnded {
+        winner,
+        stats: stats.to_vec(),
+    });
+
+    // everybody has to ready up and load the level again for the next match
+    for (mut readiness, mut level_loaded) in &mut player_query {
+        readiness.0 = false;
+        level_loaded.0 = false;
+    }
+    returning_players.0.clear();
+}
+
+fn wait_for_players(
+    mut return_requests: EventReader<ReturnToLobbyRequest>,
+    mut returning_players: ResMut<ReturningPlayers>,
+    mut app_state: ResMut<State<ServerState>>,
+    mut app_exit_events: EventWriter<AppExit>,
+    player_query: Query<&Player>,
+) {
+    for request in return_requests.iter() {
+        info!("{} wants to return to the lobby", request.client_id);
+        returning_players.0.insert(request.client_id);
+    }
+
+    if player_query.is_empty() {
+        info!("All players left, shutting down");
+        app_exit_events.send(AppExit);
+    } else if player_query
+        .iter()
+        .all(|player| returning_players.0.contains(&player.0))
+    {
+        app_state.set(ServerState::Lobby).unwrap();
+    }
+}
+
+/// Removes everything left over from the match, so the lobby can load the level anew
+fn reset_match(
+    mut commands: Commands,
+    mut map: ResMut<Map>,
+    mut turn_order: ResMut<TurnOrder>,
+    character_query: Query<Entity, With<Character>>,
+    current_player_query: Query<Entity, With<CurrentPlayer>>,
+    level_query: Query<(Entity, &Name), Without<Parent>>,
+) {
+    for entity in &character_query {
+        commands.entity(entity).despawn_recursive();
+    }
+    for entity in &current_player_query {
+        commands.entity(entity).remove::<CurrentPlayer>();
+    }
+    for (entity, name) in &level_query {
+        if name.as_str() == "Level" {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    map.clear();
+    turn_order.order.clear();
+    commands.insert_resource(MatchStats::new());
+}
@@ -1,9 +1,13 @@
 use std::{env::args, net::SocketAddr, time::Duration};
 
 use bevy::{prelude::*, app::ScheduleRunnerSettings, ecs::schedule::ShouldRun};
-use fallout_equestria_tactics::{resources::*, level_loader::AssetsLoading};
+use fallout_equestria_tactics::{resources::*, auth::{AuthConfig, DEFAULT_AUTH_CONFIG}, level_loader::AssetsLoading, map::{HexLayout, Map}, stats::MatchStats};
 
-use crate::{common::ServerState, foe_server::FoEServer};
+use crate::{
+    common::ServerState,
+    foe_server::FoEServer,
+    reconnect_plugin::{AbsentTurns, ReconnectPolicy},
+};
 
 /// Initialises the server and loads a level
 /// 
@@ -25,6 +29,10 @@ const DEFAULT_SERVER_ADDRESS: &str = "127.0.0.1:5000";
 /// 
 /// Is overwritten by the second argument when starting the server
 const DEFAULT_LEVEL_NAME: &str = "level.gltf#Scene0";
+/// The match waits for absent players by default.
+/// 
+/// Is overwritten by the third argument when starting the server, either `skip` or `pause`
+const DEFAULT_ABSENT_TURNS: AbsentTurns = AbsentTurns::Pause;
 
 /// Initialises all default values and inserts necessary resources for the server to start
 fn init(
@@ -43,16 +51,45 @@ fn init(
     } else {
         DEFAULT_SERVER_ADDRESS.parse().unwrap()
     };
-    commands.insert_resource(FoEServer::new(server_address));
+    // the private key is shared with the token issuer, clients only get the tokens
+    // there is no default key, a key everyone knows lets everyone mint tokens
+    let private_key = match AuthConfig::load(DEFAULT_AUTH_CONFIG)
+        .and_then(|config| config.private_key())
+    {
+        Ok(private_key) => private_key,
+        Err(error) => {
+            error!(
+                "Couldn't load {}: {}, run the token_issuer once to generate it",
+                DEFAULT_AUTH_CONFIG, error
+            );
+            std::process::exit(1);
+        }
+    };
+    commands.insert_resource(FoEServer::new(server_address, private_key));
     let level_name = if args.len() > 2 {
         &args[2]
     } else {
         DEFAULT_LEVEL_NAME
     };
     commands.insert_resource(LevelName::new(level_name));
+    let absent_turns = if args.len() > 3 {
+        args[3].parse().unwrap_or_else(|error| {
+            warn!("{}, using {:?}", error, DEFAULT_ABSENT_TURNS);
+            DEFAULT_ABSENT_TURNS
+        })
+    } else {
+        DEFAULT_ABSENT_TURNS
+    };
+    commands.insert_resource(ReconnectPolicy {
+        absent_turns,
+        ..default()
+    });
     commands.insert_resource(Players::new());
     commands.insert_resource(TurnOrder::new());
+    commands.insert_resource(MatchStats::new());
     commands.insert_resource(AssetsLoading(Vec::new()));
+    commands.insert_resource(HexLayout::default());
+    commands.insert_resource(Map::new());
     commands.insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
         1.0 / 60.0,
     )));
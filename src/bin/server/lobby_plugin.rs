@@ -1,14 +1,14 @@
 use bevy::{prelude::*, asset::LoadState};
 
 use bevy_rapier3d::prelude::RapierColliderHandle;
-use bevy_renet::renet::{RenetServer, DefaultChannel};
-use fallout_equestria_tactics::{level_loader::{add_collider, AssetsLoading, load_level}, common::{Readiness, LevelLoaded}, messages::ServerMessage, resources::{LevelName, Players}};
+use fallout_equestria_tactics::{level_loader::{add_collider, bake_map, build_map, AssetsLoading, load_level}, common::{Readiness, LevelLoaded, Spawnpoint}, inventory::Item, messages::ServerMessage, race::RaceDefinition, resources::{LevelName, Players}, squad::{Squad, SquadRules, SquadRulesHandle}};
 
-use crate::common::ServerState;
+use crate::{common::ServerState, replication_plugin::Replication};
 pub struct LobbyPlugin;
 
 impl Plugin for LobbyPlugin {
     fn build(&self, app: &mut App) {
+        app.add_event::<SquadSubmission>();
         app.add_system_set(
             SystemSet::on_enter(ServerState::Lobby)
             .with_system(load_level)
@@ -16,10 +16,13 @@ impl Plugin for LobbyPlugin {
         app.add_system_set(
             SystemSet::on_update(ServerState::Lobby)
             .with_system(add_collider)
+            .with_system(build_map)
             .with_system(check_for_level_loaded_and_readiness)
+            .with_system(handle_squad_submissions)
         );
         app.add_system_set(
             SystemSet::on_exit(ServerState::Lobby)
+            .with_system(bake_map)
             .with_system(notify_clients)
         );
         app.add_system_set(
@@ -30,19 +33,79 @@ impl Plugin for LobbyPlugin {
     }
 }
 
+/// A client submitted the squad it wants to play with
+pub struct SquadSubmission {
+    pub client_id: u64,
+    pub squad: Squad,
+}
+
+/// Validates submitted squads against the same rules the squad builder of the client uses
+///
+/// Accepted squads are kept on the player entity, so they are used again in the next match
+fn handle_squad_submissions(
+    mut commands: Commands,
+    mut squad_submissions: EventReader<SquadSubmission>,
+    mut server: Replication,
+    players: Res<Players>,
+    rules_handle: Res<SquadRulesHandle>,
+    rules: Res<Assets<SquadRules>>,
+    items: Res<Assets<Item>>,
+    race_definitions: Res<Assets<RaceDefinition>>,
+) {
+    let rules = rules_handle.rules(&rules);
+    for submission in squad_submissions.iter() {
+        let entity = match players.get(&submission.client_id) {
+            Some(&entity) => entity,
+            None => continue,
+        };
+        let message = match rules.validate(&submission.squad, &items, &race_definitions) {
+            Ok(()) => {
+                info!("Player {} submitted a valid squad", submission.client_id);
+                commands.entity(entity).insert(submission.squad.clone());
+                ServerMessage::SquadAccepted
+            }
+            Err(error) => {
+                info!("Rejecting squad of {}: {:?}", submission.client_id, error);
+                ServerMessage::SquadRejected(error)
+            }
+        };
+        server.send(submission.client_id, &message);
+    }
+}
+
+/// Starts the match once everyone is ready and the level is loaded
+///
+/// A level without a spawnpoint for every player can't be played, the lobby waits until enough players left
 fn check_for_level_loaded_and_readiness(
     readiness_query: Query<&Readiness>,
     collider_query: Query<Entity, (With<Handle<Mesh>>, Without<RapierColliderHandle>)>,
+    spawnpoint_query: Query<(), With<Spawnpoint>>,
     mut app_state: ResMut<State<ServerState>>,
     asset_server: Res<AssetServer>,
     loading: Res<AssetsLoading>,
+    mut refused: Local<Option<(usize, usize)>>,
 ) {
     if readiness_query.iter().all(|r| r.0 == true) && !readiness_query.is_empty() {
         match asset_server.get_group_load_state(loading.0.iter().map(|h| h.id)) {
             LoadState::Loaded => {
-                if collider_query.is_empty() {
-                    app_state.set(ServerState::WaitingForPlayerLoadLevel ).unwrap();
+                if !collider_query.is_empty() {
+                    return;
+                }
+                let spawnpoints = spawnpoint_query.iter().count();
+                let players = readiness_query.iter().count();
+                if spawnpoints < players {
+                    // the lobby checks every frame, once is enough
+                    if *refused != Some((spawnpoints, players)) {
+                        warn!(
+                            "Level has {} spawnpoints for {} players, not starting the match",
+                            spawnpoints, players
+                        );
+                        *refused = Some((spawnpoints, players));
+                    }
+                    return;
                 }
+                *refused = None;
+                app_state.set(ServerState::WaitingForPlayerLoadLevel ).unwrap();
             }
             _ => (),
         }
@@ -50,11 +113,10 @@ fn check_for_level_loaded_and_readiness(
 }
 
 fn notify_clients(
-    mut server: ResMut<RenetServer>,
+    mut server: Replication,
     level_name: Res<LevelName>,
 ) {
-    let message = bincode::serialize(&ServerMessage::LoadLevel(level_name.0.clone())).unwrap();
-    server.broadcast_message(DefaultChannel::Reliable, message)
+    server.broadcast(&ServerMessage::LoadLevel(level_name.0.clone()))
 }
 
 fn check_for_players_level_loaded(
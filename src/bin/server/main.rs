@@ -4,21 +4,46 @@ use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use bevy_rapier3d::prelude::{RapierPhysicsPlugin, NoUserData};
 use bevy_scene_hook::HookPlugin;
 use bevy_turborand::prelude::*;
+use fallout_equestria_tactics::{
+    inventory::ItemPlugin, progression::ProgressionPlugin, race::RacePlugin, spell::SpellPlugin,
+    squad::SquadPlugin,
+};
+
+mod action_plugin;
+use action_plugin::ActionPlugin;
+
+mod chat_plugin;
+use chat_plugin::ChatPlugin;
 
 mod foe_server;
 
+mod game_over_plugin;
+use game_over_plugin::GameOverPlugin;
+
 mod init_plugin;
 use init_plugin::InitPlugin;
 
 mod lobby_plugin;
 use lobby_plugin::LobbyPlugin;
 
+mod progression_plugin;
+use progression_plugin::ProgressionServerPlugin;
+
+mod reconnect_plugin;
+use reconnect_plugin::ReconnectPlugin;
+
+mod replication_plugin;
+use replication_plugin::ReplicationPlugin;
+
 mod server_plugin;
 use server_plugin::*;
 
 mod spawn_plugin;
 use spawn_plugin::*;
 
+mod visibility_plugin;
+use visibility_plugin::VisibilityPlugin;
+
 mod common;
 use common::ServerState;
 
@@ -32,8 +57,20 @@ fn main() {
         .add_plugin(InitPlugin)
         .add_plugin(LobbyPlugin)
         .add_plugin(ServerPlugin)
-        .add_plugin(SpawnPlugin);
-        // .add_plugin(RngPlugin::default());
+        .add_plugin(ReconnectPlugin)
+        .add_plugin(ReplicationPlugin)
+        .add_plugin(ChatPlugin)
+        .add_plugin(ActionPlugin)
+        .add_plugin(SpellPlugin)
+        .add_plugin(RacePlugin)
+        .add_plugin(ItemPlugin)
+        .add_plugin(ProgressionPlugin)
+        .add_plugin(ProgressionServerPlugin)
+        .add_plugin(SquadPlugin)
+        .add_plugin(SpawnPlugin)
+        .add_plugin(VisibilityPlugin)
+        .add_plugin(GameOverPlugin)
+        .add_plugin(RngPlugin::default());
 
     app.run();
 }
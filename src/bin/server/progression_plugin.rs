@@ -0,0 +1,113 @@
+use bevy::prelude::*;
+use fallout_equestria_tactics::{
+    action_points::{ActionError, ActionPoints},
+    character::{BaseSpecial, Character, Health, Owner},
+    common::{Race, Special},
+    messages::ServerMessage,
+    progression::{derive_special, find_skill, SkillTree, XpLedger},
+    race::{find_race_definition, RaceDefinition},
+};
+
+use crate::{
+    action_plugin::reject, replication_plugin::Replication, visibility_plugin::VisibleCharacters,
+};
+
+/// Lets players spend the XP of their characters on skills
+pub struct ProgressionServerPlugin;
+
+impl Plugin for ProgressionServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SpendXpRequest>()
+            .add_system(handle_spend_xp_requests);
+        info!("ProgressionServerPlugin has been loaded");
+    }
+}
+
+/// A client wants one of its characters to learn a skill
+pub struct SpendXpRequest {
+    pub client_id: u64,
+    pub character: Entity,
+    pub skill: String,
+}
+
+/// Skills can be learned at any time, even during the turn of another player
+fn handle_spend_xp_requests(
+    mut spend_requests: EventReader<SpendXpRequest>,
+    mut server: Replication,
+    skill_trees: Res<Assets<SkillTree>>,
+    race_definitions: Res<Assets<RaceDefinition>>,
+    visible_characters: Res<VisibleCharacters>,
+    mut character_query: Query<
+        (
+            &Owner,
+            &Race,
+            &BaseSpecial,
+            &mut Special,
+            &mut Health,
+            &mut ActionPoints,
+            &mut XpLedger,
+        ),
+        With<Character>,
+    >,
+) {
+    for request in spend_requests.iter() {
+        let (owner, race, base_special, mut special, mut health, mut action_points, mut ledger) =
+            match character_query.get_mut(request.character) {
+                Ok(character) => character,
+                Err(_) => {
+                    reject(
+                        &mut server,
+                        request.client_id,
+                        request.character,
+                        ActionError::UnknownCharacter,
+                    );
+                    continue;
+                }
+            };
+        if owner.0 != request.client_id {
+            reject(
+                &mut server,
+                request.client_id,
+                request.character,
+                ActionError::NotYourCharacter,
+            );
+            continue;
+        }
+
+        let race_definition = find_race_definition(&race_definitions, *race);
+        let result = find_skill(&skill_trees, &request.skill)
+            .ok_or(ActionError::UnknownSkill)
+            .and_then(|(tree, skill)| {
+                if race_definition.map_or(true, |race| !race.skill_trees.contains(&tree.name)) {
+                    Err(ActionError::SkillLocked)
+                } else {
+                    ledger.learn(skill)
+                }
+            });
+        if let Err(error) = result {
+            reject(&mut server, request.client_id, request.character, error);
+            continue;
+        }
+        info!("{:?} learned {}", request.character, request.skill);
+
+        *special = derive_special(base_special, race_definition, &ledger, &skill_trees);
+        // raised maxima are granted right away, lowered ones cut off what is above them
+        let derived_health = Health::from_special(&special);
+        health.current = (health.current + derived_health.max.saturating_sub(health.max))
+            .min(derived_health.max);
+        health.max = derived_health.max;
+        action_points.max = ActionPoints::from_special(&special).max;
+        action_points.current = action_points.current.min(action_points.max);
+
+        server.send(
+            owner.0,
+            &ServerMessage::ExperienceChanged(request.character, ledger.clone()),
+        );
+        visible_characters.send(
+            &mut server,
+            &[request.character],
+            &[owner.0],
+            &ServerMessage::StatsChanged(request.character, *special, *health, *action_points),
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use fallout_equestria_tactics::{
+    character::{Character, Owner, Position},
+    common::{CurrentPlayer, Player},
+    map::Map,
+    resources::{Players, TurnOrder},
+};
+
+use crate::{
+    common::ServerState,
+    replication_plugin::Replication,
+    server_plugin::{end_turn, remove_player},
+    visibility_plugin::VisibleCharacters,
+};
+
+/// How long the match keeps the slot of a player who lost the connection
+const RECONNECT_GRACE: Duration = Duration::from_secs(60);
+
+/// Keeps players who lost the connection during a match in it for a while
+///
+/// Their characters stay on the map and their turns are skipped or waited for, depending on the [`ReconnectPolicy`]
+pub struct ReconnectPlugin;
+
+impl Plugin for ReconnectPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReconnectPolicy>()
+            .add_system(handle_absent_players);
+        info!("ReconnectPlugin has been loaded");
+    }
+}
+
+/// What happens to the turns of absent players
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AbsentTurns {
+    /// Their turns end right away
+    Skip,
+    /// The match waits for them until the grace period is over
+    #[default]
+    Pause,
+}
+
+impl FromStr for AbsentTurns {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(AbsentTurns::Skip),
+            "pause" => Ok(AbsentTurns::Pause),
+            _ => Err(format!("{} is neither skip nor pause", s)),
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct ReconnectPolicy {
+    pub grace: Duration,
+    pub absent_turns: AbsentTurns,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            grace: RECONNECT_GRACE,
+            absent_turns: AbsentTurns::default(),
+        }
+    }
+}
+
+/// The player lost the connection, their entity is kept until the grace period is over
+#[derive(Component)]
+pub struct Absent {
+    pub since: Instant,
+}
+
+/// Only running matches keep the slots of absent players, the lobby doesn't need them
+pub fn keeps_absent_players(state: &ServerState) -> bool {
+    matches!(
+        state,
+        ServerState::SpawnPhase | ServerState::PlayerTurn | ServerState::NextTurn
+    )
+}
+
+/// Removes absent players whose grace period is over and passes on the turns they can't take
+fn handle_absent_players(
+    mut commands: Commands,
+    mut server: Replication,
+    mut players: ResMut<Players>,
+    mut turn_order: ResMut<TurnOrder>,
+    mut map: ResMut<Map>,
+    mut app_state: ResMut<State<ServerState>>,
+    policy: Res<ReconnectPolicy>,
+    absent_query: Query<(&Player, &Absent, Option<&CurrentPlayer>)>,
+    visible_characters: Res<VisibleCharacters>,
+    character_query: Query<(Entity, &Owner, &Position), With<Character>>,
+) {
+    let now = Instant::now();
+    let mut pass_turn = false;
+    for (player, absent, current_player) in &absent_query {
+        let expired = !keeps_absent_players(app_state.current())
+            || now.duration_since(absent.since) >= policy.grace;
+        if expired {
+            info!("{} didn't return in time", player.0);
+            remove_player(
+                &mut commands,
+                &mut server,
+                &mut players,
+                &mut map,
+                &visible_characters,
+                &character_query,
+                player.0,
+            );
+            turn_order.order.retain(|&id| id != player.0);
+        }
+        if current_player.is_some() && (expired || policy.absent_turns == AbsentTurns::Skip) {
+            pass_turn = true;
+        }
+    }
+    if pass_turn && app_state.current() == &ServerState::PlayerTurn {
+        info!("Passing on the turn of an absent player");
+        end_turn(&mut app_state);
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_renet::renet::{DefaultChannel, RenetServer};
+use fallout_equestria_tactics::{
+    character::{BaseSpecial, Character},
+    common::{CurrentPlayer, Player},
+    inventory::Inventory,
+    messages::{encode, Sequenced, ServerMessage},
+    progression::XpLedger,
+    replication::{CharacterSnapshot, GameSnapshot, MatchPhase, PlayerSnapshot},
+    resources::{LevelName, TurnOrder},
+};
+
+use crate::{
+    common::ServerState,
+    reconnect_plugin::Absent,
+    spawn_plugin::Placement,
+    visibility_plugin::{
+        character_data, update_visibility, CharacterComponents, VisibleCharacters,
+    },
+};
+
+/// Sends every client a [`GameSnapshot`] when it joins, returns or asks for one,
+/// everything after that are numbered deltas
+pub struct ReplicationPlugin;
+
+impl Plugin for ReplicationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Sequences::default())
+            .add_event::<SnapshotRequest>()
+            // after the commands of the new players are applied, before the visibility sends any deltas about them
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                send_snapshots.before(update_visibility),
+            );
+        info!("ReplicationPlugin has been loaded");
+    }
+}
+
+/// Sequence of the next message to each client
+#[derive(Default, Resource)]
+pub struct Sequences(HashMap<u64, u64>);
+
+/// The server, sending [`ServerMessage`]s numbered per client
+///
+/// Receiving and disconnecting go straight to the [`RenetServer`]
+#[derive(SystemParam)]
+pub struct Replication<'w, 's> {
+    server: ResMut<'w, RenetServer>,
+    sequences: ResMut<'w, Sequences>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> Replication<'w, 's> {
+    /// Snapshots go over the chunk channel, they may be too big for the reliable one
+    pub fn send(&mut self, client_id: u64, message: &ServerMessage) {
+        let sequence = self.sequences.0.entry(client_id).or_default();
+        let channel = match message {
+            ServerMessage::Snapshot(_) => DefaultChannel::Chunk,
+            _ => DefaultChannel::Reliable,
+        };
+        let bytes = encode(&Sequenced {
+            sequence: *sequence,
+            message,
+        });
+        *sequence += 1;
+        self.server.send_message(client_id, channel, bytes);
+    }
+
+    pub fn broadcast(&mut self, message: &ServerMessage) {
+        for client_id in self.server.clients_id() {
+            self.send(client_id, message);
+        }
+    }
+
+    pub fn broadcast_except(&mut self, except: u64, message: &ServerMessage) {
+        for client_id in self.server.clients_id() {
+            if client_id != except {
+                self.send(client_id, message);
+            }
+        }
+    }
+
+    /// A new connection of the client starts counting from zero again
+    pub fn reset(&mut self, client_id: u64) {
+        self.sequences.0.remove(&client_id);
+    }
+}
+
+impl<'w, 's> Deref for Replication<'w, 's> {
+    type Target = RenetServer;
+
+    fn deref(&self) -> &Self::Target {
+        &self.server
+    }
+}
+
+impl<'w, 's> DerefMut for Replication<'w, 's> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.server
+    }
+}
+
+/// The client needs a [`GameSnapshot`]
+pub struct SnapshotRequest {
+    pub client_id: u64,
+}
+
+fn phase(state: &ServerState) -> MatchPhase {
+    match state {
+        ServerState::Init | ServerState::Lobby => MatchPhase::Lobby,
+        ServerState::WaitingForPlayerLoadLevel => MatchPhase::LoadingLevel,
+        ServerState::SpawnPhase => MatchPhase::Placement,
+        ServerState::PlayerTurn | ServerState::NextTurn => MatchPhase::Turns,
+        ServerState::GameOver => MatchPhase::GameOver,
+    }
+}
+
+/// Sends the game as the client is allowed to know it, enemies only while in sight
+fn send_snapshots(
+    mut snapshot_requests: EventReader<SnapshotRequest>,
+    mut server: Replication,
+    app_state: Res<State<ServerState>>,
+    level_name: Res<LevelName>,
+    turn_order: Res<TurnOrder>,
+    placement: Option<Res<Placement>>,
+    visible_characters: Res<VisibleCharacters>,
+    player_query: Query<(
+        &Player,
+        Entity,
+        &Name,
+        Option<&Absent>,
+        Option<&CurrentPlayer>,
+    )>,
+    character_query: Query<CharacterComponents, With<Character>>,
+    base_special_query: Query<&BaseSpecial>,
+    progress_query: Query<(&Inventory, &XpLedger)>,
+) {
+    for request in snapshot_requests.iter() {
+        let client_id = request.client_id;
+        let phase = phase(app_state.current());
+        let players = player_query
+            .iter()
+            .map(|(player, entity, name, absent, _)| PlayerSnapshot {
+                id: player.0,
+                name: name.to_string(),
+                entity,
+                absent: absent.is_some(),
+            })
+            .collect();
+        let characters = character_query
+            .iter()
+            .filter(|(entity, _, owner, ..)| {
+                owner.0 == client_id || visible_characters.sees(client_id, *entity)
+            })
+            .map(|components| {
+                let data = character_data(components, &base_special_query);
+                // enemies never see the inventory or XP of a character
+                let (inventory, ledger) = match progress_query.get(data.entity) {
+                    Ok((inventory, ledger)) if data.owner == client_id => {
+                        (Some(inventory.clone()), Some(ledger.clone()))
+                    }
+                    _ => (None, None),
+                };
+                CharacterSnapshot {
+                    data,
+                    inventory,
+                    ledger,
+                }
+            })
+            .collect();
+        let current_player = match phase {
+            MatchPhase::Placement => placement
+                .as_ref()
+                .and_then(|placement| placement.current_player()),
+            MatchPhase::Turns => player_query
+                .iter()
+                .find_map(|(.., current_player)| current_player.map(|current| current.0)),
+            _ => None,
+        };
+        let spawn_zone = match phase {
+            MatchPhase::Placement => placement
+                .as_ref()
+                .map_or_else(Vec::new, |placement| placement.zone(client_id).to_vec()),
+            _ => Vec::new(),
+        };
+        info!("Sending a snapshot to {}", client_id);
+        server.send(
+            client_id,
+            &ServerMessage::Snapshot(GameSnapshot {
+                phase,
+                level: level_name.0.clone(),
+                players,
+                characters,
+                current_player,
+                turn_order: turn_order.order.iter().copied().collect(),
+                spawn_zone,
+            }),
+        );
+    }
+}
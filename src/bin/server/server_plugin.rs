@@ -1,16 +1,37 @@
-use bevy::prelude::*;
+use std::time::Instant;
+
+use bevy::{ecs::schedule::StateError, prelude::*};
 use bevy_renet::{
-    renet::{DefaultChannel, RenetServer, ServerEvent},
+    renet::{DefaultChannel, ServerEvent},
     RenetServerPlugin,
 };
 
 use fallout_equestria_tactics::{
-    common::{CurrentPlayer, LevelLoaded, Player, Readiness, Username},
-    messages::{ClientMessage, ServerMessage},
+    action_points::ActionPoints,
+    character::{Character, Owner, Position},
+    common::{CurrentPlayer, LevelLoaded, Player, Readiness, Special, Username},
+    map::Map,
+    messages::{
+        decode, ClientMessage, Handshake, ProtocolError, RateLimit, ServerMessage,
+        MAX_CLIENT_MESSAGE_SIZE,
+    },
     resources::{Players, TurnOrder},
+    squad::{Squad, SquadError},
 };
 
-use crate::common::ServerState;
+use crate::{
+    action_plugin::{
+        AltitudeRequest, AttackRequest, CastSpellRequest, ItemAction, ItemRequest, MoveRequest,
+    },
+    common::ServerState,
+    game_over_plugin::ReturnToLobbyRequest,
+    lobby_plugin::SquadSubmission,
+    progression_plugin::SpendXpRequest,
+    reconnect_plugin::{keeps_absent_players, Absent},
+    replication_plugin::{Replication, SnapshotRequest},
+    spawn_plugin::PlacementRequest,
+    visibility_plugin::VisibleCharacters,
+};
 
 pub struct ServerPlugin;
 
@@ -18,8 +39,9 @@ impl Plugin for ServerPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(RenetServerPlugin::default())
             .add_system(handle_server_events)
-            .add_system(handle_reliable_messages)
-            .add_system(handle_unreliable_messages)
+            .add_system(disconnect_kicked_clients.before(handle_reliable_messages))
+            .add_system(handle_reliable_messages.after(handle_server_events))
+            .add_system(handle_unreliable_messages.after(handle_server_events))
             .add_system_set(
                 SystemSet::on_enter(ServerState::PlayerTurn)
                 .with_system(handle_new_turn),
@@ -34,17 +56,103 @@ impl Plugin for ServerPlugin {
     }
 }
 
+/// Protocol state of a connected client, kept on its player entity
+#[derive(Component)]
+pub(crate) struct Session {
+    /// Set once the client sent a matching [`Handshake`]
+    handshake: bool,
+    rate_limit: RateLimit,
+    /// Why the client is disconnected, it only happens in the next frame so the reason reaches the client first
+    kicked: Option<ProtocolError>,
+}
+
+impl Session {
+    fn new(now: Instant) -> Self {
+        Self {
+            handshake: false,
+            rate_limit: RateLimit::new(now),
+            kicked: None,
+        }
+    }
+
+    /// Checks and decodes a message of the client, the first reliable message has to be its [`Handshake`]
+    fn receive(
+        &mut self,
+        message: &[u8],
+        now: Instant,
+        reliable: bool,
+    ) -> Result<Option<ClientMessage>, ProtocolError> {
+        self.rate_limit.allow(now)?;
+        if self.handshake {
+            return decode(message, MAX_CLIENT_MESSAGE_SIZE).map(Some);
+        }
+        if !reliable {
+            return Err(ProtocolError::MissingHandshake);
+        }
+        let handshake: Handshake = decode(message, MAX_CLIENT_MESSAGE_SIZE)
+            .map_err(|_| ProtocolError::MissingHandshake)?;
+        handshake.check()?;
+        self.handshake = true;
+        Ok(None)
+    }
+
+    /// Chat only counts once the client is known to speak the protocol, and stops with a kick
+    pub(crate) fn can_chat(&self) -> bool {
+        self.handshake && self.kicked.is_none()
+    }
+
+    /// Tells the client why it's disconnected, messages it sends in the meantime are ignored
+    pub(crate) fn kick(&mut self, server: &mut Replication, client_id: u64, error: ProtocolError) {
+        warn!("Kicking {}: {}", client_id, error);
+        server.send(client_id, &ServerMessage::Kicked(error));
+        self.kicked = Some(error);
+    }
+}
+
+fn disconnect_kicked_clients(mut server: Replication, query: Query<(&Player, &Session)>) {
+    for (player, session) in &query {
+        if session.kicked.is_some() {
+            server.disconnect(player.0);
+        }
+    }
+}
+
 fn handle_server_events(
     mut server_events: EventReader<ServerEvent>,
-    mut server: ResMut<RenetServer>,
+    mut server: Replication,
     mut commands: Commands,
     mut players: ResMut<Players>,
-    player_query: Query<(&Player, Entity, &Name)>,
+    character_query: Query<(Entity, &Owner, &Position), With<Character>>,
+    mut map: ResMut<Map>,
+    app_state: Res<State<ServerState>>,
+    session_query: Query<&Session>,
+    absent_query: Query<(), With<Absent>>,
+    visible_characters: Res<VisibleCharacters>,
+    mut snapshot_requests: EventWriter<SnapshotRequest>,
 ) {
     for event in server_events.iter() {
         match event {
             ServerEvent::ClientConnected(id, user_data) => {
                 let user_name = Username::from_user_data(user_data);
+                if let Some(&entity) = players.get(id) {
+                    // the issuer only hands out the id again to the same identity
+                    if absent_query.contains(entity) {
+                        info!("{} ({}) returned", user_name.0, id);
+                        commands
+                            .entity(entity)
+                            .remove::<Absent>()
+                            .insert(Session::new(Instant::now()));
+                        // the new connection counts from zero and starts with a snapshot
+                        server.reset(*id);
+                        server.broadcast_except(*id, &ServerMessage::PlayerReturned(*id));
+                        snapshot_requests.send(SnapshotRequest { client_id: *id });
+                        continue;
+                    }
+                    // the issuer never hands out an id twice, a second player with it is an impostor
+                    warn!("Rejecting {} ({}), the id is already taken", user_name.0, id);
+                    server.disconnect(*id);
+                    continue;
+                }
                 info!("{} ({}) connected", user_name.0, id);
 
                 let entity = commands
@@ -52,52 +160,123 @@ fn handle_server_events(
                     .insert(Readiness(false))
                     .insert(LevelLoaded(false))
                     .insert(Name::from(user_name.0.clone()))
+                    .insert(Session::new(Instant::now()))
                     .id();
 
-                for (player, server_entity, player_name) in &player_query {
-                    let message = bincode::serialize(&ServerMessage::PlayerConnected(
-                        player.0,
-                        player_name.to_string(),
-                        server_entity,
-                    ))
-                    .unwrap();
-                    server.send_message(*id, DefaultChannel::Reliable, message);
-                }
-
                 players.players.insert(*id, entity);
+                server.reset(*id);
 
-                // notify everyone of the new player
-                let message =
-                    bincode::serialize(&ServerMessage::PlayerConnected(*id, user_name.0, entity)).unwrap();
-                server.broadcast_message(DefaultChannel::Reliable, message);
+                // the new player learns about everyone, including themselves, from the snapshot
+                let message = ServerMessage::PlayerConnected(*id, user_name.0, entity);
+                server.broadcast_except(*id, &message);
+                snapshot_requests.send(SnapshotRequest { client_id: *id });
             }
             ServerEvent::ClientDisconnected(id) => {
-                info!("{} disconnected", id);
-                if let Some(player_entity) = players.players.remove(id) {
-                    commands.entity(player_entity).despawn();
+                // kicked clients broke the protocol, they don't get to come back
+                let absent = players.get(id).copied().filter(|&entity| {
+                    keeps_absent_players(app_state.current())
+                        && session_query
+                            .get(entity)
+                            .map_or(false, |session| session.kicked.is_none())
+                });
+                if let Some(entity) = absent {
+                    info!("{} lost the connection, keeping their slot", id);
+                    commands.entity(entity).insert(Absent {
+                        since: Instant::now(),
+                    });
+                    server.broadcast(&ServerMessage::PlayerAbsent(*id));
+                    continue;
                 }
-
-                let message = bincode::serialize(&ServerMessage::PlayerDisconnected(*id)).unwrap();
-                server.broadcast_message(DefaultChannel::Reliable, message);
+                info!("{} disconnected", id);
+                remove_player(
+                    &mut commands,
+                    &mut server,
+                    &mut players,
+                    &mut map,
+                    &visible_characters,
+                    &character_query,
+                    *id,
+                );
             }
         }
     }
 }
 
+/// Despawns the player and their characters and tells everyone they left
+///
+/// Only clients that see a character are told it's gone, the others never knew it
+pub(crate) fn remove_player(
+    commands: &mut Commands,
+    server: &mut Replication,
+    players: &mut Players,
+    map: &mut Map,
+    visible_characters: &VisibleCharacters,
+    character_query: &Query<(Entity, &Owner, &Position), With<Character>>,
+    client_id: u64,
+) {
+    if let Some(player_entity) = players.players.remove(&client_id) {
+        commands.entity(player_entity).despawn();
+    }
+
+    for (character, owner, position) in character_query {
+        if owner.0 == client_id {
+            commands.entity(character).despawn();
+            map.vacate(position.0);
+            let message = ServerMessage::CharacterDespawned(character);
+            visible_characters.send(server, &[character], &[], &message);
+        }
+    }
+
+    server.broadcast(&ServerMessage::PlayerDisconnected(client_id));
+}
+
 fn handle_reliable_messages(
-    mut server: ResMut<RenetServer>,
+    mut server: Replication,
     players: Res<Players>,
-    mut query: Query<&mut Readiness>,
+    mut query: Query<(&mut Readiness, &mut LevelLoaded, Option<&Squad>, Option<&CurrentPlayer>)>,
     mut app_state: ResMut<State<ServerState>>,
-    mut level_loaded_query: Query<&mut LevelLoaded>,
+    mut move_requests: EventWriter<MoveRequest>,
+    mut attack_requests: EventWriter<AttackRequest>,
+    mut altitude_requests: EventWriter<AltitudeRequest>,
+    mut spell_requests: EventWriter<CastSpellRequest>,
+    mut item_requests: EventWriter<ItemRequest>,
+    mut spend_xp_requests: EventWriter<SpendXpRequest>,
+    mut placement_requests: EventWriter<PlacementRequest>,
+    mut return_requests: EventWriter<ReturnToLobbyRequest>,
+    mut squad_submissions: EventWriter<SquadSubmission>,
+    mut snapshot_requests: EventWriter<SnapshotRequest>,
+    mut session_query: Query<&mut Session>,
 ) {
+    let now = Instant::now();
     for client_id in server.clients_id().into_iter() {
         if let Some(&entity) = players.get(&client_id) {
+            // the session of a new client is only inserted once the commands are applied,
+            // its messages wait in the channel until the next frame
+            let mut session = match session_query.get_mut(entity) {
+                Ok(session) => session,
+                Err(_) => continue,
+            };
             while let Some(message) = server.receive_message(client_id, DefaultChannel::Reliable) {
-                let client_message: ClientMessage = bincode::deserialize(&message).unwrap();
+                if session.kicked.is_some() {
+                    continue;
+                }
+                let client_message = match session.receive(&message, now, true) {
+                    Ok(Some(client_message)) => client_message,
+                    Ok(None) => continue,
+                    Err(error) => {
+                        session.kick(&mut server, client_id, error);
+                        continue;
+                    }
+                };
                 match client_message {
                     ClientMessage::ClientReady => {
-                        let mut readiness = query.get_mut(entity).unwrap();
+                        let (mut readiness, _, squad, _) = query.get_mut(entity).unwrap();
+                        if !readiness.0 && squad.is_none() {
+                            info!("Player {} has no squad to ready up with", client_id);
+                            let message = ServerMessage::SquadRejected(SquadError::NoSquad);
+                            server.send(client_id, &message);
+                            continue;
+                        }
                         readiness.0 = !readiness.0;
                         info!(
                             "Player {} reports {}readiness",
@@ -109,14 +288,92 @@ fn handle_reliable_messages(
                         );
                     }
                     ClientMessage::EndTurn => {
-                        app_state.set(ServerState::NextTurn).unwrap();
-                        return;
+                        let (.., current_player) = query.get(entity).unwrap();
+                        if current_player.is_none() {
+                            info!("Player {} can't end the turn of another player", client_id);
+                            continue;
+                        }
+                        if app_state.current() == &ServerState::PlayerTurn {
+                            end_turn(&mut app_state);
+                            return;
+                        }
                     }
                     ClientMessage::LevelLoaded => {
-                        let mut level_loaded = level_loaded_query.get_mut(entity).unwrap();
+                        let (_, mut level_loaded, ..) = query.get_mut(entity).unwrap();
                         level_loaded.0 = true;
                         info!("Player {} reports level loaded", client_id,);
                     }
+                    ClientMessage::MoveCharacter(character, target) => {
+                        move_requests.send(MoveRequest {
+                            client_id,
+                            character,
+                            target,
+                        });
+                    }
+                    ClientMessage::Attack(attacker, defender, attack_type) => {
+                        attack_requests.send(AttackRequest {
+                            client_id,
+                            attacker,
+                            defender,
+                            attack_type,
+                        });
+                    }
+                    ClientMessage::CastSpell(caster, spell, target) => {
+                        spell_requests.send(CastSpellRequest {
+                            client_id,
+                            caster,
+                            spell,
+                            target,
+                        });
+                    }
+                    ClientMessage::UseItem(character, item) => {
+                        item_requests.send(ItemRequest {
+                            client_id,
+                            character,
+                            action: ItemAction::Use(item),
+                        });
+                    }
+                    ClientMessage::EquipItem(character, item) => {
+                        item_requests.send(ItemRequest {
+                            client_id,
+                            character,
+                            action: ItemAction::Equip(item),
+                        });
+                    }
+                    ClientMessage::UnequipItem(character, slot) => {
+                        item_requests.send(ItemRequest {
+                            client_id,
+                            character,
+                            action: ItemAction::Unequip(slot),
+                        });
+                    }
+                    ClientMessage::SpendXp(character, skill) => {
+                        spend_xp_requests.send(SpendXpRequest {
+                            client_id,
+                            character,
+                            skill,
+                        });
+                    }
+                    ClientMessage::ChangeAltitude(character, altitude) => {
+                        altitude_requests.send(AltitudeRequest {
+                            client_id,
+                            character,
+                            altitude,
+                        });
+                    }
+                    ClientMessage::PlaceCharacter(target) => {
+                        placement_requests.send(PlacementRequest { client_id, target });
+                    }
+                    ClientMessage::SubmitSquad(squad) => {
+                        squad_submissions.send(SquadSubmission { client_id, squad });
+                    }
+                    ClientMessage::ReturnToLobby => {
+                        return_requests.send(ReturnToLobbyRequest { client_id });
+                    }
+                    ClientMessage::RequestResync => {
+                        info!("Player {} lost track of the game", client_id);
+                        snapshot_requests.send(SnapshotRequest { client_id });
+                    }
                     _ => (),
                 }
             }
@@ -125,15 +382,31 @@ fn handle_reliable_messages(
 }
 
 fn handle_unreliable_messages(
-    mut server: ResMut<RenetServer>,
+    mut server: Replication,
     players: Res<Players>,
     mut commands: Commands,
+    mut session_query: Query<&mut Session>,
 ) {
+    let now = Instant::now();
     for client_id in server.clients_id().into_iter() {
         if let Some(&entity) = players.get(&client_id) {
+            let mut session = match session_query.get_mut(entity) {
+                Ok(session) => session,
+                Err(_) => continue,
+            };
             while let Some(message) = server.receive_message(client_id, DefaultChannel::Unreliable)
             {
-                let client_message: ClientMessage = bincode::deserialize(&message).unwrap();
+                if session.kicked.is_some() {
+                    continue;
+                }
+                let client_message = match session.receive(&message, now, false) {
+                    Ok(Some(client_message)) => client_message,
+                    Ok(None) => continue,
+                    Err(error) => {
+                        session.kick(&mut server, client_id, error);
+                        continue;
+                    }
+                };
                 match client_message {
                     _ => (),
                 }
@@ -144,12 +417,16 @@ fn handle_unreliable_messages(
 
 
 /// Runs once when PlayerTurn is entered
+///
+/// Hands the turn to the next player and refills the action points of their characters
 fn handle_new_turn(
-    mut server: ResMut<RenetServer>,
+    mut server: Replication,
     mut turn_order: ResMut<TurnOrder>,
     players: Res<Players>,
+    visible_characters: Res<VisibleCharacters>,
     mut commands: Commands,
     query: Query<(Entity, &CurrentPlayer)>,
+    mut character_query: Query<(Entity, &Owner, &Special, &mut ActionPoints), With<Character>>,
 ) {
     info!("handling new turn");
     if let Some(next_player) = turn_order.order.pop_front() {
@@ -163,13 +440,32 @@ fn handle_new_turn(
         if let Some(entity) = players.players.get(&next_player) {
             commands.entity(*entity).insert(CurrentPlayer(next_player));
         }
-        let message = bincode::serialize(&ServerMessage::PlayerTurn(next_player)).unwrap();
-        server.broadcast_message(DefaultChannel::Reliable, message);
+        server.broadcast(&ServerMessage::PlayerTurn(next_player));
+
+        for (character, owner, special, mut action_points) in &mut character_query {
+            if owner.0 == next_player {
+                action_points.refill(special);
+                visible_characters.send(
+                    &mut server,
+                    &[character],
+                    &[owner.0],
+                    &ServerMessage::ActionPointsChanged(character, *action_points),
+                );
+            }
+        }
     } else {
         error!("Turn order is empty");
     }
 }
 
+/// Passes the turn on, unless another state change like the end of the match is already queued
+pub(crate) fn end_turn(app_state: &mut State<ServerState>) {
+    match app_state.set(ServerState::NextTurn) {
+        Ok(()) | Err(StateError::StateAlreadyQueued) => (),
+        Err(error) => error!("Couldn't end the turn: {}", error),
+    }
+}
+
 fn next_turn(mut app_state: ResMut<State<ServerState>>) {
     app_state.set(ServerState::PlayerTurn).unwrap();
 }
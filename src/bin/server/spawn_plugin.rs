@@ -1,40 +1,315 @@
+use std::collections::HashMap;
+
 use bevy::prelude::*;
-use bevy_renet::renet::{DefaultChannel, RenetServer};
+use bevy_turborand::prelude::*;
 use fallout_equestria_tactics::{
-    common::{Player, Spawnpoint},
-    map::AxialCoordinates,
+    action_points::ActionError,
+    character::{CharacterBundle, CharacterData, CHARACTERS_PER_PLAYER},
+    common::{Player, Spawnpoint, Special},
+    inventory::{find_item, Inventory, Item},
+    map::{AxialCoordinates, HexLayout, Map},
     messages::ServerMessage,
+    progression::XpLedger,
+    race::{find_race_definition, RaceDefinition},
+    resources::{Players, TurnOrder},
+    squad::{CharacterSheet, Squad},
+    stats::MatchStats,
 };
 
-use crate::common::ServerState;
+use crate::{common::ServerState, replication_plugin::Replication};
+
+/// Characters can be placed on every passable tile up to this many steps away from a spawnpoint
+const SPAWN_ZONE_RADIUS: i32 = 2;
 
+/// Lets the players take turns placing one character at a time in their spawn zone
+///
+/// Once every character is placed, play starts with the placement order reversed
 pub struct SpawnPlugin;
 
 impl Plugin for SpawnPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(
-            SystemSet::on_enter(ServerState::SpawnPhase).with_system(notify_players),
-        );
+        app.add_event::<PlacementRequest>()
+            .add_system_set(
+                SystemSet::on_enter(ServerState::SpawnPhase).with_system(start_placement),
+            )
+            .add_system_set(
+                SystemSet::on_update(ServerState::SpawnPhase).with_system(handle_placements),
+            );
         info!("SpawnPlugin has been loaded");
     }
 }
 
-/// Notifies players of their spawnpoint on the Default Reliable channel
-fn notify_players(
-    query: Query<&Transform, With<Spawnpoint>>,
-    mut player_query: Query<&Player>,
-    mut server: ResMut<RenetServer>,
+/// A client wants to place its next character on `target`
+pub struct PlacementRequest {
+    pub client_id: u64,
+    pub target: AxialCoordinates,
+}
+
+#[derive(Resource)]
+pub(crate) struct Placement {
+    /// Random order in which the players place their characters
+    order: Vec<u64>,
+    /// Index into `order` of the player placing next
+    current: usize,
+    zones: HashMap<u64, Vec<AxialCoordinates>>,
+    placed: HashMap<u64, usize>,
+}
+
+impl Placement {
+    pub(crate) fn current_player(&self) -> Option<u64> {
+        self.order.get(self.current).copied()
+    }
+
+    pub(crate) fn zone(&self, player: u64) -> &[AxialCoordinates] {
+        self.zones.get(&player).map_or(&[], |zone| zone)
+    }
+
+    fn has_characters_left(&self, player: u64) -> bool {
+        self.placed.get(&player).copied().unwrap_or(0) < CHARACTERS_PER_PLAYER
+            && self
+                .zones
+                .get(&player)
+                .map_or(false, |zone| !zone.is_empty())
+    }
+
+    fn in_zone(&self, player: u64, coordinates: AxialCoordinates) -> bool {
+        self.zones.get(&player).map_or(false, |zone| {
+            zone.iter()
+                .any(|c| c.q == coordinates.q && c.r == coordinates.r)
+        })
+    }
+
+    /// Hands the placement to the next player with characters left, starting with the current one if `include_current`
+    ///
+    /// Returns false once everybody is done
+    fn advance(&mut self, include_current: bool) -> bool {
+        if self.order.is_empty() {
+            return false;
+        }
+        let skip = if include_current { 0 } else { 1 };
+        for offset in skip..=self.order.len() {
+            let index = (self.current + offset) % self.order.len();
+            if self.has_characters_left(self.order[index]) {
+                self.current = index;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Rolls the turn order, hands out the spawn zones and starts the first placement turn
+fn start_placement(
+    mut commands: Commands,
+    spawnpoint_query: Query<&Transform, With<Spawnpoint>>,
+    player_query: Query<(&Player, &Name)>,
+    mut server: Replication,
+    mut global_rng: ResMut<GlobalRng>,
+    layout: Res<HexLayout>,
+    map: Res<Map>,
+) {
+    // sorted first, so the order only depends on the rng
+    let mut order: Vec<u64> = player_query.iter().map(|(player, _)| player.0).collect();
+    order.sort();
+    global_rng.shuffle(&mut order);
+    info!("Placement order is {:?}", order);
+
+    let mut spawnpoints: Vec<AxialCoordinates> = spawnpoint_query
+        .iter()
+        .map(|transform| AxialCoordinates::from_world(transform.translation, &layout))
+        .collect();
+    spawnpoints.sort_by_key(|c| (c.q, c.r));
+    if spawnpoints.len() < order.len() {
+        error!(
+            "Level has {} spawnpoints for {} players",
+            spawnpoints.len(),
+            order.len()
+        );
+    }
+
+    let mut zones = HashMap::new();
+    for (&player, spawnpoint) in order.iter().zip(spawnpoints) {
+        let zone: Vec<AxialCoordinates> = map
+            .tiles()
+            .filter(|tile| {
+                tile.coordinates.distance(spawnpoint) <= SPAWN_ZONE_RADIUS
+                    && tile.movement_cost().is_some()
+                    && tile.occupant.is_none()
+            })
+            .map(|tile| tile.coordinates)
+            .collect();
+        info!(
+            "Assigning spawn zone of {} tiles around {:?} to {}",
+            zone.len(),
+            spawnpoint,
+            player
+        );
+        server.send(player, &ServerMessage::AssignSpawnZone(zone.clone()));
+        zones.insert(player, zone);
+    }
+
+    let mut placement = Placement {
+        order,
+        current: 0,
+        zones,
+        placed: HashMap::new(),
+    };
+    if placement.advance(true) {
+        let player = placement.current_player().unwrap();
+        server.broadcast(&ServerMessage::PlacementTurn(player));
+    }
+    commands.insert_resource(placement);
+
+    let mut stats = MatchStats::new();
+    for (player, name) in &player_query {
+        stats.add_player(player.0, name);
+    }
+    commands.insert_resource(stats);
+}
+
+/// Places the characters of the player whose turn it is and passes the placement on
+fn handle_placements(
+    mut commands: Commands,
+    mut placement_requests: EventReader<PlacementRequest>,
+    placement: Option<ResMut<Placement>>,
+    mut server: Replication,
+    mut map: ResMut<Map>,
+    mut turn_order: ResMut<TurnOrder>,
+    mut app_state: ResMut<State<ServerState>>,
+    players: Res<Players>,
+    race_definitions: Res<Assets<RaceDefinition>>,
+    items: Res<Assets<Item>>,
+    squad_query: Query<&Squad, With<Player>>,
 ) {
-    info!("assigning spawn points");
-    let mut player_iter = player_query.iter_mut();
-    for transform in &query {
-        info!("assigning spawn point {:?}", transform);
-        if let Some(player) = player_iter.next() {
-            info!("assigning spawn point {:?} to {}", transform, player.0);
-            let axial_coordinates = AxialCoordinates::from_world(transform.translation);
-            let message =
-                bincode::serialize(&ServerMessage::AssignSpawnpoint(axial_coordinates)).unwrap();
-            server.send_message(player.0, DefaultChannel::Reliable, message);
+    // inserted by commands on enter, so it may only be there from the next frame on
+    let mut placement = match placement {
+        Some(placement) => placement,
+        None => return,
+    };
+
+    // players who left during placement can't hold it up
+    let current = placement.current_player();
+    placement
+        .order
+        .retain(|player| players.players.contains_key(player));
+    match current.and_then(|current| placement.order.iter().position(|&p| p == current)) {
+        Some(index) => placement.current = index,
+        None if current.is_some() => {
+            placement.current = placement
+                .current
+                .min(placement.order.len().saturating_sub(1));
+            if placement.advance(true) {
+                let next = placement.current_player().unwrap();
+                server.broadcast(&ServerMessage::PlacementTurn(next));
+            }
+        }
+        None => (),
+    }
+
+    for request in placement_requests.iter() {
+        let player = request.client_id;
+        let result = if placement.current_player() != Some(player) {
+            Err(ActionError::NotYourTurn)
+        } else if !placement.has_characters_left(player) {
+            Err(ActionError::NothingToPlace)
+        } else if !placement.in_zone(player, request.target) {
+            Err(ActionError::NotInSpawnZone)
+        } else if map
+            .get(request.target)
+            .map_or(true, |tile| tile.occupant.is_some())
+        {
+            Err(ActionError::TileOccupied)
+        } else {
+            Ok(())
+        };
+        if let Err(error) = result {
+            info!("Rejecting placement of {}: {:?}", player, error);
+            server.send(player, &ServerMessage::PlacementRejected(error));
+            continue;
+        }
+
+        let index = placement.placed.get(&player).copied().unwrap_or(0);
+        // readiness requires an accepted squad, the default one is only a safety net
+        let sheet = players
+            .get(&player)
+            .and_then(|&entity| squad_query.get(entity).ok())
+            .map_or_else(Squad::default, |squad| squad.clone())
+            .characters
+            .swap_remove(index);
+        let position = map.get(request.target).unwrap().coordinates;
+        let name = sheet.name.clone();
+        let mut bundle = CharacterBundle::new(&name, player, sheet.race, sheet.special, position);
+        match find_race_definition(&race_definitions, sheet.race) {
+            Some(definition) => bundle = bundle.with_race_definition(definition),
+            None => warn!(
+                "No definition for {:?} loaded, using the base SPECIAL",
+                sheet.race
+            ),
+        }
+        let entity = commands.spawn_empty().id();
+        map.occupy(position, entity);
+        let data = CharacterData::from_bundle(entity, &bundle);
+        let inventory = starting_inventory(&items, &sheet, &bundle.special);
+        // enemies learn about it once it enters their vision, but never see its inventory or XP
+        server.send(player, &ServerMessage::CharacterSpawned(data));
+        server.send(
+            player,
+            &ServerMessage::InventoryChanged(entity, inventory.clone()),
+        );
+        server.send(
+            player,
+            &ServerMessage::ExperienceChanged(entity, XpLedger::default()),
+        );
+        commands
+            .entity(entity)
+            .insert(bundle)
+            .insert(inventory)
+            .insert(XpLedger::default());
+        placement.placed.insert(player, index + 1);
+        info!("{} placed {} at {:?}", player, name, position);
+
+        if placement.advance(false) {
+            let next = placement.current_player().unwrap();
+            server.broadcast(&ServerMessage::PlacementTurn(next));
+        } else {
+            break;
+        }
+    }
+
+    if !placement.advance(true) {
+        commands.remove_resource::<Placement>();
+        // there is no turn to give, the game over shuts the server down once nobody is left
+        if placement.order.is_empty() {
+            info!("Every player left during placement");
+            app_state.set(ServerState::GameOver).unwrap();
+            return;
+        }
+        info!("All characters are placed");
+        turn_order.order = placement.order.iter().rev().copied().collect();
+        app_state.set(ServerState::PlayerTurn).unwrap();
+    }
+}
+
+/// Packs the loadout of the character sheet and equips whatever can be equipped
+///
+/// The loadout was validated with the squad, so everything should fit into the bags
+fn starting_inventory(
+    items: &Assets<Item>,
+    sheet: &CharacterSheet,
+    special: &Special,
+) -> Inventory {
+    let mut inventory = Inventory::default();
+    for name in &sheet.loadout {
+        let item = match find_item(items, name) {
+            Some(item) => item,
+            None => {
+                warn!("Starting item {} isn't loaded", name);
+                continue;
+            }
+        };
+        if inventory.add(item, items, special).is_ok() && item.kind.slot().is_some() {
+            inventory.equip(item).unwrap();
         }
     }
+    inventory
 }
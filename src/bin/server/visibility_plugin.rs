@@ -0,0 +1,165 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use fallout_equestria_tactics::{
+    action_points::ActionPoints,
+    character::{BaseSpecial, Character, CharacterData, Health, Owner, Position},
+    common::{Player, Race, Special},
+    flight::Altitude,
+    map::Map,
+    messages::ServerMessage,
+    visibility::sight_range,
+};
+
+use crate::{reconnect_plugin::Absent, replication_plugin::Replication};
+
+/// Keeps track of which enemy characters every player can see
+///
+/// Players always know their own characters, enemies are only replicated while in sight
+pub struct VisibilityPlugin;
+
+impl Plugin for VisibilityPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(VisibleCharacters::default())
+            // runs after the commands of the action systems, so moved and killed characters are up to date
+            .add_system_to_stage(CoreStage::PostUpdate, update_visibility);
+        info!("VisibilityPlugin has been loaded");
+    }
+}
+
+/// Enemy characters each client currently sees
+#[derive(Default, Resource)]
+pub struct VisibleCharacters {
+    visible: HashMap<u64, HashSet<Entity>>,
+}
+
+impl VisibleCharacters {
+    pub fn sees(&self, client_id: u64, character: Entity) -> bool {
+        self.visible
+            .get(&client_id)
+            .map_or(false, |visible| visible.contains(&character))
+    }
+
+    /// Returns all clients that know about at least one of `characters`
+    ///
+    /// `owners` are the players controlling them, they always know their own characters
+    pub fn observers(&self, characters: &[Entity], owners: &[u64]) -> HashSet<u64> {
+        let mut observers: HashSet<u64> = owners.iter().copied().collect();
+        for (&client_id, visible) in &self.visible {
+            if characters
+                .iter()
+                .any(|character| visible.contains(character))
+            {
+                observers.insert(client_id);
+            }
+        }
+        observers
+    }
+
+    /// Sends `message` to every client that knows about one of `characters`
+    pub fn send(
+        &self,
+        server: &mut Replication,
+        characters: &[Entity],
+        owners: &[u64],
+        message: &ServerMessage,
+    ) {
+        for client_id in self.observers(characters, owners) {
+            server.send(client_id, message);
+        }
+    }
+}
+
+pub(crate) type CharacterComponents<'a> = (
+    Entity,
+    &'a Name,
+    &'a Owner,
+    &'a Race,
+    &'a Special,
+    &'a Position,
+    &'a Altitude,
+    &'a ActionPoints,
+    &'a Health,
+);
+
+/// Everything a client needs to know about a character to show it
+pub(crate) fn character_data(
+    (entity, name, owner, race, special, position, altitude, action_points, health): CharacterComponents,
+    base_special_query: &Query<&BaseSpecial>,
+) -> CharacterData {
+    CharacterData {
+        entity,
+        name: name.to_string(),
+        owner: owner.0,
+        race: *race,
+        base_special: base_special_query.get(entity).map_or(*special, |base| base.0),
+        special: *special,
+        position: position.0,
+        altitude: *altitude,
+        action_points: *action_points,
+        health: *health,
+    }
+}
+
+/// Recomputes what every player sees whenever a character spawned, moved or died
+/// and tells the clients which enemies entered or left their vision
+///
+/// Absent players see nothing, once they return they are told about every enemy in sight again
+pub(crate) fn update_visibility(
+    mut server: Replication,
+    mut visible_characters: ResMut<VisibleCharacters>,
+    map: Res<Map>,
+    player_query: Query<&Player, Without<Absent>>,
+    character_query: Query<CharacterComponents, With<Character>>,
+    base_special_query: Query<&BaseSpecial>,
+    changed_query: Query<(), (With<Character>, Or<(Added<Character>, Changed<Position>)>)>,
+    // absent players are forgotten right away, so they are told about everything on return
+    added_player_query: Query<(), Or<(Added<Player>, Added<Absent>)>>,
+    removed: RemovedComponents<Character>,
+    returned: RemovedComponents<Absent>,
+) {
+    if changed_query.is_empty()
+        && added_player_query.is_empty()
+        && removed.iter().next().is_none()
+        && returned.iter().next().is_none()
+    {
+        return;
+    }
+
+    let mut visible = HashMap::new();
+    for player in &player_query {
+        let own: Vec<(&Position, &Special)> = character_query
+            .iter()
+            .filter(|(_, _, owner, ..)| owner.0 == player.0)
+            .map(|(_, _, _, _, special, position, ..)| (position, special))
+            .collect();
+        let seen: HashSet<Entity> = character_query
+            .iter()
+            .filter(|(_, _, owner, ..)| owner.0 != player.0)
+            .filter(|(_, _, _, _, _, target, ..)| {
+                own.iter().any(|(position, special)| {
+                    map.can_see(position.0, target.0, sight_range(special))
+                })
+            })
+            .map(|(entity, ..)| entity)
+            .collect();
+        visible.insert(player.0, seen);
+    }
+
+    for (&client_id, seen) in &visible {
+        let previous = visible_characters.visible.get(&client_id);
+        for &entity in seen {
+            if previous.map_or(false, |previous| previous.contains(&entity)) {
+                continue;
+            }
+            let data = character_data(character_query.get(entity).unwrap(), &base_special_query);
+            server.send(client_id, &ServerMessage::EnteredVision(data));
+        }
+        for &entity in previous.into_iter().flatten() {
+            if !seen.contains(&entity) {
+                server.send(client_id, &ServerMessage::LeftVision(entity));
+            }
+        }
+    }
+    visible_characters.visible = visible;
+}
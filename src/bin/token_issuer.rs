@@ -0,0 +1,77 @@
+use std::{
+    env::args,
+    net::TcpListener,
+    path::Path,
+    process::exit,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use fallout_equestria_tactics::auth::{
+    generate_private_key, AuthConfig, AuthError, TokenIssuer, DEFAULT_AUTH_CONFIG,
+};
+
+/// Hands out connect tokens to clients on localhost, signed with the private key of the config
+///
+/// The first run creates the config with a new private key, start the server only after that.
+/// Run with `--generate-key` to replace the key of the config with a new one, any other argument
+/// is taken as the path of the config
+fn main() {
+    let argument = args().nth(1);
+    let generate_key = argument.as_deref() == Some("--generate-key");
+    let config_path = match argument {
+        Some(path) if !generate_key => path,
+        _ => DEFAULT_AUTH_CONFIG.to_string(),
+    };
+    if generate_key || !Path::new(&config_path).exists() {
+        // keeps the address of an existing config
+        let config = AuthConfig::load(&config_path)
+            .map(|config| AuthConfig {
+                private_key: generate_private_key(),
+                ..config
+            })
+            .unwrap_or_else(|_| AuthConfig::generate());
+        if let Err(error) = config.save(&config_path) {
+            eprintln!("Couldn't write {}: {}", config_path, error);
+            exit(1);
+        }
+        println!(
+            "Wrote a new private key to {}, restart the server to use it",
+            config_path
+        );
+        if generate_key {
+            return;
+        }
+    }
+    let (config, private_key) = match AuthConfig::load(&config_path)
+        .and_then(|config| config.private_key().map(|key| (config, key)))
+    {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("Couldn't load {}: {}", config_path, error);
+            exit(1);
+        }
+    };
+    let listener = match TcpListener::bind(config.issuer_address) {
+        Ok(listener) => listener,
+        Err(error) => {
+            eprintln!("Couldn't listen on {}: {}", config.issuer_address, error);
+            exit(1);
+        }
+    };
+    println!("Issuing connect tokens on {}", config.issuer_address);
+
+    let mut issuer = TokenIssuer::new(private_key, current_time());
+    for stream in listener.incoming() {
+        let result = stream
+            .map_err(AuthError::from)
+            .and_then(|mut stream| issuer.serve(&mut stream, current_time()));
+        match result {
+            Ok(client_id) => println!("Issued a token for client {}", client_id),
+            Err(error) => eprintln!("Refused a token request: {}", error),
+        }
+    }
+}
+
+fn current_time() -> std::time::Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+}
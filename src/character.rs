@@ -0,0 +1,147 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    action_points::ActionPoints,
+    common::{Race, Special},
+    flight::Altitude,
+    map::AxialCoordinates,
+    race::RaceDefinition,
+};
+
+/// Number of characters every player controls
+pub const CHARACTERS_PER_PLAYER: usize = 4;
+
+#[derive(Component)]
+pub struct Character;
+
+/// Id of the player controlling the character
+#[derive(Clone, Component, Copy, Debug, Deref, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Owner(pub u64);
+
+/// SPECIAL of the character before racial modifiers, the [`Special`] component holds the derived stats
+#[derive(Clone, Component, Copy, Debug, Deref, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BaseSpecial(pub Special);
+
+/// Tile the character stands on
+#[derive(Clone, Component, Copy, Debug, Deref, DerefMut, Deserialize, PartialEq, Serialize)]
+pub struct Position(pub AxialCoordinates);
+
+#[derive(Clone, Component, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Health {
+    pub current: u32,
+    pub max: u32,
+}
+
+impl Health {
+    /// Max health is 15 + Strength + 2 * Endurance
+    pub fn from_special(special: &Special) -> Self {
+        let max = 15 + special.strength as u32 + 2 * special.endurance as u32;
+        Self { current: max, max }
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.current > 0
+    }
+
+    pub fn take_damage(&mut self, damage: u32) {
+        self.current = self.current.saturating_sub(damage);
+    }
+
+    pub fn heal(&mut self, amount: u32) {
+        self.current = (self.current + amount).min(self.max);
+    }
+}
+
+#[derive(Bundle)]
+pub struct CharacterBundle {
+    pub character: Character,
+    pub name: Name,
+    pub owner: Owner,
+    pub race: Race,
+    pub base_special: BaseSpecial,
+    pub special: Special,
+    pub position: Position,
+    pub altitude: Altitude,
+    pub action_points: ActionPoints,
+    pub health: Health,
+}
+
+impl CharacterBundle {
+    pub fn new(
+        name: &str,
+        owner: u64,
+        race: Race,
+        special: Special,
+        position: AxialCoordinates,
+    ) -> Self {
+        Self {
+            character: Character,
+            name: Name::from(name),
+            owner: Owner(owner),
+            race,
+            base_special: BaseSpecial(special),
+            special,
+            position: Position(position),
+            altitude: Altitude::default(),
+            action_points: ActionPoints::from_special(&special),
+            health: Health::from_special(&special),
+        }
+    }
+
+    /// Applies the racial modifiers to the base SPECIAL, along with everything derived from it
+    pub fn with_race_definition(mut self, definition: &RaceDefinition) -> Self {
+        self.special = definition.derive_special(&self.base_special);
+        self.action_points = ActionPoints::from_special(&self.special);
+        self.health = Health::from_special(&self.special);
+        self
+    }
+}
+
+/// Everything a client needs to know to spawn a character
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CharacterData {
+    /// The character entity on the server
+    pub entity: Entity,
+    pub name: String,
+    pub owner: u64,
+    pub race: Race,
+    pub base_special: Special,
+    pub special: Special,
+    pub position: AxialCoordinates,
+    pub altitude: Altitude,
+    pub action_points: ActionPoints,
+    pub health: Health,
+}
+
+impl CharacterData {
+    pub fn from_bundle(entity: Entity, bundle: &CharacterBundle) -> Self {
+        Self {
+            entity,
+            name: bundle.name.to_string(),
+            owner: bundle.owner.0,
+            race: bundle.race,
+            base_special: bundle.base_special.0,
+            special: bundle.special,
+            position: bundle.position.0,
+            altitude: bundle.altitude,
+            action_points: bundle.action_points,
+            health: bundle.health,
+        }
+    }
+
+    pub fn to_bundle(&self) -> CharacterBundle {
+        CharacterBundle {
+            character: Character,
+            name: Name::from(self.name.clone()),
+            owner: Owner(self.owner),
+            race: self.race,
+            base_special: BaseSpecial(self.base_special),
+            special: self.special,
+            position: Position(self.position),
+            altitude: self.altitude,
+            action_points: self.action_points,
+            health: self.health,
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use bevy::prelude::*;
+use bevy_turborand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    action_points::ActionError, character::Health, common::Special, line_of_sight::LineOfSight,
+};
+
+/// Chance to hit never drops below or exceeds these bounds, so every attack stays a gamble
+const MIN_HIT_CHANCE: f64 = 0.05;
+const MAX_HIT_CHANCE: f64 = 0.95;
+
+/// Hit chance lost when the defender is in partial cover
+const PARTIAL_COVER_PENALTY: f64 = 0.2;
+
+/// Damage of a critical hit is multiplied by this
+const CRITICAL_MULTIPLIER: u32 = 2;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum AttackType {
+    /// Hooves and teeth, only against adjacent characters
+    Melee,
+    /// Guns and other projectiles
+    Ranged,
+}
+
+impl AttackType {
+    /// Action points an attack of this type costs
+    pub fn cost(&self) -> u32 {
+        match self {
+            AttackType::Melee => 3,
+            AttackType::Ranged => 4,
+        }
+    }
+
+    /// Farthest distance in tiles an attack of this type can reach
+    pub fn range(&self) -> i32 {
+        match self {
+            AttackType::Melee => 1,
+            AttackType::Ranged => 10,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum AttackOutcome {
+    Miss,
+    Hit { damage: u32 },
+    Critical { damage: u32 },
+}
+
+impl AttackOutcome {
+    pub fn damage(&self) -> u32 {
+        match self {
+            AttackOutcome::Miss => 0,
+            AttackOutcome::Hit { damage } | AttackOutcome::Critical { damage } => *damage,
+        }
+    }
+
+    /// Adds the weapon `bonus` to a hit and takes the armour `protection` off, a hit always deals at least 1 damage
+    pub fn with_equipment(self, bonus: u32, protection: u32) -> Self {
+        let adjust = |damage: u32| (damage + bonus).saturating_sub(protection).max(1);
+        match self {
+            AttackOutcome::Miss => AttackOutcome::Miss,
+            AttackOutcome::Hit { damage } => AttackOutcome::Hit {
+                damage: adjust(damage),
+            },
+            AttackOutcome::Critical { damage } => AttackOutcome::Critical {
+                damage: adjust(damage),
+            },
+        }
+    }
+}
+
+/// Everything clients need to know about a resolved attack
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct AttackResult {
+    /// Server entities of the characters involved
+    pub attacker: Entity,
+    pub defender: Entity,
+    pub attack_type: AttackType,
+    pub outcome: AttackOutcome,
+    /// Health of the defender after the attack
+    pub health: Health,
+}
+
+impl AttackResult {
+    pub fn is_lethal(&self) -> bool {
+        !self.health.is_alive()
+    }
+}
+
+/// Chance between 0 and 1 that an attack over `distance` tiles hits
+///
+/// Perception of the attacker improves accuracy, Agility of the defender lets them dodge.
+/// Ranged attacks get less accurate the farther away the target is and against targets in cover
+pub fn hit_chance(
+    attacker: &Special,
+    defender: &Special,
+    attack_type: AttackType,
+    distance: i32,
+    cover: LineOfSight,
+) -> f64 {
+    let base = match attack_type {
+        AttackType::Melee => 0.6,
+        AttackType::Ranged => 0.5 - 0.03 * (distance - 1).max(0) as f64,
+    };
+    let cover = match (attack_type, cover) {
+        (AttackType::Ranged, LineOfSight::PartialCover) => PARTIAL_COVER_PENALTY,
+        _ => 0.0,
+    };
+    (base + 0.05 * attacker.perception as f64 - 0.03 * defender.agility as f64 - cover)
+        .clamp(MIN_HIT_CHANCE, MAX_HIT_CHANCE)
+}
+
+/// Chance between 0 and 1 that a hit is critical, 2% per point of Luck
+pub fn critical_chance(attacker: &Special) -> f64 {
+    (0.02 * attacker.luck as f64).min(1.0)
+}
+
+/// Rolls an attack, results only depend on the state of `rng` and the arguments
+///
+/// Melee damage scales with Strength, ranged damage is fixed by the weapon
+pub fn resolve_attack(
+    rng: &impl TurboRand,
+    attacker: &Special,
+    defender: &Special,
+    attack_type: AttackType,
+    distance: i32,
+    cover: LineOfSight,
+) -> Result<AttackOutcome, ActionError> {
+    if distance > attack_type.range() {
+        return Err(ActionError::OutOfRange);
+    }
+    if cover == LineOfSight::Blocked {
+        return Err(ActionError::NoLineOfSight);
+    }
+
+    if !rng.chance(hit_chance(attacker, defender, attack_type, distance, cover)) {
+        return Ok(AttackOutcome::Miss);
+    }
+    let damage = match attack_type {
+        AttackType::Melee => 1 + attacker.strength as u32 / 2 + rng.u32(0..=2),
+        AttackType::Ranged => 3 + rng.u32(0..=4),
+    };
+    if rng.chance(critical_chance(attacker)) {
+        Ok(AttackOutcome::Critical {
+            damage: damage * CRITICAL_MULTIPLIER,
+        })
+    } else {
+        Ok(AttackOutcome::Hit { damage })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_rolls_same_outcomes() {
+        let attacker = Special::new();
+        let defender = Special::new();
+        let roll = |seed| {
+            let rng = Rng::with_seed(seed);
+            (0..20)
+                .map(|distance| {
+                    resolve_attack(
+                        &rng,
+                        &attacker,
+                        &defender,
+                        AttackType::Ranged,
+                        distance % 8,
+                        LineOfSight::Clear,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(roll(42), roll(42));
+    }
+
+    #[test]
+    fn rejects_targets_out_of_range() {
+        let rng = Rng::with_seed(1);
+        let special = Special::new();
+        assert_eq!(
+            resolve_attack(
+                &rng,
+                &special,
+                &special,
+                AttackType::Melee,
+                2,
+                LineOfSight::Clear
+            ),
+            Err(ActionError::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn rejects_targets_out_of_sight() {
+        let rng = Rng::with_seed(1);
+        let special = Special::new();
+        assert_eq!(
+            resolve_attack(
+                &rng,
+                &special,
+                &special,
+                AttackType::Ranged,
+                4,
+                LineOfSight::Blocked
+            ),
+            Err(ActionError::NoLineOfSight)
+        );
+    }
+
+    #[test]
+    fn hit_chance_follows_special() {
+        let mut attacker = Special::new();
+        let mut defender = Special::new();
+        let base = hit_chance(
+            &attacker,
+            &defender,
+            AttackType::Ranged,
+            3,
+            LineOfSight::Clear,
+        );
+        assert!(
+            hit_chance(
+                &attacker,
+                &defender,
+                AttackType::Ranged,
+                8,
+                LineOfSight::Clear
+            ) < base
+        );
+        attacker.perception = 9;
+        assert!(
+            hit_chance(
+                &attacker,
+                &defender,
+                AttackType::Ranged,
+                3,
+                LineOfSight::Clear
+            ) > base
+        );
+        assert!(
+            hit_chance(
+                &attacker,
+                &defender,
+                AttackType::Ranged,
+                3,
+                LineOfSight::PartialCover
+            ) < hit_chance(
+                &attacker,
+                &defender,
+                AttackType::Ranged,
+                3,
+                LineOfSight::Clear
+            )
+        );
+        attacker.perception = 0;
+        defender.agility = 10;
+        assert_eq!(
+            hit_chance(
+                &attacker,
+                &defender,
+                AttackType::Ranged,
+                10,
+                LineOfSight::Clear
+            ),
+            MIN_HIT_CHANCE
+        );
+    }
+
+    #[test]
+    fn critical_hits_deal_double_damage() {
+        let mut attacker = Special::new();
+        attacker.luck = 50;
+        let rng = Rng::with_seed(3);
+        let outcomes = (0..20).map(|_| {
+            resolve_attack(
+                &rng,
+                &attacker,
+                &Special::new(),
+                AttackType::Melee,
+                1,
+                LineOfSight::Clear,
+            )
+            .unwrap()
+        });
+        for outcome in outcomes.filter(|outcome| outcome != &AttackOutcome::Miss) {
+            match outcome {
+                AttackOutcome::Critical { damage } => assert!((6..=10).contains(&damage)),
+                outcome => panic!("expected a critical hit, got {:?}", outcome),
+            }
+        }
+    }
+}
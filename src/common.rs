@@ -1,5 +1,6 @@
 use bevy::prelude::*;
 use bevy_renet::renet::NETCODE_USER_DATA_BYTES;
+use serde::{Deserialize, Serialize};
 
 #[derive(Component)]
 pub struct Readiness(pub bool);
@@ -16,6 +17,7 @@ pub struct CurrentPlayer(pub u64);
 #[derive(Component)]
 pub struct ServerEntity(pub Entity);
 
+#[derive(Clone, Component, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Special {
     pub strength: u8,
     pub perception: u8,
@@ -38,6 +40,48 @@ impl Special {
             luck: 5,
         }
     }
+
+    /// Names of the stats, in the order of [`Special::stats`]
+    pub const NAMES: [&'static str; 7] = [
+        "Strength",
+        "Perception",
+        "Endurance",
+        "Charisma",
+        "Intelligence",
+        "Agility",
+        "Luck",
+    ];
+
+    /// All stats in SPECIAL order
+    pub fn stats(&self) -> [u8; 7] {
+        [
+            self.strength,
+            self.perception,
+            self.endurance,
+            self.charisma,
+            self.intelligence,
+            self.agility,
+            self.luck,
+        ]
+    }
+
+    pub fn from_stats(stats: [u8; 7]) -> Self {
+        let [strength, perception, endurance, charisma, intelligence, agility, luck] = stats;
+        Self {
+            strength,
+            perception,
+            endurance,
+            charisma,
+            intelligence,
+            agility,
+            luck,
+        }
+    }
+
+    /// Sum of all stats, as spent in the squad builder
+    pub fn points(&self) -> u32 {
+        self.stats().iter().map(|&stat| stat as u32).sum()
+    }
 }
 
 pub enum TileType {
@@ -45,6 +89,7 @@ pub enum TileType {
     Impassable,
 }
 
+#[derive(Clone, Component, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum Race {
     EarthPony,
     Unicorn,
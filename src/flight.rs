@@ -0,0 +1,63 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::race::{RaceDefinition, FLIGHT};
+
+/// Highest a flying character can rise above the ground, in elevation steps
+pub const MAX_ALTITUDE: i32 = 6;
+
+/// Elevation steps a flying character can rise or sink for a single AP
+pub const HEIGHT_PER_AP: i32 = 2;
+
+/// Height of a character above the ground below it, in elevation steps
+///
+/// Characters with an altitude above 0 are airborne and ignore the terrain below them
+#[derive(
+    Clone, Component, Copy, Debug, Default, Deref, DerefMut, Deserialize, Eq, PartialEq, Serialize,
+)]
+pub struct Altitude(pub i32);
+
+impl Altitude {
+    pub fn is_airborne(&self) -> bool {
+        self.0 > 0
+    }
+}
+
+/// Only races with the [`FLIGHT`] ability have wings to fly with
+pub fn can_fly(definition: &RaceDefinition) -> bool {
+    definition.has_ability(FLIGHT)
+}
+
+/// AP needed to change the altitude from `from` to `to`, 1 AP per [`HEIGHT_PER_AP`] steps or part of it
+pub fn altitude_cost(from: Altitude, to: Altitude) -> u32 {
+    let difference = (to.0 - from.0).abs();
+    ((difference + HEIGHT_PER_AP - 1) / HEIGHT_PER_AP) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Race;
+
+    #[test]
+    fn only_races_with_flight_fly() {
+        let mut pegasus = RaceDefinition {
+            race: Race::Pegasus,
+            modifiers: Default::default(),
+            skill_trees: Vec::new(),
+            abilities: vec![FLIGHT.to_string()],
+        };
+        assert!(can_fly(&pegasus));
+        pegasus.abilities.clear();
+        assert!(!can_fly(&pegasus));
+    }
+
+    #[test]
+    fn one_ap_lifts_two_steps() {
+        assert_eq!(altitude_cost(Altitude(0), Altitude(0)), 0);
+        assert_eq!(altitude_cost(Altitude(0), Altitude(1)), 1);
+        assert_eq!(altitude_cost(Altitude(0), Altitude(2)), 1);
+        assert_eq!(altitude_cost(Altitude(0), Altitude(3)), 2);
+        assert_eq!(altitude_cost(Altitude(5), Altitude(1)), 2);
+    }
+}
@@ -0,0 +1,294 @@
+use bevy::{prelude::*, reflect::TypeUuid};
+use bevy_common_assets::json::JsonAssetPlugin;
+use serde::{Deserialize, Serialize};
+
+use crate::{action_points::ActionError, character::Health, combat::AttackType, common::Special};
+
+/// AP needed to equip or unequip an item, rummaging through the saddlebags takes time
+pub const OPEN_INVENTORY_COST: u32 = 1;
+
+/// AP needed to use a consumable
+pub const USE_ITEM_COST: u32 = 2;
+
+/// Items every character starts the match with
+pub const STARTING_ITEMS: [&str; 3] = ["Healing Potion", "Healing Potion", "Leather Barding"];
+
+/// Loads every item definition from `assets/items/*.item.json`
+pub struct ItemPlugin;
+
+impl Plugin for ItemPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(JsonAssetPlugin::<Item>::new(&["item.json"]))
+            .add_startup_system(load_items);
+        info!("ItemPlugin has been loaded");
+    }
+}
+
+/// Keeps the handles of all item assets, so they stay loaded
+#[derive(Resource)]
+pub struct ItemHandles(pub Vec<HandleUntyped>);
+
+fn load_items(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let handles = asset_server.load_folder("items").unwrap_or_else(|error| {
+        warn!("Couldn't load items: {:?}", error);
+        Vec::new()
+    });
+    commands.insert_resource(ItemHandles(handles));
+}
+
+/// An item as defined in a `.item.json` asset, items are identified by their name
+#[derive(Clone, Debug, Deserialize, TypeUuid)]
+#[uuid = "c3a1e7d2-58f4-4b6a-9e0d-7f2c4b8a1d63"]
+pub struct Item {
+    pub name: String,
+    pub weight: u32,
+    pub kind: ItemKind,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ItemKind {
+    /// Adds `damage` to every hit of attacks of this type
+    Weapon {
+        attack_type: AttackType,
+        damage: u32,
+    },
+    /// Takes `protection` off the damage of every hit, a hit always deals at least 1 damage
+    Armour { protection: u32 },
+    /// Used up on use, healing the character
+    Consumable { heal: u32 },
+}
+
+impl ItemKind {
+    pub fn slot(&self) -> Option<EquipmentSlot> {
+        match self {
+            ItemKind::Weapon { .. } => Some(EquipmentSlot::Weapon),
+            ItemKind::Armour { .. } => Some(EquipmentSlot::Armour),
+            ItemKind::Consumable { .. } => None,
+        }
+    }
+}
+
+/// Looks up the item called `name` among the loaded items
+pub fn find_item<'a>(items: &'a Assets<Item>, name: &str) -> Option<&'a Item> {
+    items
+        .iter()
+        .map(|(_, item)| item)
+        .find(|item| item.name == name)
+}
+
+/// Heaviest load a character with this [`Special`] can carry, 20 plus 10 per point of Strength
+pub fn carry_capacity(special: &Special) -> u32 {
+    20 + 10 * special.strength as u32
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum EquipmentSlot {
+    Weapon,
+    Armour,
+}
+
+/// Everything a character carries, items are referred to by name
+///
+/// Only the server changes inventories, clients only learn about those of their own characters
+#[derive(Clone, Component, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Inventory {
+    /// Carried items that aren't equipped
+    pub items: Vec<String>,
+    pub weapon: Option<String>,
+    pub armour: Option<String>,
+}
+
+impl Inventory {
+    /// Total weight of all items, equipped or not
+    pub fn weight(&self, items: &Assets<Item>) -> u32 {
+        self.items
+            .iter()
+            .chain(self.weapon.iter())
+            .chain(self.armour.iter())
+            .filter_map(|name| find_item(items, name))
+            .map(|item| item.weight)
+            .sum()
+    }
+
+    /// Puts the item into the bags, unless it's too heavy for a character with this [`Special`]
+    pub fn add(
+        &mut self,
+        item: &Item,
+        items: &Assets<Item>,
+        special: &Special,
+    ) -> Result<(), ActionError> {
+        if self.weight(items) + item.weight > carry_capacity(special) {
+            return Err(ActionError::TooHeavy);
+        }
+        self.items.push(item.name.clone());
+        Ok(())
+    }
+
+    /// Takes an unequipped item with this name out of the bags
+    fn take(&mut self, name: &str) -> Result<(), ActionError> {
+        let index = self
+            .items
+            .iter()
+            .position(|item| item == name)
+            .ok_or(ActionError::ItemNotCarried)?;
+        self.items.remove(index);
+        Ok(())
+    }
+
+    fn slot_mut(&mut self, slot: EquipmentSlot) -> &mut Option<String> {
+        match slot {
+            EquipmentSlot::Weapon => &mut self.weapon,
+            EquipmentSlot::Armour => &mut self.armour,
+        }
+    }
+
+    /// Equips the carried `item`, putting whatever was equipped in its slot back into the bags
+    pub fn equip(&mut self, item: &Item) -> Result<(), ActionError> {
+        let slot = item.kind.slot().ok_or(ActionError::CannotEquip)?;
+        self.take(&item.name)?;
+        if let Some(previous) = self.slot_mut(slot).replace(item.name.clone()) {
+            self.items.push(previous);
+        }
+        Ok(())
+    }
+
+    pub fn unequip(&mut self, slot: EquipmentSlot) -> Result<(), ActionError> {
+        let item = self
+            .slot_mut(slot)
+            .take()
+            .ok_or(ActionError::ItemNotCarried)?;
+        self.items.push(item);
+        Ok(())
+    }
+
+    /// Uses up the carried consumable `item` on `health`
+    pub fn use_item(&mut self, item: &Item, health: &mut Health) -> Result<(), ActionError> {
+        let heal = match item.kind {
+            ItemKind::Consumable { heal } => heal,
+            _ => return Err(ActionError::CannotUse),
+        };
+        self.take(&item.name)?;
+        health.heal(heal);
+        Ok(())
+    }
+
+    /// Extra damage of the equipped weapon, if it fits the attack
+    pub fn damage_bonus(&self, items: &Assets<Item>, attack_type: AttackType) -> u32 {
+        match self
+            .weapon
+            .as_ref()
+            .and_then(|name| find_item(items, name))
+            .map(|item| item.kind)
+        {
+            Some(ItemKind::Weapon {
+                attack_type: weapon_type,
+                damage,
+            }) if weapon_type == attack_type => damage,
+            _ => 0,
+        }
+    }
+
+    /// Damage the equipped armour takes off every hit
+    pub fn protection(&self, items: &Assets<Item>) -> u32 {
+        match self
+            .armour
+            .as_ref()
+            .and_then(|name| find_item(items, name))
+            .map(|item| item.kind)
+        {
+            Some(ItemKind::Armour { protection }) => protection,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(name: &str, weight: u32, kind: ItemKind) -> Item {
+        Item {
+            name: name.to_string(),
+            weight,
+            kind,
+        }
+    }
+
+    fn assets() -> Assets<Item> {
+        let mut app = App::new();
+        app.add_plugin(CorePlugin::default())
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<Item>();
+        let mut assets = app.world.remove_resource::<Assets<Item>>().unwrap();
+        assets.add(item("Potion", 1, ItemKind::Consumable { heal: 5 }));
+        assets.add(item(
+            "Pistol",
+            4,
+            ItemKind::Weapon {
+                attack_type: AttackType::Ranged,
+                damage: 2,
+            },
+        ));
+        assets.add(item("Barding", 10, ItemKind::Armour { protection: 1 }));
+        assets.add(item("Power Armour", 60, ItemKind::Armour { protection: 5 }));
+        assets
+    }
+
+    #[test]
+    fn strength_limits_the_load() {
+        let assets = assets();
+        let mut special = Special::new();
+        special.strength = 2;
+        let mut inventory = Inventory::default();
+        let barding = find_item(&assets, "Barding").unwrap();
+        inventory.add(barding, &assets, &special).unwrap();
+        assert_eq!(inventory.weight(&assets), 10);
+        assert_eq!(
+            inventory.add(
+                find_item(&assets, "Power Armour").unwrap(),
+                &assets,
+                &special
+            ),
+            Err(ActionError::TooHeavy)
+        );
+        inventory.equip(barding).unwrap();
+        assert_eq!(inventory.weight(&assets), 10);
+    }
+
+    #[test]
+    fn equipping_swaps_with_the_slot() {
+        let assets = assets();
+        let special = Special::new();
+        let mut inventory = Inventory::default();
+        let pistol = find_item(&assets, "Pistol").unwrap();
+        inventory.add(pistol, &assets, &special).unwrap();
+        inventory.equip(pistol).unwrap();
+        assert_eq!(inventory.weapon.as_deref(), Some("Pistol"));
+        assert!(inventory.items.is_empty());
+        assert_eq!(inventory.damage_bonus(&assets, AttackType::Ranged), 2);
+        assert_eq!(inventory.damage_bonus(&assets, AttackType::Melee), 0);
+        assert_eq!(inventory.equip(pistol), Err(ActionError::ItemNotCarried));
+        inventory.unequip(EquipmentSlot::Weapon).unwrap();
+        assert_eq!(inventory.items, vec!["Pistol".to_string()]);
+    }
+
+    #[test]
+    fn consumables_are_used_up() {
+        let assets = assets();
+        let mut inventory = Inventory::default();
+        let potion = find_item(&assets, "Potion").unwrap();
+        inventory.add(potion, &assets, &Special::new()).unwrap();
+        let mut health = Health {
+            current: 10,
+            max: 12,
+        };
+        inventory.use_item(potion, &mut health).unwrap();
+        assert_eq!(health.current, 12);
+        assert_eq!(
+            inventory.use_item(potion, &mut health),
+            Err(ActionError::ItemNotCarried)
+        );
+        assert_eq!(inventory.equip(potion), Err(ActionError::CannotEquip));
+    }
+}
@@ -1,12 +1,159 @@
-use bevy::{asset::LoadState, prelude::*};
-use bevy_rapier3d::prelude::{Collider, ComputedColliderShape, RapierColliderHandle};
+use std::f32::consts::FRAC_PI_4;
+
+use bevy::{asset::LoadState, gltf::GltfExtras, prelude::*, render::primitives::Aabb};
+use bevy_rapier3d::prelude::{
+    Collider, ComputedColliderShape, QueryFilter, RapierColliderHandle, RapierContext,
+};
 use bevy_scene_hook::{HookedSceneBundle, SceneHook};
+use serde::Deserialize;
 
-use crate::{common::Spawnpoint, resources::LevelName};
+use crate::{
+    common::Spawnpoint,
+    map::{AxialCoordinates, HexLayout, Map, Tile, TileType},
+    resources::LevelName,
+};
 
 #[derive(Resource)]
 pub struct AssetsLoading(pub Vec<HandleUntyped>);
 
+/// Half the edge length of a `Floorplate` mesh in world units
+const FLOORPLATE_HALF_EXTENT: f32 = 1.0;
+
+/// Steepest floor angle in radians a character can still stand on
+const MAX_WALKABLE_SLOPE: f32 = FRAC_PI_4;
+
+/// Name prefixes of level nodes that block the hex they stand on
+const OBSTACLE_PREFIXES: [&str; 6] = ["Barrel", "Column", "CornerWall", "Door", "Reaktor", "Wall"];
+
+/// Marks a level node that contributes tiles to the [`Map`]
+///
+/// Which nodes become tiles is decided by their name:
+/// - `Tile` nodes describe the single hex they stand on
+/// - `Floorplate` nodes cover every hex whose centre lies on the plate
+/// - nodes starting with one of [`OBSTACLE_PREFIXES`] make their hex impassable
+///
+/// Any of these can be adjusted with glTF extras (custom properties in Blender):
+/// `tile_type` (`"passable"` or `"impassable"`), `movement_cost` and `elevation`
+#[derive(Clone, Component, Debug)]
+pub struct TileSource {
+    pub shape: TileShape,
+    pub tile_type: TileType,
+    /// Overrides the elevation derived from the height of the node
+    pub elevation: Option<i32>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum TileShape {
+    /// Only the hex the node stands on
+    Single,
+    /// Every hex whose centre lies within the given half extents around the node
+    Area(Vec2),
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ExtrasTileType {
+    Passable,
+    Impassable,
+}
+
+/// Tile related custom properties of a glTF node
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TileExtras {
+    tile_type: Option<ExtrasTileType>,
+    movement_cost: Option<f32>,
+    elevation: Option<i32>,
+}
+
+impl TileSource {
+    /// Creates the tile source for a node with the given name prefix, if it describes tiles
+    pub fn from_node(prefix: &str, extras: Option<&GltfExtras>) -> Option<Self> {
+        let (shape, tile_type) = match prefix {
+            "Tile" => (TileShape::Single, TileType::Passable(1.0)),
+            "Floorplate" => (
+                TileShape::Area(Vec2::splat(FLOORPLATE_HALF_EXTENT)),
+                TileType::Passable(1.0),
+            ),
+            _ if OBSTACLE_PREFIXES.contains(&prefix) => (TileShape::Single, TileType::Impassable),
+            _ => return None,
+        };
+
+        let extras = match extras.map(|extras| serde_json::from_str::<TileExtras>(&extras.value)) {
+            Some(Ok(extras)) => extras,
+            Some(Err(error)) => {
+                warn!("Ignoring malformed extras on {} node: {}", prefix, error);
+                TileExtras::default()
+            }
+            None => TileExtras::default(),
+        };
+        let movement_cost = extras.movement_cost.unwrap_or(match tile_type {
+            TileType::Passable(cost) => cost,
+            TileType::Impassable => 1.0,
+        });
+        let tile_type = match extras.tile_type {
+            Some(ExtrasTileType::Passable) => TileType::Passable(movement_cost),
+            Some(ExtrasTileType::Impassable) => TileType::Impassable,
+            None => match tile_type {
+                TileType::Passable(_) => TileType::Passable(movement_cost),
+                TileType::Impassable => TileType::Impassable,
+            },
+        };
+
+        Some(Self {
+            shape,
+            tile_type,
+            elevation: extras.elevation,
+        })
+    }
+
+    /// Returns all tiles this source describes when its node is placed at `transform`
+    pub fn tiles(&self, transform: &Transform, layout: &HexLayout) -> Vec<Tile> {
+        let centre = AxialCoordinates::from_world(transform.translation, layout);
+        let elevation = self.elevation.unwrap_or(centre.elevation);
+        match self.shape {
+            TileShape::Single => vec![Tile::new(
+                AxialCoordinates::new(centre.q, centre.r, elevation),
+                self.tile_type,
+            )],
+            TileShape::Area(half_extents) => {
+                // the area lies on the ground, its height doesn't matter
+                let half_extents = half_extents * Vec2::new(transform.scale.x, transform.scale.z);
+                let min = transform.translation - Vec3::new(half_extents.x, 0.0, half_extents.y);
+                let max = transform.translation + Vec3::new(half_extents.x, 0.0, half_extents.y);
+                // every hex touching the area is within this many steps of its centre
+                let radius = (half_extents.max_element() / layout.size).ceil() as i32 + 1;
+
+                let mut tiles = Vec::new();
+                for q in -radius..=radius {
+                    for r in -radius..=radius {
+                        let coordinates =
+                            AxialCoordinates::new(centre.q + q, centre.r + r, elevation);
+                        let world = coordinates.to_world(layout);
+                        // half open, so hexes on the border between two plates aren't claimed twice
+                        if world.x >= min.x
+                            && world.x < max.x
+                            && world.z >= min.z
+                            && world.z < max.z
+                        {
+                            tiles.push(Tile::new(coordinates, self.tile_type));
+                        }
+                    }
+                }
+                tiles
+            }
+        }
+    }
+
+    /// Areas are applied first, so that single tiles placed on them take precedence
+    fn priority(&self) -> u8 {
+        match self.shape {
+            TileShape::Area(_) => 0,
+            TileShape::Single => 1,
+        }
+    }
+}
+
 /// Loads a level and hooks unit components to the entities by name
 pub fn load_level(
     mut commands: Commands,
@@ -30,6 +177,12 @@ pub fn load_level(
                     .map(|t| t.as_str().split('.').collect::<Vec<&str>>()[0])
                 {
                     Some("Spawnpoint") => cmds.insert(Spawnpoint),
+                    Some(prefix) => {
+                        match TileSource::from_node(prefix, entity.get::<GltfExtras>()) {
+                            Some(tile_source) => cmds.insert(tile_source),
+                            None => cmds,
+                        }
+                    }
                     _ => cmds,
                 };
             }),
@@ -39,6 +192,115 @@ pub fn load_level(
     info!("Level {} loaded", level_name.0);
 }
 
+/// Builds the [`Map`] from all [`TileSource`]s of a freshly spawned level
+///
+/// Level nodes are direct children of the scene root, so their [`Transform`] is already in level space
+pub fn build_map(
+    query: Query<(&Transform, &TileSource), Added<TileSource>>,
+    layout: Res<HexLayout>,
+    mut map: ResMut<Map>,
+) {
+    let mut sources: Vec<(&Transform, &TileSource)> = query.iter().collect();
+    if sources.is_empty() {
+        return;
+    }
+    sources.sort_by_key(|(_, tile_source)| tile_source.priority());
+    for (transform, tile_source) in sources {
+        for tile in tile_source.tiles(transform, &layout) {
+            map.insert(tile);
+        }
+    }
+    info!("Map now has {} tiles", map.tiles().count());
+}
+
+/// Raycasts down at every hex centre of the level and stores the floor it hits in the [`Map`]
+///
+/// Hexes without floor or with a floor steeper than [`MAX_WALKABLE_SLOPE`] become impassable.
+/// Tiles already built from [`TileSource`]s keep their type, but take the baked elevation.
+/// Needs all level meshes to have their colliders, so run it once loading is done
+pub fn bake_map(
+    mesh_query: Query<(&Aabb, &GlobalTransform), With<RapierColliderHandle>>,
+    rapier_context: Res<RapierContext>,
+    layout: Res<HexLayout>,
+    mut map: ResMut<Map>,
+) {
+    if mesh_query.is_empty() {
+        warn!("There is no level geometry to bake the map from");
+        return;
+    }
+
+    let mut min = Vec3::splat(f32::MAX);
+    let mut max = Vec3::splat(f32::MIN);
+    for (aabb, transform) in &mesh_query {
+        let (center, half_extents) = (Vec3::from(aabb.center), Vec3::from(aabb.half_extents));
+        for x in [-1.0, 1.0] {
+            for y in [-1.0, 1.0] {
+                for z in [-1.0, 1.0] {
+                    let corner =
+                        transform.transform_point(center + half_extents * Vec3::new(x, y, z));
+                    min = min.min(corner);
+                    max = max.max(corner);
+                }
+            }
+        }
+    }
+
+    let corners = [
+        min,
+        max,
+        Vec3::new(min.x, 0.0, max.z),
+        Vec3::new(max.x, 0.0, min.z),
+    ]
+    .map(|corner| AxialCoordinates::from_world(corner, &layout));
+    let (min_q, max_q) = (
+        corners.iter().map(|c| c.q).min().unwrap(),
+        corners.iter().map(|c| c.q).max().unwrap(),
+    );
+    let (min_r, max_r) = (
+        corners.iter().map(|c| c.r).min().unwrap(),
+        corners.iter().map(|c| c.r).max().unwrap(),
+    );
+
+    let mut baked = 0;
+    for q in min_q..=max_q {
+        for r in min_r..=max_r {
+            let coordinates = AxialCoordinates::new(q, r, 0);
+            let centre = coordinates.to_world(&layout);
+            if centre.x < min.x || centre.x > max.x || centre.z < min.z || centre.z > max.z {
+                continue;
+            }
+
+            let origin = Vec3::new(centre.x, max.y + 1.0, centre.z);
+            let hit = rapier_context.cast_ray_and_get_normal(
+                origin,
+                Vec3::NEG_Y,
+                max.y - min.y + 2.0,
+                true,
+                QueryFilter::new(),
+            );
+            let tile = match hit {
+                Some((_, intersection)) if intersection.normal.y >= MAX_WALKABLE_SLOPE.cos() => {
+                    let elevation =
+                        AxialCoordinates::from_world(intersection.point, &layout).elevation;
+                    let tile_type = map
+                        .get(coordinates)
+                        .map_or(TileType::Passable(1.0), |tile| tile.tile_type);
+                    Tile::new(AxialCoordinates::new(q, r, elevation), tile_type)
+                }
+                Some((_, intersection)) => {
+                    let elevation =
+                        AxialCoordinates::from_world(intersection.point, &layout).elevation;
+                    Tile::new(AxialCoordinates::new(q, r, elevation), TileType::Impassable)
+                }
+                None => Tile::new(coordinates, TileType::Impassable),
+            };
+            map.insert(tile);
+            baked += 1;
+        }
+    }
+    info!("Baked {} tiles from the level geometry", baked);
+}
+
 /// Finds all Mesh-handles and adds a [`RapierColliderHandle`] to them
 pub fn add_collider(
     query: Query<(Entity, &Handle<Mesh>), Without<RapierColliderHandle>>,
@@ -60,3 +322,66 @@ pub fn add_collider(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::schedule::SystemStage;
+
+    use super::*;
+    use crate::line_of_sight::LineOfSight;
+
+    /// Builds the map of a level with the given nodes, named like Blender names duplicates
+    fn build(nodes: &[(&str, AxialCoordinates)]) -> Map {
+        let layout = HexLayout::default();
+        let mut world = World::new();
+        world.insert_resource(layout);
+        world.insert_resource(Map::new());
+        for (name, coordinates) in nodes {
+            let prefix = name.split('.').next().unwrap();
+            if let Some(tile_source) = TileSource::from_node(prefix, None) {
+                world.spawn((
+                    Transform::from_translation(coordinates.to_world(&layout)),
+                    tile_source,
+                ));
+            }
+        }
+        SystemStage::single(build_map).run(&mut world);
+        world.remove_resource::<Map>().unwrap()
+    }
+
+    #[test]
+    fn walls_and_doors_block_their_hex() {
+        let row = |q| AxialCoordinates::new(q, 0, 0);
+        let map = build(&[
+            ("Tile", row(0)),
+            ("Wall.003", row(1)),
+            ("Tile.001", row(2)),
+            ("CornerWall.012", row(3)),
+            ("Door.004", row(4)),
+            ("Tile.002", row(5)),
+        ]);
+        assert_eq!(map.get(row(0)).unwrap().tile_type, TileType::Passable(1.0));
+        for q in [1, 3, 4] {
+            assert_eq!(map.get(row(q)).unwrap().tile_type, TileType::Impassable);
+        }
+        assert_eq!(map.line_of_sight(row(0), row(2)), LineOfSight::Blocked);
+        assert_eq!(map.line_of_sight(row(2), row(0)), LineOfSight::Blocked);
+    }
+
+    #[test]
+    fn areas_are_scaled_on_the_ground() {
+        let layout = HexLayout::default();
+        let floorplate = TileSource::from_node("Floorplate", None).unwrap();
+        let tiles = |scale: Vec3| floorplate.tiles(&Transform::from_scale(scale), &layout);
+        let square = tiles(Vec3::new(4.0, 1.0, 4.0));
+        assert_eq!(tiles(Vec3::new(4.0, 5.0, 4.0)).len(), square.len());
+
+        let stretched = tiles(Vec3::new(4.0, 1.0, 8.0));
+        assert!(stretched.len() > square.len() * 3 / 2);
+        let depth = stretched
+            .iter()
+            .map(|tile| tile.coordinates.to_world(&layout).z.abs())
+            .fold(0.0, f32::max);
+        assert!(depth > 4.0 * FLOORPLATE_HALF_EXTENT);
+    }
+}
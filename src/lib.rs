@@ -1,7 +1,25 @@
+pub mod action_points;
+pub mod auth;
+pub mod character;
+pub mod combat;
 pub mod common;
+pub mod flight;
+pub mod inventory;
 pub mod level_loader;
+pub mod line_of_sight;
 pub mod map;
 pub mod messages;
+pub mod pathfinding;
+pub mod progression;
+pub mod race;
+pub mod replication;
 pub mod resources;
+pub mod spell;
+pub mod squad;
+pub mod stats;
+pub mod visibility;
 
-pub const PROTOCOL_ID: u64 = 7;
+/// Version of the protocol, bump it whenever a message changes
+///
+/// Checked when connecting and again by the [`messages::Handshake`]
+pub const PROTOCOL_ID: u64 = 11;
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+
+use crate::map::{AxialCoordinates, Map, TileType};
+
+/// Height of a characters eyes above the floor, in elevation steps
+pub const EYE_HEIGHT: f32 = 1.0;
+
+/// Impassable tiles are obstacles reaching this many elevation steps above their floor
+pub const OBSTACLE_HEIGHT: f32 = 2.0;
+
+/// Result of a line of sight check between two tiles
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum LineOfSight {
+    Clear,
+    /// The target can be seen, but something between reaches up to less than a step below the sight line
+    PartialCover,
+    Blocked,
+}
+
+impl Map {
+    /// Checks whether a character standing on `from` can see a character standing on `to`
+    ///
+    /// Sight runs from eye to eye along [`AxialCoordinates::line_to`], so it rises or falls with the
+    /// elevation of both ends. Tiles in between block sight if their top pokes through the sight line.
+    /// Tiles reaching up to within a step below it, and characters standing in between, give partial cover.
+    /// Hexes missing from the map are treated as open air.
+    /// Only walks the tiles on the line and doesn't allocate besides the line itself
+    pub fn line_of_sight(&self, from: AxialCoordinates, to: AxialCoordinates) -> LineOfSight {
+        let (from, to) = match (self.get(from), self.get(to)) {
+            (Some(from), Some(to)) => (from.coordinates, to.coordinates),
+            _ => return LineOfSight::Blocked,
+        };
+        let line = from.line_to(to);
+        let steps = (line.len() - 1) as f32;
+        let eye = from.elevation as f32 + EYE_HEIGHT;
+        let target_eye = to.elevation as f32 + EYE_HEIGHT;
+
+        let mut result = LineOfSight::Clear;
+        for (index, coordinates) in line.iter().enumerate().skip(1) {
+            if index == line.len() - 1 {
+                break;
+            }
+            let tile = match self.get(*coordinates) {
+                Some(tile) => tile,
+                None => continue,
+            };
+            let sight = eye + (target_eye - eye) * index as f32 / steps;
+            let top = match tile.tile_type {
+                TileType::Passable(_) => tile.coordinates.elevation as f32,
+                TileType::Impassable => tile.coordinates.elevation as f32 + OBSTACLE_HEIGHT,
+            };
+            if top > sight {
+                return LineOfSight::Blocked;
+            }
+            if top > sight - 1.0 || tile.occupant.is_some() {
+                result = LineOfSight::PartialCover;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Tile;
+    use bevy::prelude::Entity;
+
+    #[test]
+    fn flat_ground_is_clear() {
+        let map = Map::generate(6, 6);
+        let from = AxialCoordinates::new(-3, 0, 0);
+        let to = AxialCoordinates::new(3, 0, 0);
+        assert_eq!(map.line_of_sight(from, to), LineOfSight::Clear);
+    }
+
+    #[test]
+    fn obstacles_block_and_low_walls_cover() {
+        let mut map = Map::generate(6, 6);
+        let from = AxialCoordinates::new(-3, 0, 0);
+        let to = AxialCoordinates::new(3, 0, 0);
+
+        map.insert(Tile::new(
+            AxialCoordinates::new(1, 0, 0),
+            TileType::Impassable,
+        ));
+        assert_eq!(map.line_of_sight(from, to), LineOfSight::Blocked);
+
+        map.insert(Tile::new(
+            AxialCoordinates::new(1, 0, 1),
+            TileType::Passable(1.0),
+        ));
+        assert_eq!(map.line_of_sight(from, to), LineOfSight::PartialCover);
+
+        map.insert(Tile::new(
+            AxialCoordinates::new(1, 0, 0),
+            TileType::Passable(1.0),
+        ));
+        map.occupy(AxialCoordinates::new(0, 0, 0), Entity::from_raw(1));
+        assert_eq!(map.line_of_sight(from, to), LineOfSight::PartialCover);
+    }
+
+    #[test]
+    fn elevation_lets_sight_pass_over_obstacles() {
+        let mut map = Map::generate(6, 6);
+        let from = AxialCoordinates::new(-2, 0, 3);
+        let to = AxialCoordinates::new(2, 0, 3);
+        map.insert(Tile::new(from, TileType::Passable(1.0)));
+        map.insert(Tile::new(to, TileType::Passable(1.0)));
+        map.insert(Tile::new(
+            AxialCoordinates::new(0, 0, 0),
+            TileType::Impassable,
+        ));
+        assert_eq!(map.line_of_sight(from, to), LineOfSight::Clear);
+
+        // from the ground the same obstacle is in the way
+        let (from, to) = (
+            AxialCoordinates::new(-2, 0, 0),
+            AxialCoordinates::new(2, 0, 0),
+        );
+        map.insert(Tile::new(from, TileType::Passable(1.0)));
+        map.insert(Tile::new(to, TileType::Passable(1.0)));
+        assert_eq!(map.line_of_sight(from, to), LineOfSight::Blocked);
+    }
+
+    #[test]
+    fn adjacent_tiles_always_see_each_other() {
+        let mut map = Map::generate(3, 3);
+        map.insert(Tile::new(
+            AxialCoordinates::new(1, 0, 0),
+            TileType::Impassable,
+        ));
+        let from = AxialCoordinates::new(0, 0, 0);
+        assert_eq!(
+            map.line_of_sight(from, AxialCoordinates::new(0, 1, 0)),
+            LineOfSight::Clear
+        );
+    }
+}
@@ -3,6 +3,8 @@ use std::{collections::HashMap, ops::Add};
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
+const SQRT_3: f32 = 1.732_050_8;
+
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct AxialCoordinates {
     pub q: i32,
@@ -44,13 +46,112 @@ impl AxialCoordinates {
         ]
     }
 
-    pub fn from_world(translation: Vec3) -> Self {
-        let elevation = translation.y.round() as i32;
-        Self::new(0, 0, elevation)
+    /// Number of steps between two hexes, ignoring elevation
+    pub fn distance(self, other: AxialCoordinates) -> i32 {
+        let dq = self.q - other.q;
+        let dr = self.r - other.r;
+        (dq.abs() + dr.abs() + (dq + dr).abs()) / 2
+    }
+
+    /// Converts a world position into the hex containing it
+    ///
+    /// Fractional positions are snapped to the nearest hex via cube rounding
+    pub fn from_world(translation: Vec3, layout: &HexLayout) -> Self {
+        let (x, z) = (translation.x / layout.size, translation.z / layout.size);
+        let (q, r) = match layout.orientation {
+            HexOrientation::Pointy => (SQRT_3 / 3.0 * x - 1.0 / 3.0 * z, 2.0 / 3.0 * z),
+            HexOrientation::Flat => (2.0 / 3.0 * x, -1.0 / 3.0 * x + SQRT_3 / 3.0 * z),
+        };
+        let elevation = (translation.y / layout.elevation_step).round() as i32;
+        Self::round(q, r, elevation)
     }
 
-    pub fn to_world(&self) -> Vec3 {
-        Vec3::splat(0.0)
+    /// Converts the hex into the world position of its centre
+    pub fn to_world(&self, layout: &HexLayout) -> Vec3 {
+        let (q, r) = (self.q as f32, self.r as f32);
+        let (x, z) = match layout.orientation {
+            HexOrientation::Pointy => (SQRT_3 * q + SQRT_3 / 2.0 * r, 1.5 * r),
+            HexOrientation::Flat => (1.5 * q, SQRT_3 / 2.0 * q + SQRT_3 * r),
+        };
+        Vec3::new(
+            x * layout.size,
+            self.elevation as f32 * layout.elevation_step,
+            z * layout.size,
+        )
+    }
+
+    /// Rounds fractional axial coordinates to the nearest hex
+    ///
+    /// Rounds in cube space and resets the component with the largest rounding error,
+    /// so that q + r + s = 0 still holds
+    pub fn round(q: f32, r: f32, elevation: i32) -> Self {
+        Self::round_f64(q as f64, r as f64, elevation)
+    }
+
+    fn round_f64(q: f64, r: f64, elevation: i32) -> Self {
+        let s = -q - r;
+        let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+        let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+        if dq > dr && dq > ds {
+            rq = -rr - rs;
+        } else if dr > ds {
+            rr = -rq - rs;
+        }
+        Self::new(rq as i32, rr as i32, elevation)
+    }
+
+    /// Returns every hex on the straight line to `other`, both ends included
+    ///
+    /// Elevation is interpolated along the line, a line within a single hex only contains the start.
+    /// Both ends are nudged by the same tiny offset, so lines running exactly along hex edges always
+    /// pick the same side, whichever end they start from. f64 keeps the offset meaningful on any map
+    pub fn line_to(self, other: AxialCoordinates) -> Vec<AxialCoordinates> {
+        const NUDGE: (f64, f64) = (1e-6, 2e-6);
+        let steps = self.distance(other);
+        if steps == 0 {
+            return vec![self];
+        }
+        let (q, r) = (self.q as f64 + NUDGE.0, self.r as f64 + NUDGE.1);
+        let (dq, dr) = ((other.q - self.q) as f64, (other.r - self.r) as f64);
+        let de = (other.elevation - self.elevation) as f64;
+        (0..=steps)
+            .map(|step| {
+                let t = step as f64 / steps as f64;
+                let elevation = (self.elevation as f64 + de * t).round() as i32;
+                Self::round_f64(q + dq * t, r + dr * t, elevation)
+            })
+            .collect()
+    }
+}
+
+/// Orientation of the hexes in world space
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum HexOrientation {
+    /// Hexes have a corner pointing along the z axis
+    Pointy,
+    /// Hexes have a flat side facing the z axis
+    Flat,
+}
+
+/// Describes how [`AxialCoordinates`] are laid out in the world
+///
+/// q runs along the x axis, r along the z axis and elevation along the y axis
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Resource, Serialize)]
+pub struct HexLayout {
+    pub orientation: HexOrientation,
+    /// Distance from the centre of a hex to any of its corners
+    pub size: f32,
+    /// Height of a single elevation step in world units
+    pub elevation_step: f32,
+}
+
+impl Default for HexLayout {
+    fn default() -> Self {
+        Self {
+            orientation: HexOrientation::Pointy,
+            size: 1.0,
+            elevation_step: 1.0,
+        }
     }
 }
 
@@ -65,34 +166,67 @@ impl Add<AxialCoordinates> for AxialCoordinates {
     }
 }
 
-enum TileType {
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum TileType {
+    /// Can be walked over for the given movement cost in AP
     Passable(f32),
     Impassable,
 }
 
-struct Tile {
-    coordinates: AxialCoordinates,
-    tile_type: TileType,
+#[derive(Clone, Debug)]
+pub struct Tile {
+    pub coordinates: AxialCoordinates,
+    pub tile_type: TileType,
+    /// The character standing on this tile
+    pub occupant: Option<Entity>,
 }
 
+impl Tile {
+    pub fn new(coordinates: AxialCoordinates, tile_type: TileType) -> Self {
+        Self {
+            coordinates,
+            tile_type,
+            occupant: None,
+        }
+    }
+
+    /// Returns the movement cost of this tile or None if it can't be walked on
+    pub fn movement_cost(&self) -> Option<f32> {
+        match self.tile_type {
+            TileType::Passable(cost) => Some(cost),
+            TileType::Impassable => None,
+        }
+    }
+}
+
+/// Hex grid of the loaded level
+///
+/// Tiles are stored by q and r only, so every column of the grid holds exactly one tile.
+/// The elevation of a column is stored in the coordinates of its [`Tile`]
 #[derive(Resource)]
 pub struct Map {
-    tiles: HashMap<AxialCoordinates, Tile>,
+    tiles: HashMap<(i32, i32), Tile>,
     width: i32,
     depth: i32,
 }
 
 impl Map {
+    /// Creates a map without any tiles
+    pub fn new() -> Self {
+        Self {
+            tiles: HashMap::new(),
+            width: 0,
+            depth: 0,
+        }
+    }
+
     pub fn generate(width: i32, depth: i32) -> Self {
         let mut tiles = HashMap::new();
 
         for w in -width..width {
             for d in -depth..depth {
-                let tile = Tile {
-                    coordinates: AxialCoordinates::new(w, d, 0),
-                    tile_type: TileType::Passable(1.0),
-                };
-                tiles.insert(tile.coordinates, tile);
+                let tile = Tile::new(AxialCoordinates::new(w, d, 0), TileType::Passable(1.0));
+                tiles.insert((w, d), tile);
             }
         }
 
@@ -102,4 +236,193 @@ impl Map {
             depth,
         }
     }
+
+    /// Inserts a tile, replacing any tile in the same column
+    pub fn insert(&mut self, tile: Tile) {
+        self.tiles
+            .insert((tile.coordinates.q, tile.coordinates.r), tile);
+    }
+
+    /// Returns the tile at q and r, ignoring the elevation of `coordinates`
+    pub fn get(&self, coordinates: AxialCoordinates) -> Option<&Tile> {
+        self.tiles.get(&(coordinates.q, coordinates.r))
+    }
+
+    pub fn get_mut(&mut self, coordinates: AxialCoordinates) -> Option<&mut Tile> {
+        self.tiles.get_mut(&(coordinates.q, coordinates.r))
+    }
+
+    /// Removes all tiles, e.g. before another level is loaded
+    pub fn clear(&mut self) {
+        self.tiles.clear();
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = &Tile> {
+        self.tiles.values()
+    }
+
+    /// Returns up to `count` passable, unoccupied tiles, closest to `centre` first
+    pub fn free_tiles_near(&self, centre: AxialCoordinates, count: usize) -> Vec<AxialCoordinates> {
+        let mut free: Vec<AxialCoordinates> = self
+            .tiles()
+            .filter(|tile| tile.occupant.is_none() && tile.movement_cost().is_some())
+            .map(|tile| tile.coordinates)
+            .collect();
+        free.sort_by_key(|c| (c.distance(centre), c.q, c.r));
+        free.truncate(count);
+        free
+    }
+
+    /// Places `entity` on the tile at `coordinates`
+    ///
+    /// Returns false if there is no such tile or it is already held by another entity
+    pub fn occupy(&mut self, coordinates: AxialCoordinates, entity: Entity) -> bool {
+        match self.get_mut(coordinates) {
+            Some(tile) if tile.occupant.is_none() || tile.occupant == Some(entity) => {
+                tile.occupant = Some(entity);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes whatever stands on the tile at `coordinates`
+    pub fn vacate(&mut self, coordinates: AxialCoordinates) -> Option<Entity> {
+        self.get_mut(coordinates)
+            .and_then(|tile| tile.occupant.take())
+    }
+}
+
+impl Default for Map {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn layouts() -> Vec<HexLayout> {
+        let mut layouts = Vec::new();
+        for orientation in [HexOrientation::Pointy, HexOrientation::Flat] {
+            for (size, elevation_step) in [(1.0, 1.0), (0.5, 0.25), (2.5, 3.0)] {
+                layouts.push(HexLayout {
+                    orientation,
+                    size,
+                    elevation_step,
+                });
+            }
+        }
+        layouts
+    }
+
+    #[test]
+    fn round_trips_across_the_whole_map() {
+        let map = Map::generate(25, 25);
+        for layout in layouts() {
+            for tile in map.tiles() {
+                let coordinates = tile.coordinates;
+                for elevation in -3..=3 {
+                    let coordinates = AxialCoordinates::new(coordinates.q, coordinates.r, elevation);
+                    let world = coordinates.to_world(&layout);
+                    assert_eq!(
+                        AxialCoordinates::from_world(world, &layout),
+                        coordinates,
+                        "{:?} with {:?}",
+                        world,
+                        layout
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn positions_inside_a_hex_round_to_that_hex() {
+        let map = Map::generate(10, 10);
+        let mut rng = StdRng::seed_from_u64(7);
+        for layout in layouts() {
+            // radius of the circle touching all six sides
+            let inner_radius = layout.size * SQRT_3 / 2.0;
+            for coordinates in map.tiles().map(|tile| tile.coordinates) {
+                for _ in 0..8 {
+                    let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+                    let distance = rng.gen_range(0.0..inner_radius * 0.99);
+                    let height = rng.gen_range(-0.49..0.49) * layout.elevation_step;
+                    let offset = Vec3::new(angle.cos() * distance, height, angle.sin() * distance);
+                    let world = coordinates.to_world(&layout) + offset;
+                    assert_eq!(AxialCoordinates::from_world(world, &layout), coordinates);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn neighbors_are_one_hex_apart_in_the_world() {
+        for layout in layouts() {
+            let origin = AxialCoordinates::new(3, -2, 0);
+            for neighbor in origin.neighbors() {
+                let distance = origin.to_world(&layout).distance(neighbor.to_world(&layout));
+                assert!((distance - layout.size * SQRT_3).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn lines_are_contiguous_and_include_both_ends() {
+        let mut rng = StdRng::seed_from_u64(11);
+        for _ in 0..2000 {
+            let from =
+                AxialCoordinates::new(rng.gen_range(-500..500), rng.gen_range(-500..500), 0);
+            let to =
+                AxialCoordinates::new(rng.gen_range(-500..500), rng.gen_range(-500..500), 4);
+            let line = from.line_to(to);
+            if from.distance(to) == 0 {
+                assert_eq!(line, vec![from]);
+                continue;
+            }
+            assert_eq!(line.len() as i32, from.distance(to) + 1);
+            assert_eq!(line.first(), Some(&from));
+            assert_eq!(line.last(), Some(&to));
+            for pair in line.windows(2) {
+                assert_eq!(pair[0].distance(pair[1]), 1);
+                assert!(pair[1].elevation >= pair[0].elevation);
+            }
+        }
+    }
+
+    #[test]
+    fn lines_are_the_same_both_ways() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let flat = |line: Vec<AxialCoordinates>| {
+            line.into_iter()
+                .map(|coordinates| (coordinates.q, coordinates.r))
+                .collect::<Vec<_>>()
+        };
+        for range in [10, 100, 1000] {
+            for _ in 0..500 {
+                let from = AxialCoordinates::new(
+                    rng.gen_range(-range..range),
+                    rng.gen_range(-range..range),
+                    0,
+                );
+                // lines along hex edges are where the ties are
+                let k = rng.gen_range(1..5);
+                let to = match rng.gen_range(0..3) {
+                    0 => AxialCoordinates::new(from.q + 2 * k, from.r - k, 0),
+                    1 => AxialCoordinates::new(from.q + k, from.r + k, 0),
+                    _ => AxialCoordinates::new(
+                        rng.gen_range(-range..range),
+                        rng.gen_range(-range..range),
+                        0,
+                    ),
+                };
+                let mut back = flat(to.line_to(from));
+                back.reverse();
+                assert_eq!(flat(from.line_to(to)), back, "{:?} to {:?}", from, to);
+            }
+        }
+    }
 }
@@ -1,28 +1,536 @@
+use std::{fmt, time::Instant};
+
 use bevy::prelude::*;
-use serde::{Deserialize, Serialize};
+use bevy_renet::renet::{ChannelConfig, ReliableChannelConfig, RenetConnectionConfig};
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    action_points::{ActionError, ActionPoints},
+    character::{CharacterData, Health},
+    combat::{AttackResult, AttackType},
+    common::Special,
+    flight::Altitude,
+    inventory::{EquipmentSlot, Inventory},
+    map::AxialCoordinates,
+    pathfinding::Path,
+    progression::XpLedger,
+    replication::GameSnapshot,
+    spell::SpellResult,
+    squad::{Squad, SquadError},
+    stats::PlayerStats,
+    PROTOCOL_ID,
+};
+
+/// Largest message a client may send, the biggest legitimate one is a squad submission
+pub const MAX_CLIENT_MESSAGE_SIZE: u64 = 2 * 1024;
+
+/// Largest message the server sends, spawn zones and match results can get long
+pub const MAX_SERVER_MESSAGE_SIZE: u64 = 64 * 1024;
+
+/// Messages a client may send per second on average
+pub const CLIENT_MESSAGES_PER_SECOND: f32 = 20.0;
+
+/// Messages a client may send at once, like when clicking through the squad builder
+pub const CLIENT_MESSAGE_BURST: f32 = 40.0;
+
+/// Renet channel of the chat, after the reliable, unreliable and chunk channels of renet
+pub const CHAT_CHANNEL: u8 = 3;
+
+/// Characters a chat message may have
+pub const MAX_CHAT_LENGTH: usize = 200;
 
-use crate::map::AxialCoordinates;
+/// Largest chat message on the wire, [`MAX_CHAT_LENGTH`] characters of up to four bytes plus the header
+pub const MAX_CHAT_MESSAGE_SIZE: u64 = 1024;
+
+/// Chat messages a client may send per second on average
+pub const CHAT_MESSAGES_PER_SECOND: f32 = 1.0;
+
+/// Chat messages a client may send at once
+pub const CHAT_MESSAGE_BURST: f32 = 5.0;
+
+/// The default channels of renet plus the ordered [`CHAT_CHANNEL`], server and client have to agree on them
+pub fn connection_config() -> RenetConnectionConfig {
+    let mut config = RenetConnectionConfig::default();
+    let chat = ChannelConfig::Reliable(ReliableChannelConfig {
+        channel_id: CHAT_CHANNEL,
+        ordered: true,
+        ..Default::default()
+    });
+    config.send_channels_config.push(chat.clone());
+    config.receive_channels_config.push(chat);
+    config
+}
+
+/// Fixed-size integers, so messages are as big as with `bincode::serialize`, but with a size limit
+/// and without trailing garbage
+fn options() -> impl Options {
+    bincode::DefaultOptions::new().with_fixint_encoding()
+}
+
+/// Serializes a message for sending, serializing our own messages can't fail
+pub fn encode<T: Serialize>(message: &T) -> Vec<u8> {
+    options()
+        .serialize(message)
+        .expect("messages are always serializable")
+}
+
+/// Deserializes a received message, refusing messages above `max_size` bytes
+pub fn decode<T: DeserializeOwned>(bytes: &[u8], max_size: u64) -> Result<T, ProtocolError> {
+    if bytes.len() as u64 > max_size {
+        return Err(ProtocolError::TooLarge {
+            size: bytes.len() as u64,
+            max_size,
+        });
+    }
+    options()
+        .with_limit(max_size)
+        .deserialize(bytes)
+        .map_err(|_| ProtocolError::Malformed)
+}
+
+/// First message of every client, before anything else is accepted
+///
+/// Kept outside of [`ClientMessage`], so it stays readable whatever the messages of the other side look like
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Handshake {
+    pub protocol_id: u64,
+}
+
+impl Handshake {
+    pub fn new() -> Self {
+        Self {
+            protocol_id: PROTOCOL_ID,
+        }
+    }
+
+    pub fn check(&self) -> Result<(), ProtocolError> {
+        if self.protocol_id != PROTOCOL_ID {
+            return Err(ProtocolError::VersionMismatch {
+                expected: PROTOCOL_ID,
+                actual: self.protocol_id,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for Handshake {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reason why a message was refused, clients are disconnected over any of them
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ProtocolError {
+    /// The bytes don't form a message
+    Malformed,
+    TooLarge {
+        size: u64,
+        max_size: u64,
+    },
+    /// The client sent more messages than the [`RateLimit`] allows
+    RateLimited,
+    /// The client speaks another version of the protocol
+    VersionMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    /// The client sent messages before its [`Handshake`]
+    MissingHandshake,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Malformed => write!(f, "malformed message"),
+            ProtocolError::TooLarge { size, max_size } => write!(
+                f,
+                "message of {} bytes is larger than {} bytes",
+                size, max_size
+            ),
+            ProtocolError::RateLimited => write!(f, "too many messages"),
+            ProtocolError::VersionMismatch { expected, actual } => {
+                write!(f, "protocol version {} doesn't match {}", actual, expected)
+            }
+            ProtocolError::MissingHandshake => write!(f, "no handshake"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// Token bucket limiting how many messages a client may send
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    tokens: f32,
+    last: Instant,
+    per_second: f32,
+    burst: f32,
+}
+
+impl RateLimit {
+    pub fn new(now: Instant) -> Self {
+        Self::with_rate(now, CLIENT_MESSAGES_PER_SECOND, CLIENT_MESSAGE_BURST)
+    }
+
+    /// A bucket refilling `per_second` tokens and holding up to `burst` of them, starting full
+    pub fn with_rate(now: Instant, per_second: f32, burst: f32) -> Self {
+        Self {
+            tokens: burst,
+            last: now,
+            per_second,
+            burst,
+        }
+    }
+
+    /// Takes a token for a message received at `now`, failing once the bucket is empty
+    pub fn allow(&mut self, now: Instant) -> Result<(), ProtocolError> {
+        let elapsed = now.saturating_duration_since(self.last);
+        self.last = self.last.max(now);
+        self.tokens = (self.tokens + elapsed.as_secs_f32() * self.per_second).min(self.burst);
+        if self.tokens < 1.0 {
+            return Err(ProtocolError::RateLimited);
+        }
+        self.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+/// Reliable messages of the server are numbered per client, so the client can apply them in order
+/// and notice when one is missing
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Sequenced<M = ServerMessage> {
+    pub sequence: u64,
+    pub message: M,
+}
 
 #[derive(Debug, Serialize, Deserialize, Component)]
 pub enum ServerMessage {
+    /// The client broke the protocol and is disconnected
+    Kicked(ProtocolError),
     PlayerConnected(u64, String, Entity),
     PlayerDisconnected(u64),
+    /// The player lost the connection, their characters stay in the match for a while
+    PlayerAbsent(u64),
+    PlayerReturned(u64),
+    /// Everything the client knows about the game, sent on joining, returning and on request
+    Snapshot(GameSnapshot),
     PlayerName(String),
     PlayerTurn(u64),
     LoadLevel(String),
-    /// Assigns a spawnpoint in q, r, elevation
-    AssignSpawnpoint(AxialCoordinates),
+    /// The tiles this player may place their characters on
+    AssignSpawnZone(Vec<AxialCoordinates>),
+    /// The squad of the player passed the rules, they can ready up
+    SquadAccepted,
+    SquadRejected(SquadError),
+    /// It's this players turn to place a character
+    PlacementTurn(u64),
+    /// The flying character rose or sank to the altitude
+    AltitudeChanged(Entity, Altitude),
+    /// An attack was rolled, lethal attacks remove the defender
+    AttackResolved(AttackResult),
+    /// A spell was cast, characters killed by it are removed
+    SpellCast(SpellResult),
+    /// Health of the character changed without an attack, like after drinking a potion
+    HealthChanged(Entity, Health),
+    /// Content of the inventory of one of the players own characters
+    InventoryChanged(Entity, Inventory),
+    /// XP of one of the players own characters changed
+    ExperienceChanged(Entity, XpLedger),
+    /// Learned skills changed the SPECIAL of the character and everything derived from it
+    StatsChanged(Entity, Special, Health, ActionPoints),
+    /// An enemy character came into sight of the players characters
+    EnteredVision(CharacterData),
+    /// An enemy character is no longer seen by any of the players characters
+    LeftVision(Entity),
+    /// Only one player has characters left, None if nobody survived
+    MatchEnded {
+        winner: Option<u64>,
+        stats: Vec<PlayerStats>,
+    },
+    /// The requested character placement was refused
+    PlacementRejected(ActionError),
+    CharacterSpawned(CharacterData),
+    /// Removes the character with this server entity
+    CharacterDespawned(Entity),
+    ActionPointsChanged(Entity, ActionPoints),
+    /// The character walks along the path, ending on its last tile
+    CharacterMoved(Entity, Path),
+    /// The action requested for the character was refused
+    ActionRejected(Entity, ActionError),
 }
 
 #[derive(Debug, Serialize, Deserialize, Component)]
 pub enum ClientMessage {
+    /// Toggles readiness, only counts once the squad was accepted
     ClientReady,
     ChangeName(String),
+    /// The characters the player wants to play the next match with
+    SubmitSquad(Squad),
     EndTurn,
     LevelLoaded,
+    /// Moves the character with this server entity to the target tile
+    MoveCharacter(Entity, AxialCoordinates),
+    /// The first character attacks the second one, both given by their server entity
+    Attack(Entity, Entity, AttackType),
+    /// The character with this server entity casts the spell with this name at the tile
+    CastSpell(Entity, String, AxialCoordinates),
+    /// The character uses up a carried consumable with this name
+    UseItem(Entity, String),
+    /// The character equips a carried weapon or armour with this name
+    EquipItem(Entity, String),
+    /// The character puts the item in the slot back into its bags
+    UnequipItem(Entity, EquipmentSlot),
+    /// The character spends XP to learn the skill with this name
+    SpendXp(Entity, String),
+    /// Lets the flying character rise or sink to the altitude, 0 lands it
+    ChangeAltitude(Entity, Altitude),
+    /// Places the next character of the squad on a tile of the spawn zone
+    PlaceCharacter(AxialCoordinates),
+    /// The player wants to play another match after the results screen
+    ReturnToLobby,
+    /// The client lost track of the deltas and needs a new [`GameSnapshot`]
+    RequestResync,
 }
 
+/// What a client says, sent on the [`CHAT_CHANNEL`]
+///
+/// The server relays it as a [`ChatLine`] with the sender filled in, clients can't speak for someone else
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum ChatMessage {
+    /// To everyone
     Public(String),
+    /// Whisper to the player with the id
     Private(u64, String),
+    /// To the players of the own team
+    Team(String),
+}
+
+impl ChatMessage {
+    pub fn text(&self) -> &str {
+        match self {
+            ChatMessage::Public(text) | ChatMessage::Private(_, text) | ChatMessage::Team(text) => {
+                text
+            }
+        }
+    }
+}
+
+/// Checks the text of a chat message, returning it without surrounding whitespace
+pub fn check_chat_text(text: &str) -> Result<&str, ChatError> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(ChatError::Empty);
+    }
+    if text.chars().count() > MAX_CHAT_LENGTH {
+        return Err(ChatError::TooLong);
+    }
+    if text.chars().any(char::is_control) {
+        return Err(ChatError::ControlCharacters);
+    }
+    Ok(text)
+}
+
+/// What the server sends on the [`CHAT_CHANNEL`]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ChatLine {
+    /// From the player with the id to everyone
+    Public(u64, String),
+    /// Whisper between two players, the sender gets it back once it was delivered
+    Private { from: u64, to: u64, text: String },
+    /// From the player with the id to the own team
+    Team(u64, String),
+    /// From the server, about joins, turns and kills
+    System(String),
+    /// The last message of the client wasn't relayed
+    Refused(ChatError),
+}
+
+/// Reason why the server didn't relay a chat message
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ChatError {
+    Empty,
+    /// The text has more than [`MAX_CHAT_LENGTH`] characters
+    TooLong,
+    /// Line breaks and other control characters would mess up the chat of everyone else
+    ControlCharacters,
+    /// The client chats faster than [`CHAT_MESSAGES_PER_SECOND`]
+    RateLimited,
+    /// No player with the id of a whisper is in the game
+    UnknownPlayer,
+}
+
+impl fmt::Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatError::Empty => write!(f, "the message is empty"),
+            ChatError::TooLong => write!(
+                f,
+                "the message is longer than {} characters",
+                MAX_CHAT_LENGTH
+            ),
+            ChatError::ControlCharacters => write!(f, "the message has control characters"),
+            ChatError::RateLimited => write!(f, "too many messages, slow down"),
+            ChatError::UnknownPlayer => write!(f, "no such player"),
+        }
+    }
+}
+
+impl std::error::Error for ChatError {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        let bytes = encode(&ClientMessage::CastSpell(
+            Entity::from_raw(3),
+            "Flame Wave".to_string(),
+            AxialCoordinates::new(1, -2, 0),
+        ));
+        match decode(&bytes, MAX_CLIENT_MESSAGE_SIZE) {
+            Ok(ClientMessage::CastSpell(entity, spell, target)) => {
+                assert_eq!(entity, Entity::from_raw(3));
+                assert_eq!(spell, "Flame Wave");
+                assert_eq!(target, AxialCoordinates::new(1, -2, 0));
+            }
+            other => panic!("decoded {:?}", other),
+        }
+        // the same bytes as before the protocol layer
+        assert_eq!(
+            bytes,
+            bincode::serialize(&ClientMessage::CastSpell(
+                Entity::from_raw(3),
+                "Flame Wave".to_string(),
+                AxialCoordinates::new(1, -2, 0),
+            ))
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn oversized_and_trailing_bytes_are_refused() {
+        let mut bytes = encode(&ClientMessage::ChangeName("x".repeat(4000)));
+        assert_eq!(
+            decode::<ClientMessage>(&bytes, MAX_CLIENT_MESSAGE_SIZE).unwrap_err(),
+            ProtocolError::TooLarge {
+                size: bytes.len() as u64,
+                max_size: MAX_CLIENT_MESSAGE_SIZE
+            }
+        );
+        bytes = encode(&ClientMessage::EndTurn);
+        bytes.push(0);
+        assert_eq!(
+            decode::<ClientMessage>(&bytes, MAX_CLIENT_MESSAGE_SIZE).unwrap_err(),
+            ProtocolError::Malformed
+        );
+    }
+
+    #[test]
+    fn handshakes_check_the_protocol() {
+        let handshake: Handshake = decode(&encode(&Handshake::new()), 8).unwrap();
+        assert_eq!(handshake.check(), Ok(()));
+        let handshake = Handshake {
+            protocol_id: PROTOCOL_ID + 1,
+        };
+        assert_eq!(
+            handshake.check(),
+            Err(ProtocolError::VersionMismatch {
+                expected: PROTOCOL_ID,
+                actual: PROTOCOL_ID + 1
+            })
+        );
+    }
+
+    #[test]
+    fn random_bytes_never_panic() {
+        let mut rng = StdRng::seed_from_u64(21);
+        for _ in 0..20_000 {
+            let length = rng.gen_range(0..256);
+            let bytes: Vec<u8> = (0..length).map(|_| rng.gen()).collect();
+            let _ = decode::<ClientMessage>(&bytes, MAX_CLIENT_MESSAGE_SIZE);
+            let _ = decode::<ServerMessage>(&bytes, MAX_SERVER_MESSAGE_SIZE);
+            let _ = decode::<Handshake>(&bytes, MAX_CLIENT_MESSAGE_SIZE);
+        }
+    }
+
+    #[test]
+    fn mutated_messages_never_panic() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let messages = [
+            encode(&ClientMessage::SubmitSquad(Squad::default())),
+            encode(&ClientMessage::MoveCharacter(
+                Entity::from_raw(1),
+                AxialCoordinates::new(0, 0, 0),
+            )),
+            encode(&ServerMessage::AssignSpawnZone(vec![
+                AxialCoordinates::new(
+                    2, 3, 1
+                );
+                8
+            ])),
+        ];
+        for _ in 0..20_000 {
+            let mut bytes = messages[rng.gen_range(0..messages.len())].clone();
+            for _ in 0..rng.gen_range(1..4) {
+                let index = rng.gen_range(0..bytes.len());
+                bytes[index] = rng.gen();
+            }
+            bytes.truncate(rng.gen_range(0..=bytes.len()));
+            let _ = decode::<ClientMessage>(&bytes, MAX_CLIENT_MESSAGE_SIZE);
+            let _ = decode::<ServerMessage>(&bytes, MAX_SERVER_MESSAGE_SIZE);
+        }
+    }
+
+    #[test]
+    fn rate_limit_refills_over_time() {
+        let start = Instant::now();
+        let mut rate_limit = RateLimit::new(start);
+        for _ in 0..CLIENT_MESSAGE_BURST as usize {
+            assert_eq!(rate_limit.allow(start), Ok(()));
+        }
+        assert_eq!(rate_limit.allow(start), Err(ProtocolError::RateLimited));
+        let later = start + Duration::from_secs_f32(1.5 / CLIENT_MESSAGES_PER_SECOND);
+        assert_eq!(rate_limit.allow(later), Ok(()));
+        assert_eq!(rate_limit.allow(later), Err(ProtocolError::RateLimited));
+    }
+
+    #[test]
+    fn chat_text_is_checked() {
+        assert_eq!(check_chat_text("  hello "), Ok("hello"));
+        assert_eq!(check_chat_text(" \t "), Err(ChatError::Empty));
+        assert_eq!(check_chat_text("a\nb"), Err(ChatError::ControlCharacters));
+        let longest = "ü".repeat(MAX_CHAT_LENGTH);
+        assert_eq!(check_chat_text(&longest), Ok(longest.as_str()));
+        assert_eq!(
+            check_chat_text(&format!("{}!", longest)),
+            Err(ChatError::TooLong)
+        );
+        // the longest text of the widest characters still fits on the wire
+        let widest = ChatMessage::Private(u64::MAX, "𝄞".repeat(MAX_CHAT_LENGTH));
+        assert_eq!(
+            decode::<ChatMessage>(&encode(&widest), MAX_CHAT_MESSAGE_SIZE),
+            Ok(widest)
+        );
+    }
+
+    #[test]
+    fn chat_rate_limit_is_stricter() {
+        let start = Instant::now();
+        let mut rate_limit =
+            RateLimit::with_rate(start, CHAT_MESSAGES_PER_SECOND, CHAT_MESSAGE_BURST);
+        for _ in 0..CHAT_MESSAGE_BURST as usize {
+            assert_eq!(rate_limit.allow(start), Ok(()));
+        }
+        assert_eq!(rate_limit.allow(start), Err(ProtocolError::RateLimited));
+        let later = start + Duration::from_secs_f32(1.0 / CHAT_MESSAGES_PER_SECOND);
+        assert_eq!(rate_limit.allow(later), Ok(()));
+    }
 }
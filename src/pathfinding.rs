@@ -0,0 +1,546 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    flight::Altitude,
+    line_of_sight::OBSTACLE_HEIGHT,
+    map::{AxialCoordinates, Map, Tile, TileType},
+};
+
+/// How far a character can climb or drop between two neighboring tiles by default
+pub const DEFAULT_MAX_CLIMB: i32 = 1;
+
+/// Describes who is moving over the [`Map`]
+#[derive(Clone, Copy, Debug)]
+pub struct Mover {
+    /// The moving character, its own tile never counts as blocked
+    pub entity: Entity,
+    /// Maximum elevation difference per step
+    pub max_climb: i32,
+    /// Airborne movers fly over the terrain instead of walking on it
+    pub altitude: Altitude,
+}
+
+impl Mover {
+    pub fn new(entity: Entity) -> Self {
+        Self {
+            entity,
+            max_climb: DEFAULT_MAX_CLIMB,
+            altitude: Altitude::default(),
+        }
+    }
+
+    pub fn with_altitude(mut self, altitude: Altitude) -> Self {
+        self.altitude = altitude;
+        self
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Path {
+    /// All tiles from start to goal, both included
+    pub tiles: Vec<AxialCoordinates>,
+    /// AP needed to walk the path
+    pub cost: f32,
+}
+
+/// Cheapest way to reach a tile found by [`Map::reachable`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reach {
+    pub coordinates: AxialCoordinates,
+    /// AP needed to get here
+    pub cost: f32,
+    /// Number of tiles walked to get here
+    pub steps: u32,
+    /// Tile this one is entered from, None for the start tile
+    pub previous: Option<AxialCoordinates>,
+}
+
+/// Every tile a character can reach within its AP and tile limits
+#[derive(Clone, Debug, Default)]
+pub struct Reachable {
+    tiles: HashMap<(i32, i32), Reach>,
+    /// Every settled (tile, steps) state with the state it was reached from
+    states: HashMap<((i32, i32), u32), (AxialCoordinates, Option<u32>)>,
+}
+
+impl Reachable {
+    /// Returns the cheapest way to reach the tile at q and r, ignoring elevation
+    pub fn get(&self, coordinates: AxialCoordinates) -> Option<&Reach> {
+        self.tiles.get(&(coordinates.q, coordinates.r))
+    }
+
+    pub fn contains(&self, coordinates: AxialCoordinates) -> bool {
+        self.get(coordinates).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Reach> {
+        self.tiles.values()
+    }
+
+    /// Walks the predecessors back to the start and returns the cheapest path to `coordinates`
+    pub fn path_to(&self, coordinates: AxialCoordinates) -> Option<Path> {
+        let reach = self.get(coordinates)?;
+        let mut tiles = vec![reach.coordinates];
+        let mut state = ((reach.coordinates.q, reach.coordinates.r), reach.steps);
+        while let Some(&(previous, Some(steps))) = self.states.get(&state) {
+            tiles.push(previous);
+            state = ((previous.q, previous.r), steps);
+        }
+        tiles.reverse();
+        Some(Path {
+            tiles,
+            cost: reach.cost,
+        })
+    }
+}
+
+/// Entry of the open set, ordered so that the [`BinaryHeap`] pops the lowest priority first
+#[derive(Clone, Copy, PartialEq)]
+struct Candidate {
+    priority: f32,
+    coordinates: AxialCoordinates,
+    steps: u32,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.priority.total_cmp(&self.priority).then_with(|| {
+            (self.coordinates.q, self.coordinates.r, self.steps).cmp(&(
+                other.coordinates.q,
+                other.coordinates.r,
+                other.steps,
+            ))
+        })
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Map {
+    /// Returns the cost of stepping from `from` onto `to` or None if `mover` can't make that step
+    ///
+    /// Airborne movers ignore the movement cost of the terrain. Their altitude is relative to the
+    /// ground they leave, so they can climb onto ground as high as their altitude plus the climb
+    /// limit and fly over obstacles whose top stays below them. They drop down any cliff
+    pub fn step_cost(&self, from: &Tile, to: &Tile, mover: &Mover) -> Option<f32> {
+        if to.occupant.is_some() && to.occupant != Some(mover.entity) {
+            return None;
+        }
+        let rise = to.coordinates.elevation - from.coordinates.elevation;
+        if mover.altitude.is_airborne() {
+            if rise > mover.altitude.0 + mover.max_climb {
+                return None;
+            }
+            return match to.tile_type {
+                TileType::Impassable if ((mover.altitude.0 - rise) as f32) < OBSTACLE_HEIGHT => {
+                    None
+                }
+                _ => Some(1.0),
+            };
+        }
+        if rise.abs() > mover.max_climb {
+            return None;
+        }
+        to.movement_cost()
+    }
+
+    /// Finds the cheapest path from `from` to `to` with A*
+    ///
+    /// Returns None if either tile doesn't exist or there is no way for `mover` to reach `to`.
+    /// Airborne movers fly over obstacles but can't end their move above one, they could never land
+    pub fn find_path(
+        &self,
+        from: AxialCoordinates,
+        to: AxialCoordinates,
+        mover: &Mover,
+    ) -> Option<Path> {
+        let start = self.get(from)?;
+        let goal = self.get(to)?;
+        goal.movement_cost()?;
+
+        // cheapest step on the map keeps the heuristic admissible
+        let min_cost = if mover.altitude.is_airborne() {
+            1.0
+        } else {
+            self.tiles()
+                .filter_map(Tile::movement_cost)
+                .fold(f32::INFINITY, f32::min)
+        };
+
+        let mut open = BinaryHeap::new();
+        let mut costs: HashMap<AxialCoordinates, f32> = HashMap::new();
+        let mut came_from: HashMap<AxialCoordinates, AxialCoordinates> = HashMap::new();
+
+        costs.insert(start.coordinates, 0.0);
+        open.push(Candidate {
+            priority: 0.0,
+            coordinates: start.coordinates,
+            steps: 0,
+        });
+
+        while let Some(Candidate { coordinates, .. }) = open.pop() {
+            let current_cost = costs[&coordinates];
+            if coordinates == goal.coordinates {
+                let mut tiles = vec![coordinates];
+                let mut step = coordinates;
+                while let Some(&previous) = came_from.get(&step) {
+                    tiles.push(previous);
+                    step = previous;
+                }
+                tiles.reverse();
+                return Some(Path {
+                    tiles,
+                    cost: current_cost,
+                });
+            }
+
+            let current = self.get(coordinates)?;
+            for next in coordinates.neighbors().iter().filter_map(|n| self.get(*n)) {
+                if let Some(step_cost) = self.step_cost(current, next, mover) {
+                    let next_cost = current_cost + step_cost;
+                    if costs
+                        .get(&next.coordinates)
+                        .map_or(true, |&known| next_cost < known)
+                    {
+                        costs.insert(next.coordinates, next_cost);
+                        came_from.insert(next.coordinates, coordinates);
+                        let heuristic =
+                            next.coordinates.distance(goal.coordinates) as f32 * min_cost;
+                        open.push(Candidate {
+                            priority: next_cost + heuristic,
+                            coordinates: next.coordinates,
+                            steps: 0,
+                        });
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Finds every tile `mover` can reach from `start` with Dijkstra
+    ///
+    /// A tile is reachable if it costs at most `ap_budget` AP and takes at most `max_tiles` steps.
+    /// Because of the step limit the cheapest path isn't always usable, so the search keeps
+    /// every (tile, steps) state that isn't beaten by a cheaper state with fewer steps
+    pub fn reachable(
+        &self,
+        start: AxialCoordinates,
+        mover: &Mover,
+        ap_budget: f32,
+        max_tiles: u32,
+    ) -> Reachable {
+        let mut reachable = Reachable::default();
+        let start = match self.get(start) {
+            Some(start) => start,
+            None => return reachable,
+        };
+
+        let key = |c: AxialCoordinates| (c.q, c.r);
+        let mut open = BinaryHeap::new();
+        let mut fewest_steps: HashMap<(i32, i32), u32> = HashMap::new();
+        // cheapest known cost of every (tile, steps) state and the state it is reached from
+        let mut best: HashMap<((i32, i32), u32), (f32, AxialCoordinates, Option<u32>)> =
+            HashMap::new();
+
+        best.insert((key(start.coordinates), 0), (0.0, start.coordinates, None));
+        open.push(Candidate {
+            priority: 0.0,
+            coordinates: start.coordinates,
+            steps: 0,
+        });
+
+        while let Some(Candidate {
+            priority: cost,
+            coordinates,
+            steps,
+        }) = open.pop()
+        {
+            let (best_cost, from, from_steps) = best[&(key(coordinates), steps)];
+            if cost > best_cost {
+                continue;
+            }
+            // a cheaper state with fewer steps has already been expanded
+            if fewest_steps
+                .get(&key(coordinates))
+                .map_or(false, |&fewest| fewest <= steps)
+            {
+                continue;
+            }
+            fewest_steps.insert(key(coordinates), steps);
+
+            reachable
+                .states
+                .insert((key(coordinates), steps), (from, from_steps));
+            let current = match self.get(coordinates) {
+                Some(current) => current,
+                None => continue,
+            };
+            // flown over, but no place to end the move
+            if current.movement_cost().is_some() || steps == 0 {
+                reachable.tiles.entry(key(coordinates)).or_insert(Reach {
+                    coordinates,
+                    cost,
+                    steps,
+                    previous: from_steps.map(|_| from),
+                });
+            }
+
+            if steps >= max_tiles {
+                continue;
+            }
+            for next in coordinates.neighbors().iter().filter_map(|n| self.get(*n)) {
+                if let Some(step_cost) = self.step_cost(current, next, mover) {
+                    let next_cost = cost + step_cost;
+                    let state = (key(next.coordinates), steps + 1);
+                    if next_cost <= ap_budget
+                        && best
+                            .get(&state)
+                            .map_or(true, |&(known, ..)| next_cost < known)
+                    {
+                        best.insert(state, (next_cost, coordinates, Some(steps)));
+                        open.push(Candidate {
+                            priority: next_cost,
+                            coordinates: next.coordinates,
+                            steps: steps + 1,
+                        });
+                    }
+                }
+            }
+        }
+
+        reachable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::TileType;
+
+    fn mover() -> Mover {
+        Mover::new(Entity::from_raw(1))
+    }
+
+    #[test]
+    fn finds_straight_path_on_flat_map() {
+        let map = Map::generate(5, 5);
+        let from = AxialCoordinates::new(0, 0, 0);
+        let to = AxialCoordinates::new(3, 0, 0);
+        let path = map.find_path(from, to, &mover()).unwrap();
+        assert_eq!(path.tiles.first(), Some(&from));
+        assert_eq!(path.tiles.last(), Some(&to));
+        assert_eq!(path.tiles.len(), 4);
+        assert_eq!(path.cost, 3.0);
+    }
+
+    #[test]
+    fn avoids_expensive_and_impassable_tiles() {
+        let mut map = Map::generate(5, 5);
+        map.insert(Tile::new(
+            AxialCoordinates::new(1, 0, 0),
+            TileType::Impassable,
+        ));
+        map.insert(Tile::new(
+            AxialCoordinates::new(1, -1, 0),
+            TileType::Passable(10.0),
+        ));
+        let path = map
+            .find_path(
+                AxialCoordinates::new(0, 0, 0),
+                AxialCoordinates::new(2, 0, 0),
+                &mover(),
+            )
+            .unwrap();
+        assert_eq!(path.cost, 3.0);
+        assert!(!path.tiles.contains(&AxialCoordinates::new(1, 0, 0)));
+        assert!(!path.tiles.contains(&AxialCoordinates::new(1, -1, 0)));
+    }
+
+    #[test]
+    fn does_not_walk_through_other_characters() {
+        let mut map = Map::generate(2, 2);
+        let blocker = Entity::from_raw(2);
+        let goal = AxialCoordinates::new(1, 0, 0);
+        assert!(map.occupy(goal, blocker));
+        assert!(map
+            .find_path(AxialCoordinates::new(0, 0, 0), goal, &mover())
+            .is_none());
+
+        map.vacate(goal);
+        assert!(map
+            .find_path(AxialCoordinates::new(0, 0, 0), goal, &mover())
+            .is_some());
+    }
+
+    #[test]
+    fn reachable_respects_ap_budget() {
+        let map = Map::generate(10, 10);
+        let start = AxialCoordinates::new(0, 0, 0);
+        let reachable = map.reachable(start, &mover(), 2.0, 10);
+        // start, six neighbors and twelve tiles two steps out
+        assert_eq!(reachable.iter().count(), 19);
+        assert!(reachable.iter().all(|reach| reach.cost <= 2.0));
+        assert_eq!(reachable.get(start).unwrap().previous, None);
+
+        let far = AxialCoordinates::new(2, -1, 0);
+        let path = reachable.path_to(far).unwrap();
+        assert_eq!(path.tiles.len(), 3);
+        assert_eq!(path.tiles[1], reachable.get(far).unwrap().previous.unwrap());
+    }
+
+    #[test]
+    fn reachable_respects_tile_limit() {
+        let mut map = Map::generate(10, 10);
+        // the direct route is short but expensive
+        map.insert(Tile::new(
+            AxialCoordinates::new(1, 0, 0),
+            TileType::Passable(5.0),
+        ));
+        for tile in [(1, -1), (2, -1)] {
+            map.insert(Tile::new(
+                AxialCoordinates::new(tile.0, tile.1, 0),
+                TileType::Passable(0.5),
+            ));
+        }
+        let goal = AxialCoordinates::new(2, 0, 0);
+
+        let unlimited = map.reachable(AxialCoordinates::new(0, 0, 0), &mover(), 10.0, 10);
+        assert_eq!(unlimited.get(goal).unwrap().cost, 2.0);
+        assert_eq!(unlimited.path_to(goal).unwrap().tiles.len(), 4);
+
+        let limited = map.reachable(AxialCoordinates::new(0, 0, 0), &mover(), 10.0, 2);
+        let reach = limited.get(goal).unwrap();
+        assert_eq!(reach.steps, 2);
+        assert_eq!(reach.cost, 6.0);
+        assert_eq!(limited.path_to(goal).unwrap().tiles.len(), 3);
+    }
+
+    #[test]
+    fn limits_elevation_change_per_step() {
+        let mut map = Map::generate(1, 1);
+        map.insert(Tile::new(
+            AxialCoordinates::new(0, 0, 0),
+            TileType::Passable(1.0),
+        ));
+        for neighbor in AxialCoordinates::new(0, 0, 0).neighbors() {
+            if map.get(neighbor).is_some() {
+                map.insert(Tile::new(
+                    AxialCoordinates::new(neighbor.q, neighbor.r, 2),
+                    TileType::Passable(1.0),
+                ));
+            }
+        }
+        let goal = AxialCoordinates::new(-1, 0, 2);
+        assert!(map
+            .find_path(AxialCoordinates::new(0, 0, 0), goal, &mover())
+            .is_none());
+
+        let climber = Mover {
+            max_climb: 2,
+            ..mover()
+        };
+        let path = map
+            .find_path(AxialCoordinates::new(0, 0, 0), goal, &climber)
+            .unwrap();
+        assert_eq!(path.tiles, vec![AxialCoordinates::new(0, 0, 0), goal]);
+    }
+
+    #[test]
+    fn airborne_movers_fly_over_obstacles_and_cliffs() {
+        let mut map = Map::generate(5, 5);
+        for r in -5..5 {
+            map.insert(Tile::new(
+                AxialCoordinates::new(1, r, 0),
+                TileType::Impassable,
+            ));
+        }
+        map.insert(Tile::new(
+            AxialCoordinates::new(2, 0, 3),
+            TileType::Passable(3.0),
+        ));
+        let from = AxialCoordinates::new(0, 0, 0);
+        let to = AxialCoordinates::new(3, 0, 0);
+
+        assert!(map.find_path(from, to, &mover()).is_none());
+        // too low to clear the wall
+        let hovering = mover().with_altitude(Altitude(1));
+        assert!(map.find_path(from, to, &hovering).is_none());
+
+        let flying = mover().with_altitude(Altitude(2));
+        let path = map.find_path(from, to, &flying).unwrap();
+        assert_eq!(path.tiles.len(), 4);
+        assert_eq!(path.cost, 3.0);
+        assert!(map.reachable(from, &flying, 3.0, 5).contains(to));
+    }
+
+    #[test]
+    fn airborne_movers_only_clear_ridges_below_their_altitude() {
+        let mut map = Map::generate(5, 5);
+        for r in -5..5 {
+            map.insert(Tile::new(
+                AxialCoordinates::new(1, r, 4),
+                TileType::Passable(1.0),
+            ));
+        }
+        let from = AxialCoordinates::new(0, 0, 0);
+        let to = AxialCoordinates::new(3, 0, 0);
+
+        let low = mover().with_altitude(Altitude(2));
+        assert!(map.find_path(from, to, &low).is_none());
+        assert!(!map.reachable(from, &low, 5.0, 5).contains(to));
+
+        let high = mover().with_altitude(Altitude(3));
+        assert_eq!(map.find_path(from, to, &high).unwrap().cost, 3.0);
+        assert!(map.reachable(from, &high, 5.0, 5).contains(to));
+    }
+
+    #[test]
+    fn obstacles_on_higher_ground_need_more_altitude() {
+        let mut map = Map::generate(5, 5);
+        for r in -5..5 {
+            map.insert(Tile::new(
+                AxialCoordinates::new(1, r, 1),
+                TileType::Impassable,
+            ));
+        }
+        let from = AxialCoordinates::new(0, 0, 0);
+        let to = AxialCoordinates::new(3, 0, 0);
+
+        // would clear the wall on level ground
+        let low = mover().with_altitude(Altitude(2));
+        assert!(map.find_path(from, to, &low).is_none());
+
+        let high = mover().with_altitude(Altitude(3));
+        assert!(map.find_path(from, to, &high).is_some());
+    }
+
+    #[test]
+    fn airborne_moves_cant_end_above_obstacles() {
+        let mut map = Map::generate(5, 5);
+        let obstacle = AxialCoordinates::new(1, 0, 0);
+        map.insert(Tile::new(obstacle, TileType::Impassable));
+        let from = AxialCoordinates::new(0, 0, 0);
+        let beyond = AxialCoordinates::new(2, 0, 0);
+
+        let flying = mover().with_altitude(Altitude(4));
+        assert!(map.find_path(from, obstacle, &flying).is_none());
+        let reachable = map.reachable(from, &flying, 5.0, 5);
+        assert!(!reachable.contains(obstacle));
+        assert!(reachable.contains(beyond));
+        assert_eq!(map.find_path(from, beyond, &flying).unwrap().tiles.len(), 3);
+    }
+}
@@ -0,0 +1,335 @@
+use std::{collections::HashSet, fmt};
+
+use bevy::{
+    asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+};
+use bevy_common_assets::json::JsonAssetPlugin;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    action_points::ActionError,
+    common::Special,
+    race::{RaceDefinition, SpecialModifiers, MAX_MODIFIER},
+};
+
+/// Loads the XP reward rules from `assets/rewards.xp.json`
+/// and every skill tree from `assets/skills/*.skills.json`
+pub struct ProgressionPlugin;
+
+impl Plugin for ProgressionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(JsonAssetPlugin::<XpRules>::new(&["xp.json"]))
+            .add_asset::<SkillTree>()
+            .add_asset_loader(SkillTreeLoader)
+            .add_startup_system(load_progression);
+        info!("ProgressionPlugin has been loaded");
+    }
+}
+
+/// Keeps the XP rules and the skill trees loaded
+#[derive(Resource)]
+pub struct ProgressionHandles {
+    pub rules: Handle<XpRules>,
+    pub skill_trees: Vec<HandleUntyped>,
+}
+
+impl ProgressionHandles {
+    /// The loaded XP rules, the defaults until they are loaded
+    pub fn rules(&self, rules: &Assets<XpRules>) -> XpRules {
+        rules.get(&self.rules).copied().unwrap_or_default()
+    }
+}
+
+fn load_progression(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let skill_trees = asset_server.load_folder("skills").unwrap_or_else(|error| {
+        warn!("Couldn't load skill trees: {:?}", error);
+        Vec::new()
+    });
+    commands.insert_resource(ProgressionHandles {
+        rules: asset_server.load("rewards.xp.json"),
+        skill_trees,
+    });
+}
+
+/// How much XP characters are awarded for what they do
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, TypeUuid)]
+#[uuid = "2f6d8b14-93a7-4c0e-b5e1-6a9c3d7f0b42"]
+pub struct XpRules {
+    /// For finishing off an enemy character
+    pub kill: u32,
+    /// For every point of damage dealt to enemy characters
+    pub damage: u32,
+    /// For every spell cast, whether it hits anyone or not
+    pub spell_cast: u32,
+}
+
+impl Default for XpRules {
+    fn default() -> Self {
+        Self {
+            kill: 100,
+            damage: 5,
+            spell_cast: 10,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum XpReason {
+    Kill,
+    Damage,
+    SpellCast,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct XpAward {
+    pub reason: XpReason,
+    pub amount: u32,
+}
+
+/// Every XP award a character received and the skills the XP was spent on
+///
+/// Kept by the server, clients only learn about the ledgers of their own characters
+#[derive(Clone, Component, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct XpLedger {
+    pub awards: Vec<XpAward>,
+    pub spent: u32,
+    /// Names of the learned skills, in the order they were learned
+    pub learned: Vec<String>,
+}
+
+impl XpLedger {
+    pub fn earned(&self) -> u32 {
+        self.awards.iter().map(|award| award.amount).sum()
+    }
+
+    pub fn available(&self) -> u32 {
+        self.earned() - self.spent
+    }
+
+    /// Books an award, awards of 0 XP are left out
+    pub fn award(&mut self, reason: XpReason, amount: u32) {
+        if amount > 0 {
+            self.awards.push(XpAward { reason, amount });
+        }
+    }
+
+    pub fn has_learned(&self, skill: &str) -> bool {
+        self.learned.iter().any(|learned| learned == skill)
+    }
+
+    /// Spends XP on `skill` once all its prerequisites are learned
+    pub fn learn(&mut self, skill: &Skill) -> Result<(), ActionError> {
+        if self.has_learned(&skill.name) {
+            return Err(ActionError::AlreadyLearned);
+        }
+        if !skill
+            .requires
+            .iter()
+            .all(|required| self.has_learned(required))
+        {
+            return Err(ActionError::SkillLocked);
+        }
+        if skill.cost > self.available() {
+            return Err(ActionError::NotEnoughXp {
+                cost: skill.cost,
+                available: self.available(),
+            });
+        }
+        self.spent += skill.cost;
+        self.learned.push(skill.name.clone());
+        Ok(())
+    }
+}
+
+/// A tree of skills as defined in a `.skills.json` asset
+///
+/// Which races may learn from a tree is listed in the [`RaceDefinition`]s
+#[derive(Clone, Debug, Deserialize, TypeUuid)]
+#[uuid = "71c3e9a5-0d2b-4f86-8e4a-b19f5c6d2e30"]
+pub struct SkillTree {
+    pub name: String,
+    pub skills: Vec<Skill>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Skill {
+    pub name: String,
+    /// XP needed to learn the skill
+    pub cost: u32,
+    /// Skills of the same tree that have to be learned first
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// Added to the SPECIAL of characters who learned the skill
+    #[serde(default)]
+    pub modifiers: SpecialModifiers,
+}
+
+impl SkillTree {
+    /// Checks that skill names are unique and skills only require skills listed before them,
+    /// so there can't be any cycles
+    pub fn validate(&self) -> Result<(), SkillTreeError> {
+        let mut seen = HashSet::new();
+        for skill in &self.skills {
+            for required in &skill.requires {
+                if !seen.contains(required.as_str()) {
+                    return Err(SkillTreeError::UnknownPrerequisite {
+                        skill: skill.name.clone(),
+                        required: required.clone(),
+                    });
+                }
+            }
+            if skill
+                .modifiers
+                .stats()
+                .iter()
+                .any(|(_, modifier)| !(-MAX_MODIFIER..=MAX_MODIFIER).contains(modifier))
+            {
+                return Err(SkillTreeError::ModifierOutOfRange(skill.name.clone()));
+            }
+            if !seen.insert(skill.name.as_str()) {
+                return Err(SkillTreeError::Duplicate(skill.name.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Looks up the skill called `name` among all loaded skill trees
+pub fn find_skill<'a>(
+    skill_trees: &'a Assets<SkillTree>,
+    name: &str,
+) -> Option<(&'a SkillTree, &'a Skill)> {
+    skill_trees.iter().find_map(|(_, tree)| {
+        tree.skills
+            .iter()
+            .find(|skill| skill.name == name)
+            .map(|skill| (tree, skill))
+    })
+}
+
+/// SPECIAL of a character with the `base` SPECIAL, after racial modifiers and learned skills
+pub fn derive_special(
+    base: &Special,
+    race_definition: Option<&RaceDefinition>,
+    ledger: &XpLedger,
+    skill_trees: &Assets<SkillTree>,
+) -> Special {
+    let modifiers = ledger
+        .learned
+        .iter()
+        .filter_map(|name| find_skill(skill_trees, name))
+        .fold(
+            race_definition.map_or_else(SpecialModifiers::default, |race| race.modifiers),
+            |modifiers, (_, skill)| modifiers.combine(&skill.modifiers),
+        );
+    modifiers.apply(base)
+}
+
+/// Reason why a skill tree was refused while loading
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SkillTreeError {
+    UnknownPrerequisite { skill: String, required: String },
+    ModifierOutOfRange(String),
+    Duplicate(String),
+}
+
+impl fmt::Display for SkillTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkillTreeError::UnknownPrerequisite { skill, required } => write!(
+                f,
+                "{} requires {}, which isn't listed before it",
+                skill, required
+            ),
+            SkillTreeError::ModifierOutOfRange(skill) => write!(
+                f,
+                "modifiers of {} are outside of -{}..={}",
+                skill, MAX_MODIFIER, MAX_MODIFIER
+            ),
+            SkillTreeError::Duplicate(skill) => write!(f, "{} is listed twice", skill),
+        }
+    }
+}
+
+impl std::error::Error for SkillTreeError {}
+
+/// Parses skill trees and refuses those that don't pass [`SkillTree::validate`]
+struct SkillTreeLoader;
+
+impl AssetLoader for SkillTreeLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let skill_tree: SkillTree = serde_json::from_slice(bytes)?;
+            skill_tree.validate()?;
+            load_context.set_default_asset(LoadedAsset::new(skill_tree));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["skills.json"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn magic() -> SkillTree {
+        serde_json::from_str(
+            r#"{
+                "name": "Magic",
+                "skills": [
+                    { "name": "Focus", "cost": 100, "modifiers": { "intelligence": 1 } },
+                    { "name": "Battle Magic", "cost": 200, "requires": ["Focus"] }
+                ]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn skills_need_xp_and_prerequisites() {
+        let tree = magic();
+        let (focus, battle_magic) = (&tree.skills[0], &tree.skills[1]);
+        let mut ledger = XpLedger::default();
+        ledger.award(XpReason::Kill, 100);
+        ledger.award(XpReason::Damage, 0);
+        assert_eq!(ledger.awards.len(), 1);
+        assert_eq!(ledger.learn(battle_magic), Err(ActionError::SkillLocked));
+        assert_eq!(ledger.learn(focus), Ok(()));
+        assert_eq!(ledger.available(), 0);
+        assert_eq!(ledger.learn(focus), Err(ActionError::AlreadyLearned));
+        assert_eq!(
+            ledger.learn(battle_magic),
+            Err(ActionError::NotEnoughXp {
+                cost: 200,
+                available: 0
+            })
+        );
+    }
+
+    #[test]
+    fn prerequisites_have_to_come_first() {
+        let mut tree = magic();
+        assert_eq!(tree.validate(), Ok(()));
+        tree.skills.reverse();
+        assert!(matches!(
+            tree.validate(),
+            Err(SkillTreeError::UnknownPrerequisite { .. })
+        ));
+        let mut tree = magic();
+        tree.skills[1].requires.clear();
+        tree.skills[1].name = "Focus".to_string();
+        assert_eq!(
+            tree.validate(),
+            Err(SkillTreeError::Duplicate("Focus".to_string()))
+        );
+    }
+}
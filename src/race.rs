@@ -0,0 +1,270 @@
+use std::{collections::HashSet, fmt};
+
+use bevy::{
+    asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::common::{Race, Special};
+
+/// Largest bonus or malus a race may give to a single SPECIAL stat
+pub const MAX_MODIFIER: i8 = 3;
+
+/// SPECIAL stats never leave this range, whatever the modifiers
+const MIN_STAT: i8 = 1;
+const MAX_STAT: i8 = 10;
+
+/// Ability that lets characters of a race fly, see [`crate::flight`]
+pub const FLIGHT: &str = "Flight";
+/// Ability that lets characters of a race cast spells, see [`crate::spell`]
+pub const SPELLCASTING: &str = "Spellcasting";
+
+/// Loads and validates every race definition from `assets/races/*.race.json`
+pub struct RacePlugin;
+
+impl Plugin for RacePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<RaceDefinition>()
+            .add_asset_loader(RaceDefinitionLoader)
+            .add_startup_system(load_race_definitions);
+        info!("RacePlugin has been loaded");
+    }
+}
+
+/// Keeps the handles of all race definitions, so they stay loaded
+#[derive(Resource)]
+pub struct RaceDefinitionHandles(pub Vec<HandleUntyped>);
+
+fn load_race_definitions(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let handles = asset_server.load_folder("races").unwrap_or_else(|error| {
+        warn!("Couldn't load race definitions: {:?}", error);
+        Vec::new()
+    });
+    commands.insert_resource(RaceDefinitionHandles(handles));
+}
+
+/// What makes a race different from the others, as defined in a `.race.json` asset
+#[derive(Clone, Debug, Deserialize, TypeUuid)]
+#[uuid = "9e4f2c1a-7b3d-4e8f-a6c5-3d2b1f0e9a87"]
+pub struct RaceDefinition {
+    pub race: Race,
+    /// Added to the base SPECIAL of every character of this race
+    #[serde(default)]
+    pub modifiers: SpecialModifiers,
+    /// Names of the skill trees characters of this race can learn from
+    #[serde(default)]
+    pub skill_trees: Vec<String>,
+    /// Names of the racial abilities, like [`FLIGHT`] or [`SPELLCASTING`]
+    #[serde(default)]
+    pub abilities: Vec<String>,
+}
+
+impl RaceDefinition {
+    /// Checks that the definition keeps the game balanced and has no duplicate entries
+    pub fn validate(&self) -> Result<(), RaceDefinitionError> {
+        for (stat, modifier) in self.modifiers.stats() {
+            if !(-MAX_MODIFIER..=MAX_MODIFIER).contains(&modifier) {
+                return Err(RaceDefinitionError::ModifierOutOfRange { stat, modifier });
+            }
+        }
+        for names in [&self.skill_trees, &self.abilities] {
+            let mut seen = HashSet::new();
+            for name in names {
+                if name.is_empty() {
+                    return Err(RaceDefinitionError::EmptyName);
+                }
+                if !seen.insert(name) {
+                    return Err(RaceDefinitionError::Duplicate(name.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether characters of this race have the ability called `ability`
+    pub fn has_ability(&self, ability: &str) -> bool {
+        self.abilities.iter().any(|name| name == ability)
+    }
+
+    /// SPECIAL of a character of this race with the `base` SPECIAL
+    pub fn derive_special(&self, base: &Special) -> Special {
+        self.modifiers.apply(base)
+    }
+}
+
+/// Looks up the definition of `race` among the loaded definitions
+pub fn find_race_definition(
+    definitions: &Assets<RaceDefinition>,
+    race: Race,
+) -> Option<&RaceDefinition> {
+    definitions
+        .iter()
+        .map(|(_, definition)| definition)
+        .find(|definition| definition.race == race)
+}
+
+/// Bonus or malus per SPECIAL stat, stats left out in the asset stay untouched
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpecialModifiers {
+    pub strength: i8,
+    pub perception: i8,
+    pub endurance: i8,
+    pub charisma: i8,
+    pub intelligence: i8,
+    pub agility: i8,
+    pub luck: i8,
+}
+
+impl SpecialModifiers {
+    pub(crate) fn stats(&self) -> [(&'static str, i8); 7] {
+        [
+            ("strength", self.strength),
+            ("perception", self.perception),
+            ("endurance", self.endurance),
+            ("charisma", self.charisma),
+            ("intelligence", self.intelligence),
+            ("agility", self.agility),
+            ("luck", self.luck),
+        ]
+    }
+
+    /// Sums up both modifiers, stat by stat
+    pub fn combine(&self, other: &SpecialModifiers) -> Self {
+        Self {
+            strength: self.strength + other.strength,
+            perception: self.perception + other.perception,
+            endurance: self.endurance + other.endurance,
+            charisma: self.charisma + other.charisma,
+            intelligence: self.intelligence + other.intelligence,
+            agility: self.agility + other.agility,
+            luck: self.luck + other.luck,
+        }
+    }
+
+    /// Adds the modifiers to `base`, keeping every stat between 1 and 10
+    pub fn apply(&self, base: &Special) -> Special {
+        let modify =
+            |stat: u8, modifier: i8| (stat as i8 + modifier).clamp(MIN_STAT, MAX_STAT) as u8;
+        Special {
+            strength: modify(base.strength, self.strength),
+            perception: modify(base.perception, self.perception),
+            endurance: modify(base.endurance, self.endurance),
+            charisma: modify(base.charisma, self.charisma),
+            intelligence: modify(base.intelligence, self.intelligence),
+            agility: modify(base.agility, self.agility),
+            luck: modify(base.luck, self.luck),
+        }
+    }
+}
+
+/// Reason why a race definition was refused while loading
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RaceDefinitionError {
+    ModifierOutOfRange {
+        stat: &'static str,
+        modifier: i8,
+    },
+    EmptyName,
+    /// A skill tree or ability is listed twice
+    Duplicate(String),
+}
+
+impl fmt::Display for RaceDefinitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RaceDefinitionError::ModifierOutOfRange { stat, modifier } => write!(
+                f,
+                "modifier {} for {} is outside of -{}..={}",
+                modifier, stat, MAX_MODIFIER, MAX_MODIFIER
+            ),
+            RaceDefinitionError::EmptyName => write!(f, "skill trees and abilities need a name"),
+            RaceDefinitionError::Duplicate(name) => write!(f, "{} is listed twice", name),
+        }
+    }
+}
+
+impl std::error::Error for RaceDefinitionError {}
+
+/// Parses race definitions and refuses those that don't pass [`RaceDefinition::validate`]
+struct RaceDefinitionLoader;
+
+impl AssetLoader for RaceDefinitionLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let definition: RaceDefinition = serde_json::from_slice(bytes)?;
+            definition.validate()?;
+            load_context.set_default_asset(LoadedAsset::new(definition));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["race.json"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn earth_pony() -> RaceDefinition {
+        serde_json::from_str(
+            r#"{
+                "race": "EarthPony",
+                "modifiers": { "strength": 2, "agility": -1 },
+                "skill_trees": ["Brawling"],
+                "abilities": ["Buck"]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn modifiers_change_the_base_special() {
+        let special = earth_pony().derive_special(&Special::new());
+        assert_eq!(special.strength, 7);
+        assert_eq!(special.agility, 4);
+        assert_eq!(special.luck, 5);
+    }
+
+    #[test]
+    fn derived_stats_stay_in_range() {
+        let mut base = Special::new();
+        base.strength = 9;
+        base.agility = 1;
+        let special = earth_pony().derive_special(&base);
+        assert_eq!(special.strength, 10);
+        assert_eq!(special.agility, 1);
+    }
+
+    #[test]
+    fn unbalanced_definitions_are_refused() {
+        let mut definition = earth_pony();
+        assert_eq!(definition.validate(), Ok(()));
+        definition.modifiers.luck = 4;
+        assert!(matches!(
+            definition.validate(),
+            Err(RaceDefinitionError::ModifierOutOfRange { stat: "luck", .. })
+        ));
+        definition.modifiers.luck = 0;
+        definition.abilities.push("Buck".to_string());
+        assert_eq!(
+            definition.validate(),
+            Err(RaceDefinitionError::Duplicate("Buck".to_string()))
+        );
+    }
+
+    #[test]
+    fn unknown_stats_are_refused() {
+        let result: Result<RaceDefinition, _> =
+            serde_json::from_str(r#"{ "race": "Unicorn", "modifiers": { "magic": 1 } }"#);
+        assert!(result.is_err());
+    }
+}
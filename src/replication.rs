@@ -0,0 +1,294 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    character::CharacterData,
+    inventory::Inventory,
+    map::AxialCoordinates,
+    messages::{Sequenced, ServerMessage},
+    progression::XpLedger,
+};
+
+/// Deltas the client holds back while waiting for a missing one, before it asks for a new snapshot
+pub const MAX_PENDING_DELTAS: usize = 32;
+
+/// How long the client waits for a missing delta or the snapshot, before it asks for a new snapshot
+///
+/// Turns can be quiet for a long time, the gap may never fill up the [`MAX_PENDING_DELTAS`]
+pub const MAX_GAP_WAIT: Duration = Duration::from_secs(5);
+
+/// Part of the match the server is in
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum MatchPhase {
+    Lobby,
+    LoadingLevel,
+    Placement,
+    Turns,
+    GameOver,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PlayerSnapshot {
+    pub id: u64,
+    pub name: String,
+    /// Server entity of the player
+    pub entity: Entity,
+    /// The player lost the connection and may still return
+    pub absent: bool,
+}
+
+/// A character as the receiving client knows it, inventory and XP are only known for its own
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CharacterSnapshot {
+    pub data: CharacterData,
+    pub inventory: Option<Inventory>,
+    pub ledger: Option<XpLedger>,
+}
+
+/// Everything one client knows about the game, replaces whatever it knew before
+///
+/// Sent on joining, on returning and whenever the client lost track of the [`Sequenced`] deltas
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GameSnapshot {
+    pub phase: MatchPhase,
+    pub level: String,
+    pub players: Vec<PlayerSnapshot>,
+    /// Own characters and the enemies in sight, which also tells what occupies the map
+    pub characters: Vec<CharacterSnapshot>,
+    /// Player whose turn or placement it is
+    pub current_player: Option<u64>,
+    /// Players taking their turns after the current one
+    pub turn_order: Vec<u64>,
+    /// Tiles the client may place its characters on during placement
+    pub spawn_zone: Vec<AxialCoordinates>,
+}
+
+/// Puts the deltas of the server back into order on the client
+///
+/// Deltas arriving before the snapshot or ahead of a missing one are held back, if the gap doesn't close
+/// within [`MAX_GAP_WAIT`] or too many pile up the client drops them and asks for a new snapshot
+#[derive(Debug, Default, Resource)]
+pub struct SequenceTracker {
+    /// Sequence of the next delta to apply, None while waiting for a snapshot
+    expected: Option<u64>,
+    pending: BTreeMap<u64, ServerMessage>,
+    /// When the oldest of the pending deltas arrived
+    gap_since: Option<Instant>,
+    resync: bool,
+}
+
+impl SequenceTracker {
+    /// Returns the messages received at `now` that can be applied, in order
+    pub fn receive(&mut self, sequenced: Sequenced, now: Instant) -> Vec<ServerMessage> {
+        let Sequenced { sequence, message } = sequenced;
+        match self.expected {
+            // already applied, or older than what is applied
+            Some(expected) if sequence < expected => return Vec::new(),
+            _ if matches!(message, ServerMessage::Snapshot(_)) => {
+                self.pending = self.pending.split_off(&(sequence + 1));
+            }
+            Some(expected) if sequence == expected => (),
+            _ => {
+                if self.pending.len() < MAX_PENDING_DELTAS {
+                    self.pending.insert(sequence, message);
+                    self.gap_since.get_or_insert(now);
+                } else {
+                    // the missing delta or snapshot isn't coming
+                    self.give_up();
+                }
+                return Vec::new();
+            }
+        }
+        let mut ready = vec![message];
+        let mut next = sequence + 1;
+        while let Some(message) = self.pending.remove(&next) {
+            ready.push(message);
+            next += 1;
+        }
+        self.expected = Some(next);
+        // whatever is still held back waits for the next gap to close
+        self.gap_since = (!self.pending.is_empty()).then_some(now);
+        ready
+    }
+
+    /// Whether the client should ask for a new snapshot at `now`, only true once per gap
+    pub fn take_resync_request(&mut self, now: Instant) -> bool {
+        if self.gap_since.map_or(false, |since| {
+            now.saturating_duration_since(since) >= MAX_GAP_WAIT
+        }) {
+            self.give_up();
+        }
+        std::mem::take(&mut self.resync)
+    }
+
+    /// Drops everything held back and waits for a new snapshot
+    fn give_up(&mut self) {
+        self.expected = None;
+        self.pending.clear();
+        self.gap_since = None;
+        self.resync = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> ServerMessage {
+        ServerMessage::Snapshot(GameSnapshot {
+            phase: MatchPhase::Lobby,
+            level: String::new(),
+            players: Vec::new(),
+            characters: Vec::new(),
+            current_player: None,
+            turn_order: Vec::new(),
+            spawn_zone: Vec::new(),
+        })
+    }
+
+    fn delta(sequence: u64) -> Sequenced {
+        Sequenced {
+            sequence,
+            message: ServerMessage::PlayerTurn(sequence),
+        }
+    }
+
+    fn turns(messages: &[ServerMessage]) -> Vec<u64> {
+        messages
+            .iter()
+            .filter_map(|message| match message {
+                ServerMessage::PlayerTurn(id) => Some(*id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deltas_wait_for_the_snapshot_and_their_turn() {
+        let now = Instant::now();
+        let mut tracker = SequenceTracker::default();
+        // sent before the snapshot was built, so it's already part of it
+        assert!(tracker.receive(delta(0), now).is_empty());
+        assert!(tracker.receive(delta(3), now).is_empty());
+        let ready = tracker.receive(
+            Sequenced {
+                sequence: 1,
+                message: snapshot(),
+            },
+            now,
+        );
+        assert_eq!(ready.len(), 1);
+        assert_eq!(turns(&tracker.receive(delta(2), now)), vec![2, 3]);
+        assert!(tracker.receive(delta(2), now).is_empty());
+        assert_eq!(turns(&tracker.receive(delta(4), now)), vec![4]);
+        assert!(!tracker.take_resync_request(now));
+    }
+
+    #[test]
+    fn lasting_gaps_ask_for_a_snapshot_once() {
+        let now = Instant::now();
+        let mut tracker = SequenceTracker::default();
+        tracker.receive(
+            Sequenced {
+                sequence: 0,
+                message: snapshot(),
+            },
+            now,
+        );
+        for sequence in 2..(MAX_PENDING_DELTAS as u64 + 3) {
+            assert!(tracker.receive(delta(sequence), now).is_empty());
+        }
+        assert!(tracker.take_resync_request(now));
+        assert!(!tracker.take_resync_request(now));
+        assert!(tracker.receive(delta(1), now).is_empty());
+
+        let sequence = MAX_PENDING_DELTAS as u64 + 10;
+        let ready = tracker.receive(
+            Sequenced {
+                sequence,
+                message: snapshot(),
+            },
+            now,
+        );
+        assert_eq!(ready.len(), 1);
+        assert_eq!(
+            turns(&tracker.receive(delta(sequence + 1), now)),
+            vec![sequence + 1]
+        );
+    }
+
+    #[test]
+    fn stale_snapshots_are_ignored() {
+        let now = Instant::now();
+        let mut tracker = SequenceTracker::default();
+        tracker.receive(
+            Sequenced {
+                sequence: 5,
+                message: snapshot(),
+            },
+            now,
+        );
+        tracker.receive(delta(6), now);
+        assert!(tracker
+            .receive(
+                Sequenced {
+                    sequence: 4,
+                    message: snapshot(),
+                },
+                now,
+            )
+            .is_empty());
+        assert_eq!(turns(&tracker.receive(delta(7), now)), vec![7]);
+    }
+
+    #[test]
+    fn deltas_piling_up_before_the_snapshot_ask_for_one() {
+        let now = Instant::now();
+        let mut tracker = SequenceTracker::default();
+        for sequence in 1..=(MAX_PENDING_DELTAS as u64) {
+            assert!(tracker.receive(delta(sequence), now).is_empty());
+        }
+        assert!(!tracker.take_resync_request(now));
+        tracker.receive(delta(MAX_PENDING_DELTAS as u64 + 1), now);
+        assert!(tracker.take_resync_request(now));
+        assert!(!tracker.take_resync_request(now));
+    }
+
+    #[test]
+    fn gaps_ask_for_a_snapshot_after_a_while() {
+        let now = Instant::now();
+        let mut tracker = SequenceTracker::default();
+        tracker.receive(
+            Sequenced {
+                sequence: 0,
+                message: snapshot(),
+            },
+            now,
+        );
+        // a quiet turn, nothing else arrives after the delta behind the gap
+        tracker.receive(delta(2), now);
+        assert!(!tracker.take_resync_request(now + MAX_GAP_WAIT / 2));
+        assert!(tracker.take_resync_request(now + MAX_GAP_WAIT));
+        assert!(!tracker.take_resync_request(now + MAX_GAP_WAIT * 2));
+        // what was held back is gone, the snapshot replaces it
+        assert!(tracker.receive(delta(1), now + MAX_GAP_WAIT).is_empty());
+
+        // a gap that closes in time doesn't
+        let mut tracker = SequenceTracker::default();
+        tracker.receive(
+            Sequenced {
+                sequence: 0,
+                message: snapshot(),
+            },
+            now,
+        );
+        tracker.receive(delta(2), now);
+        assert_eq!(turns(&tracker.receive(delta(1), now)), vec![1, 2]);
+        assert!(!tracker.take_resync_request(now + MAX_GAP_WAIT));
+    }
+}
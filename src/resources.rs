@@ -18,6 +18,24 @@ impl Players {
     }
 }
 
+/// Maps server character entities to local entities
+#[derive(Resource)]
+pub struct Characters {
+    pub characters: HashMap<Entity, Entity>,
+}
+
+impl Characters {
+    pub fn new() -> Self {
+        Self {
+            characters: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, k: &Entity) -> Option<&Entity> {
+        self.characters.get(k)
+    }
+}
+
 #[derive(Resource)]
 pub struct TurnOrder {
     pub order: VecDeque<u64>,
@@ -0,0 +1,271 @@
+use std::f32::consts::FRAC_PI_6;
+
+use bevy::{prelude::*, reflect::TypeUuid};
+use bevy_common_assets::json::JsonAssetPlugin;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    character::Health,
+    common::Race,
+    map::AxialCoordinates,
+    progression::XpLedger,
+    race::{RaceDefinition, SPELLCASTING},
+};
+
+/// Loads every spell definition from `assets/spells/*.spell.json`
+pub struct SpellPlugin;
+
+impl Plugin for SpellPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(JsonAssetPlugin::<Spell>::new(&["spell.json"]))
+            .add_startup_system(load_spells);
+        info!("SpellPlugin has been loaded");
+    }
+}
+
+/// Keeps the handles of all spell assets, so they stay loaded
+#[derive(Resource)]
+pub struct SpellHandles(pub Vec<HandleUntyped>);
+
+fn load_spells(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let handles = asset_server.load_folder("spells").unwrap_or_else(|error| {
+        warn!("Couldn't load spells: {:?}", error);
+        Vec::new()
+    });
+    commands.insert_resource(SpellHandles(handles));
+}
+
+/// A spell as defined in a `.spell.json` asset, spells are identified by their name
+#[derive(Clone, Debug, Deserialize, TypeUuid)]
+#[uuid = "5b0cdb6e-2a53-4b4f-9d3c-0f1d6a8e7c21"]
+pub struct Spell {
+    pub name: String,
+    pub ap_cost: u32,
+    /// Farthest distance in tiles between the caster and the targeted tile
+    pub range: i32,
+    pub area: SpellArea,
+    pub effects: Vec<SpellEffect>,
+    /// Races that know this spell
+    pub races: Vec<Race>,
+    /// Skill the caster has to learn first, spells without one are known from the start
+    #[serde(default)]
+    pub skill: Option<String>,
+}
+
+impl Spell {
+    /// Only races with the [`SPELLCASTING`] ability have a horn to cast with, `races` can only narrow that down further
+    pub fn can_be_cast_by(&self, definition: &RaceDefinition, ledger: &XpLedger) -> bool {
+        definition.has_ability(SPELLCASTING)
+            && self.races.contains(&definition.race)
+            && self
+                .skill
+                .as_ref()
+                .map_or(true, |skill| ledger.has_learned(skill))
+    }
+}
+
+/// Looks up the spell called `name` among the loaded spells
+pub fn find_spell<'a>(spells: &'a Assets<Spell>, name: &str) -> Option<&'a Spell> {
+    spells
+        .iter()
+        .map(|(_, spell)| spell)
+        .find(|spell| spell.name == name)
+}
+
+/// Tiles a spell affects around the targeted tile
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "shape", rename_all = "snake_case")]
+pub enum SpellArea {
+    /// Only the targeted tile
+    Single,
+    /// All tiles exactly `radius` steps away from the targeted tile
+    Ring { radius: i32 },
+    /// `length` tiles in a straight line from the caster towards the targeted tile
+    Line { length: i32 },
+    /// A 60° wedge up to `length` tiles from the caster, pointing at the targeted tile
+    Cone { length: i32 },
+}
+
+impl SpellArea {
+    /// Returns the tiles affected when a character on `caster` aims at `target`
+    ///
+    /// Elevation of the returned tiles is always 0, look them up in the map to get the real tiles
+    pub fn tiles(
+        &self,
+        caster: AxialCoordinates,
+        target: AxialCoordinates,
+    ) -> Vec<AxialCoordinates> {
+        match *self {
+            SpellArea::Single => vec![AxialCoordinates::new(target.q, target.r, 0)],
+            SpellArea::Ring { radius } => hex_range(target, radius)
+                .filter(|coordinates| coordinates.distance(target) == radius)
+                .collect(),
+            SpellArea::Line { length } => {
+                let steps = caster.distance(target);
+                if steps == 0 {
+                    return Vec::new();
+                }
+                // stretch the line to the full length, no matter how close the target is
+                let scale = length as f32 / steps as f32;
+                let end = AxialCoordinates::round(
+                    caster.q as f32 + (target.q - caster.q) as f32 * scale,
+                    caster.r as f32 + (target.r - caster.r) as f32 * scale,
+                    0,
+                );
+                AxialCoordinates::new(caster.q, caster.r, 0)
+                    .line_to(end)
+                    .into_iter()
+                    .skip(1)
+                    .take(length as usize)
+                    .collect()
+            }
+            SpellArea::Cone { length } => {
+                if caster.distance(target) == 0 {
+                    return Vec::new();
+                }
+                let aim = planar_offset(caster, target);
+                hex_range(caster, length)
+                    .filter(|&coordinates| {
+                        coordinates.distance(caster) > 0
+                            && aim.angle_between(planar_offset(caster, coordinates)).abs()
+                                <= FRAC_PI_6 + 1e-3
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// All hexes at most `range` steps away from `centre`
+fn hex_range(centre: AxialCoordinates, range: i32) -> impl Iterator<Item = AxialCoordinates> {
+    (-range..=range).flat_map(move |q| {
+        ((-range).max(-q - range)..=range.min(-q + range))
+            .map(move |r| AxialCoordinates::new(centre.q + q, centre.r + r, 0))
+    })
+}
+
+/// Offset between two hexes on an undistorted plane, used to compare directions
+fn planar_offset(from: AxialCoordinates, to: AxialCoordinates) -> Vec2 {
+    let (q, r) = ((to.q - from.q) as f32, (to.r - from.r) as f32);
+    Vec2::new(3f32.sqrt() * (q + r / 2.0), 1.5 * r)
+}
+
+/// What a spell does to every character in its area
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SpellEffect {
+    Damage { amount: u32 },
+    Heal { amount: u32 },
+}
+
+impl SpellEffect {
+    pub fn apply(&self, health: &mut Health) {
+        match *self {
+            SpellEffect::Damage { amount } => health.take_damage(amount),
+            SpellEffect::Heal { amount } => health.heal(amount),
+        }
+    }
+}
+
+/// Everything clients need to know about a cast spell
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SpellResult {
+    /// Server entity of the casting character
+    pub caster: Entity,
+    pub spell: String,
+    pub target: AxialCoordinates,
+    /// Server entities of the characters in the area and their health after the spell
+    pub affected: Vec<(Entity, Health)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origin() -> AxialCoordinates {
+        AxialCoordinates::new(0, 0, 0)
+    }
+
+    #[test]
+    fn ring_surrounds_the_target() {
+        let target = AxialCoordinates::new(3, 0, 0);
+        let ring = SpellArea::Ring { radius: 1 }.tiles(origin(), target);
+        assert_eq!(ring.len(), 6);
+        assert!(ring.iter().all(|c| c.distance(target) == 1));
+        assert_eq!(
+            SpellArea::Ring { radius: 0 }.tiles(origin(), target),
+            vec![target]
+        );
+    }
+
+    #[test]
+    fn line_extends_past_a_close_target() {
+        let line = SpellArea::Line { length: 4 }.tiles(origin(), AxialCoordinates::RIGHT);
+        assert_eq!(
+            line,
+            (1..=4)
+                .map(|q| AxialCoordinates::new(q, 0, 0))
+                .collect::<Vec<_>>()
+        );
+        assert!(SpellArea::Line { length: 4 }
+            .tiles(origin(), origin())
+            .is_empty());
+    }
+
+    #[test]
+    fn cone_widens_with_distance() {
+        let cone = SpellArea::Cone { length: 2 }.tiles(origin(), AxialCoordinates::new(5, 0, 0));
+        assert_eq!(cone.len(), 4);
+        assert!(cone.contains(&AxialCoordinates::new(1, 0, 0)));
+        assert!(cone.contains(&AxialCoordinates::new(2, 0, 0)));
+        assert!(cone.contains(&AxialCoordinates::new(2, -1, 0)));
+        assert!(cone.contains(&AxialCoordinates::new(1, 1, 0)));
+    }
+
+    fn race(race: Race, abilities: &[&str]) -> RaceDefinition {
+        RaceDefinition {
+            race,
+            modifiers: Default::default(),
+            skill_trees: Vec::new(),
+            abilities: abilities.iter().map(|ability| ability.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn only_races_with_spellcasting_cast() {
+        let spell = Spell {
+            name: "Test".to_string(),
+            ap_cost: 1,
+            range: 1,
+            area: SpellArea::Single,
+            effects: vec![SpellEffect::Heal { amount: 1 }],
+            races: vec![Race::Unicorn, Race::Pegasus],
+            skill: None,
+        };
+        let ledger = XpLedger::default();
+        assert!(spell.can_be_cast_by(&race(Race::Unicorn, &[SPELLCASTING]), &ledger));
+        assert!(!spell.can_be_cast_by(&race(Race::Unicorn, &[]), &ledger));
+        assert!(!spell.can_be_cast_by(&race(Race::Pegasus, &["Flight"]), &ledger));
+        assert!(spell.can_be_cast_by(&race(Race::Pegasus, &[SPELLCASTING]), &ledger));
+        // the spell still has to list the race
+        assert!(!spell.can_be_cast_by(&race(Race::EarthPony, &[SPELLCASTING]), &ledger));
+    }
+
+    #[test]
+    fn some_spells_have_to_be_learned() {
+        let spell = Spell {
+            name: "Test".to_string(),
+            ap_cost: 1,
+            range: 1,
+            area: SpellArea::Single,
+            effects: vec![SpellEffect::Heal { amount: 1 }],
+            races: vec![Race::Unicorn],
+            skill: Some("Battle Magic".to_string()),
+        };
+        let unicorn = race(Race::Unicorn, &[SPELLCASTING]);
+        let mut ledger = XpLedger::default();
+        assert!(!spell.can_be_cast_by(&unicorn, &ledger));
+        ledger.learned.push("Battle Magic".to_string());
+        assert!(spell.can_be_cast_by(&unicorn, &ledger));
+    }
+}
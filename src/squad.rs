@@ -0,0 +1,270 @@
+use std::collections::HashSet;
+
+use bevy::{prelude::*, reflect::TypeUuid};
+use bevy_common_assets::json::JsonAssetPlugin;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    character::CHARACTERS_PER_PLAYER,
+    common::{Race, Special},
+    inventory::{carry_capacity, find_item, Item, STARTING_ITEMS},
+    race::{find_race_definition, RaceDefinition},
+};
+
+/// Longest name a character may have
+pub const MAX_NAME_LENGTH: usize = 24;
+
+/// Loads the squad building rules from `assets/squad.rules.json`
+pub struct SquadPlugin;
+
+impl Plugin for SquadPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(JsonAssetPlugin::<SquadRules>::new(&["rules.json"]))
+            .add_startup_system(load_squad_rules);
+        info!("SquadPlugin has been loaded");
+    }
+}
+
+#[derive(Resource)]
+pub struct SquadRulesHandle(pub Handle<SquadRules>);
+
+impl SquadRulesHandle {
+    /// The loaded squad rules, the defaults until they are loaded
+    pub fn rules(&self, rules: &Assets<SquadRules>) -> SquadRules {
+        rules.get(&self.0).cloned().unwrap_or_default()
+    }
+}
+
+fn load_squad_rules(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(SquadRulesHandle(asset_server.load("squad.rules.json")));
+}
+
+/// What players may choose for their squad before a match
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, TypeUuid)]
+#[uuid = "d84b6f3e-1c5a-4e97-8b20-5f3a9c7e6d14"]
+pub struct SquadRules {
+    /// Lowest and highest value of every single base SPECIAL stat
+    pub min_stat: u8,
+    pub max_stat: u8,
+    /// Points every character can spread over its base SPECIAL
+    pub special_points: u32,
+    /// Items a character may start with, items may be picked more than once
+    pub loadout: Vec<String>,
+    pub max_loadout_items: usize,
+}
+
+impl Default for SquadRules {
+    fn default() -> Self {
+        Self {
+            min_stat: 1,
+            max_stat: 9,
+            special_points: 40,
+            loadout: STARTING_ITEMS.iter().map(|item| item.to_string()).collect(),
+            max_loadout_items: 4,
+        }
+    }
+}
+
+/// A character as chosen in the squad builder, the SPECIAL is the base before racial modifiers
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CharacterSheet {
+    pub name: String,
+    pub race: Race,
+    pub special: Special,
+    pub loadout: Vec<String>,
+}
+
+#[derive(Clone, Component, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Squad {
+    pub characters: Vec<CharacterSheet>,
+}
+
+impl Default for Squad {
+    /// Two Earth ponies, a Pegasus and a Unicorn with average stats and the starting items
+    fn default() -> Self {
+        let races = [Race::EarthPony, Race::Pegasus, Race::Unicorn, Race::EarthPony];
+        Self {
+            characters: races
+                .iter()
+                .enumerate()
+                .map(|(index, &race)| CharacterSheet {
+                    name: format!("Pony {}", index + 1),
+                    race,
+                    special: Special::new(),
+                    loadout: STARTING_ITEMS.iter().map(|item| item.to_string()).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Reason why a squad was refused, characters are referred to by their index in the squad
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SquadError {
+    /// The player tried to ready up without an accepted squad
+    NoSquad,
+    WrongSize { expected: usize, actual: usize },
+    InvalidName(usize),
+    StatOutOfBounds(usize),
+    TooManyPoints { character: usize, spent: u32 },
+    ItemNotAllowed(usize, String),
+    TooManyItems(usize),
+    /// The loadout weighs more than the character can carry with its racial modifiers applied
+    TooHeavy(usize),
+}
+
+impl SquadRules {
+    /// Checks the squad against the rules, both the client before submitting and the server on receiving
+    pub fn validate(
+        &self,
+        squad: &Squad,
+        items: &Assets<Item>,
+        race_definitions: &Assets<RaceDefinition>,
+    ) -> Result<(), SquadError> {
+        if squad.characters.len() != CHARACTERS_PER_PLAYER {
+            return Err(SquadError::WrongSize {
+                expected: CHARACTERS_PER_PLAYER,
+                actual: squad.characters.len(),
+            });
+        }
+        let mut names = HashSet::new();
+        for (index, sheet) in squad.characters.iter().enumerate() {
+            let name = sheet.name.trim();
+            if name.is_empty() || name.len() > MAX_NAME_LENGTH || !names.insert(name) {
+                return Err(SquadError::InvalidName(index));
+            }
+            if sheet
+                .special
+                .stats()
+                .iter()
+                .any(|stat| !(self.min_stat..=self.max_stat).contains(stat))
+            {
+                return Err(SquadError::StatOutOfBounds(index));
+            }
+            if sheet.special.points() > self.special_points {
+                return Err(SquadError::TooManyPoints {
+                    character: index,
+                    spent: sheet.special.points(),
+                });
+            }
+            if sheet.loadout.len() > self.max_loadout_items {
+                return Err(SquadError::TooManyItems(index));
+            }
+            let mut weight = 0;
+            for name in &sheet.loadout {
+                match find_item(items, name).filter(|_| self.loadout.contains(name)) {
+                    Some(item) => weight += item.weight,
+                    None => return Err(SquadError::ItemNotAllowed(index, name.clone())),
+                }
+            }
+            let special = find_race_definition(race_definitions, sheet.race)
+                .map_or(sheet.special, |race| race.derive_special(&sheet.special));
+            if weight > carry_capacity(&special) {
+                return Err(SquadError::TooHeavy(index));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::ItemKind;
+
+    fn assets() -> (Assets<Item>, Assets<RaceDefinition>) {
+        let mut app = App::new();
+        app.add_plugin(CorePlugin::default())
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<Item>()
+            .add_asset::<RaceDefinition>();
+        let mut items = app.world.remove_resource::<Assets<Item>>().unwrap();
+        for (name, weight, kind) in [
+            ("Healing Potion", 1, ItemKind::Consumable { heal: 8 }),
+            ("Leather Barding", 15, ItemKind::Armour { protection: 1 }),
+            ("Anvil", 200, ItemKind::Armour { protection: 9 }),
+        ] {
+            items.add(Item {
+                name: name.to_string(),
+                weight,
+                kind,
+            });
+        }
+        let race_definitions = app
+            .world
+            .remove_resource::<Assets<RaceDefinition>>()
+            .unwrap();
+        (items, race_definitions)
+    }
+
+    #[test]
+    fn default_squad_follows_default_rules() {
+        let (items, races) = assets();
+        assert_eq!(
+            SquadRules::default().validate(&Squad::default(), &items, &races),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn stats_stay_within_bounds_and_points() {
+        let (items, races) = assets();
+        let rules = SquadRules::default();
+        let mut squad = Squad::default();
+        squad.characters[1].special.luck = 10;
+        assert_eq!(
+            rules.validate(&squad, &items, &races),
+            Err(SquadError::StatOutOfBounds(1))
+        );
+        squad.characters[1].special = Special::from_stats([9, 9, 5, 5, 5, 5, 5]);
+        assert_eq!(
+            rules.validate(&squad, &items, &races),
+            Err(SquadError::TooManyPoints {
+                character: 1,
+                spent: 43
+            })
+        );
+    }
+
+    #[test]
+    fn loadouts_are_checked() {
+        let (items, races) = assets();
+        let mut rules = SquadRules::default();
+        let mut squad = Squad::default();
+        squad.characters[2].loadout.push("Anvil".to_string());
+        assert_eq!(
+            rules.validate(&squad, &items, &races),
+            Err(SquadError::ItemNotAllowed(2, "Anvil".to_string()))
+        );
+        rules.loadout.push("Anvil".to_string());
+        assert_eq!(
+            rules.validate(&squad, &items, &races),
+            Err(SquadError::TooHeavy(2))
+        );
+        squad.characters[2].loadout = vec!["Healing Potion".to_string(); 5];
+        assert_eq!(
+            rules.validate(&squad, &items, &races),
+            Err(SquadError::TooManyItems(2))
+        );
+    }
+
+    #[test]
+    fn squads_need_four_named_characters() {
+        let (items, races) = assets();
+        let rules = SquadRules::default();
+        let mut squad = Squad::default();
+        squad.characters[3].name = squad.characters[0].name.clone();
+        assert_eq!(
+            rules.validate(&squad, &items, &races),
+            Err(SquadError::InvalidName(3))
+        );
+        squad.characters.pop();
+        assert_eq!(
+            rules.validate(&squad, &items, &races),
+            Err(SquadError::WrongSize {
+                expected: 4,
+                actual: 3
+            })
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// How a single player fared in a match
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PlayerStats {
+    pub player: u64,
+    pub name: String,
+    pub damage_dealt: u32,
+    pub damage_taken: u32,
+    /// Enemy characters this player finished off
+    pub kills: u32,
+    pub characters_lost: u32,
+}
+
+/// Stats of every player taking part in the running match
+#[derive(Clone, Debug, Default, Resource)]
+pub struct MatchStats {
+    pub players: HashMap<u64, PlayerStats>,
+}
+
+impl MatchStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_player(&mut self, player: u64, name: &str) {
+        self.players.insert(
+            player,
+            PlayerStats {
+                player,
+                name: name.to_string(),
+                ..default()
+            },
+        );
+    }
+
+    /// Books an attack of a character owned by `attacker` on one owned by `defender`
+    pub fn record_attack(&mut self, attacker: u64, defender: u64, damage: u32, lethal: bool) {
+        if let Some(stats) = self.players.get_mut(&attacker) {
+            stats.damage_dealt += damage;
+            if lethal {
+                stats.kills += 1;
+            }
+        }
+        if let Some(stats) = self.players.get_mut(&defender) {
+            stats.damage_taken += damage;
+            if lethal {
+                stats.characters_lost += 1;
+            }
+        }
+    }
+
+    /// Returns the stats of all players, ordered by their id so every client lists them the same way
+    pub fn to_vec(&self) -> Vec<PlayerStats> {
+        let mut stats: Vec<PlayerStats> = self.players.values().cloned().collect();
+        stats.sort_by_key(|stats| stats.player);
+        stats
+    }
+}
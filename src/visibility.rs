@@ -0,0 +1,104 @@
+use crate::{
+    common::Special,
+    line_of_sight::LineOfSight,
+    map::{AxialCoordinates, Map},
+};
+
+/// Base distance in tiles every character can see, Perception adds one tile per point
+const BASE_SIGHT_RANGE: i32 = 3;
+
+/// Number of tiles a character with this [`Special`] can see
+pub fn sight_range(special: &Special) -> i32 {
+    BASE_SIGHT_RANGE + special.perception as i32
+}
+
+impl Map {
+    /// Checks whether a character on `from` seeing `range` tiles far can see the tile `to`
+    pub fn can_see(&self, from: AxialCoordinates, to: AxialCoordinates, range: i32) -> bool {
+        from.distance(to) <= range && self.line_of_sight(from, to) != LineOfSight::Blocked
+    }
+
+    /// Returns all tiles of the map a character on `from` seeing `range` tiles far can see
+    pub fn visible_tiles(&self, from: AxialCoordinates, range: i32) -> Vec<AxialCoordinates> {
+        let mut visible = Vec::new();
+        for q in -range..=range {
+            for r in (-range).max(-q - range)..=range.min(-q + range) {
+                let coordinates = AxialCoordinates::new(from.q + q, from.r + r, 0);
+                if let Some(tile) = self.get(coordinates) {
+                    if self.can_see(from, tile.coordinates, range) {
+                        visible.push(tile.coordinates);
+                    }
+                }
+            }
+        }
+        visible
+    }
+
+    /// Returns the start of a path up to where characters on `eyes`, each with its sight range, lose sight of it
+    ///
+    /// The first tile is always kept, whoever gets told about a move already knows where it started.
+    /// Tiles seen again after a hidden stretch are left out, the move would jump across the gap
+    pub fn visible_path(
+        &self,
+        tiles: &[AxialCoordinates],
+        eyes: &[(AxialCoordinates, i32)],
+    ) -> Vec<AxialCoordinates> {
+        let seen = tiles.iter().skip(1).take_while(|tile| {
+            eyes.iter()
+                .any(|&(eye, range)| self.can_see(eye, **tile, range))
+        });
+        tiles.iter().take(1).chain(seen).copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{Tile, TileType};
+
+    #[test]
+    fn sees_everything_in_range_on_flat_ground() {
+        let map = Map::generate(10, 10);
+        let from = AxialCoordinates::new(0, 0, 0);
+        let visible = map.visible_tiles(from, 2);
+        assert_eq!(visible.len(), 19);
+        assert!(visible.iter().all(|c| c.distance(from) <= 2));
+    }
+
+    #[test]
+    fn obstacles_hide_what_is_behind_them() {
+        let mut map = Map::generate(10, 10);
+        let from = AxialCoordinates::new(0, 0, 0);
+        map.insert(Tile::new(
+            AxialCoordinates::new(1, 0, 0),
+            TileType::Impassable,
+        ));
+        assert!(!map.can_see(from, AxialCoordinates::new(3, 0, 0), 5));
+        assert!(map.can_see(from, AxialCoordinates::new(0, 3, 0), 5));
+        assert!(!map.can_see(from, AxialCoordinates::new(0, 6, 0), 5));
+    }
+
+    #[test]
+    fn paths_are_cut_to_what_can_be_seen() {
+        let mut map = Map::generate(10, 10);
+        let path: Vec<AxialCoordinates> = (0..7).map(|r| AxialCoordinates::new(0, r, 0)).collect();
+        let eye = AxialCoordinates::new(1, 0, 0);
+        assert_eq!(map.visible_path(&path, &[(eye, 3)]), path[..4].to_vec());
+        // a second character further down sees the rest
+        let eyes = [(eye, 3), (AxialCoordinates::new(1, 5, 0), 3)];
+        assert_eq!(map.visible_path(&path, &eyes), path);
+        // but not if there is a stretch neither of them sees in between
+        let eyes = [(eye, 1), (AxialCoordinates::new(1, 5, 0), 1)];
+        assert_eq!(map.visible_path(&path, &eyes), path[..2].to_vec());
+        map.insert(Tile::new(
+            AxialCoordinates::new(0, 1, 0),
+            TileType::Impassable,
+        ));
+        // the obstacle itself is seen, but nothing behind it
+        let behind = AxialCoordinates::new(0, -1, 0);
+        assert_eq!(map.visible_path(&path, &[(behind, 10)]), path[..2].to_vec());
+        // the start is known anyway, even when out of sight
+        let far = AxialCoordinates::new(0, 9, 0);
+        assert_eq!(map.visible_path(&path, &[(far, 1)]), path[..1].to_vec());
+    }
+}